@@ -0,0 +1,17 @@
+-- ログインロックアウトテーブルの作成
+-- scope は 'account'（ユーザー名単位）または 'ip'（送信元IP単位）
+CREATE TABLE IF NOT EXISTS login_lockouts (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    scope VARCHAR(20) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    failure_count INT NOT NULL,
+    locked_until TIMESTAMP NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    cleared_at TIMESTAMP NULL DEFAULT NULL,
+    cleared_by VARCHAR(255),
+    INDEX idx_scope_subject (scope, subject),
+    INDEX idx_locked_until (locked_until)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+-- IPアドレス単位の失敗回数集計用インデックス
+CREATE INDEX idx_ip_address ON audit_logs(ip_address);
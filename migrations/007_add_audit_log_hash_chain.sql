@@ -0,0 +1,17 @@
+-- 監査ログの改ざん検知用ハッシュチェーン
+-- entry_hash = SHA-256(prev_hash と各列の内容)。既存の行は NULL のまま（チェーン対象外）
+ALTER TABLE audit_logs
+ADD COLUMN prev_hash CHAR(64) NULL DEFAULT NULL AFTER created_at,
+ADD COLUMN entry_hash CHAR(64) NULL DEFAULT NULL AFTER prev_hash;
+
+-- チェーンの末尾（最後に書き込んだ行）を保持する1行だけのテーブル
+-- 書き込み時にこの行をロックして、同時書き込みでもチェーンが分岐しないようにする
+CREATE TABLE IF NOT EXISTS audit_chain_state (
+    id TINYINT PRIMARY KEY,
+    last_id INT NOT NULL DEFAULT 0,
+    last_hash CHAR(64) NOT NULL,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+INSERT IGNORE INTO audit_chain_state (id, last_id, last_hash)
+VALUES (1, 0, '0000000000000000000000000000000000000000000000000000000000000000');
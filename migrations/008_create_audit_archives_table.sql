@@ -0,0 +1,16 @@
+-- 保存期間を過ぎて audit_logs から削除した監査ログのアーカイブ記録
+-- 実データは file_name のファイル（gzip圧縮した JSON Lines）に保存する
+CREATE TABLE IF NOT EXISTS audit_archives (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    file_name VARCHAR(255) NOT NULL,
+    first_id INT NOT NULL,
+    last_id INT NOT NULL,
+    row_count INT NOT NULL,
+    oldest_at TIMESTAMP NULL DEFAULT NULL,
+    newest_at TIMESTAMP NULL DEFAULT NULL,
+    -- 最後にアーカイブした行の entry_hash（残りのログのハッシュチェーンの起点になる）
+    last_entry_hash CHAR(64) NULL DEFAULT NULL,
+    file_sha256 CHAR(64) NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    INDEX idx_last_id (last_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -0,0 +1,36 @@
+-- ロールと権限の対応をデータベースで管理する
+CREATE TABLE IF NOT EXISTS roles (
+    name VARCHAR(50) PRIMARY KEY,
+    description VARCHAR(255) NOT NULL DEFAULT '',
+    is_builtin BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+CREATE TABLE IF NOT EXISTS role_permissions (
+    role VARCHAR(50) NOT NULL,
+    permission VARCHAR(50) NOT NULL,
+    PRIMARY KEY (role, permission),
+    FOREIGN KEY (role) REFERENCES roles(name) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+-- これまでコードで固定していたロールと権限
+INSERT IGNORE INTO roles (name, description, is_builtin) VALUES
+    ('admin', 'すべての機能にアクセス可能', TRUE),
+    ('user', '一般機能にアクセス可能', TRUE),
+    ('viewer', '読み取り専用', TRUE);
+
+INSERT IGNORE INTO role_permissions (role, permission) VALUES
+    ('admin', 'sysinfo.view'),
+    ('admin', 'crypto.use'),
+    ('admin', 'users.manage'),
+    ('admin', 'audit.view'),
+    ('user', 'sysinfo.view'),
+    ('user', 'crypto.use');
+
+-- 未知のロールは以前と同じく user として扱う
+UPDATE users SET role = LOWER(role);
+UPDATE users SET role = 'user' WHERE role NOT IN (SELECT name FROM roles);
+
+ALTER TABLE users
+MODIFY COLUMN role VARCHAR(50) NOT NULL DEFAULT 'user',
+ADD CONSTRAINT fk_users_role FOREIGN KEY (role) REFERENCES roles(name);
@@ -0,0 +1,4 @@
+-- 無効化されたユーザー（NULL 以外）はログインできず、既存のセッションも次のリクエストで破棄される
+ALTER TABLE users
+ADD COLUMN disabled_at TIMESTAMP NULL DEFAULT NULL
+AFTER role;
@@ -0,0 +1,4 @@
+-- TRUE のユーザーは次のリクエストからパスワードを変更するまで他の画面に進めない
+ALTER TABLE users
+ADD COLUMN must_change_password BOOLEAN NOT NULL DEFAULT FALSE
+AFTER password_hash;
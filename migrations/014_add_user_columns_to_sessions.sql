@@ -0,0 +1,9 @@
+-- ログイン中のセッションを一覧・失効できるように、ユーザーと接続元の情報を持たせる
+-- user_id はログインが完了したセッションのみ設定される（ログイン前・二要素認証の入力中は NULL）
+ALTER TABLE sessions
+ADD COLUMN user_id INT NULL DEFAULT NULL AFTER id,
+ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP AFTER expiry_date,
+ADD COLUMN last_activity_at TIMESTAMP NULL DEFAULT NULL AFTER created_at,
+ADD COLUMN ip_address VARCHAR(45) NULL DEFAULT NULL AFTER last_activity_at,
+ADD COLUMN user_agent TEXT NULL AFTER ip_address,
+ADD INDEX idx_sessions_user_id (user_id);
@@ -0,0 +1,13 @@
+-- ユーザーごとの公開鍵暗号の鍵（key_type ごとに1つ）
+-- public_key は公開鍵の文字列表現（X25519 は age の "age1..." 形式）
+-- encrypted_private_key は秘密鍵をユーザーのパスフレーズで暗号化したもの（Base64）。
+-- 公開鍵だけを登録した場合（秘密鍵を手元で管理する場合）は NULL
+CREATE TABLE IF NOT EXISTS keyring (
+    user_id INT NOT NULL,
+    key_type VARCHAR(20) NOT NULL,
+    public_key VARCHAR(255) NOT NULL,
+    encrypted_private_key TEXT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, key_type),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
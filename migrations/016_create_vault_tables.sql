@@ -0,0 +1,53 @@
+-- シークレット保管庫
+-- 値はサーバーのマスターキー（VAULT_MASTER_KEY）で AES-256-GCM により暗号化して保存する
+CREATE TABLE IF NOT EXISTS vault_secrets (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    name VARCHAR(100) NOT NULL UNIQUE,
+    description VARCHAR(255) NOT NULL DEFAULT '',
+    -- 作成したユーザー（共有先の変更と削除ができる）
+    owner_id INT NULL,
+    current_version INT NOT NULL DEFAULT 1,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (owner_id) REFERENCES users(id) ON DELETE SET NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+-- 値は更新のたびに新しいバージョンとして追加し、以前のバージョンも残す
+-- key_id は暗号化に使ったマスターキーを見分けるための値、nonce は AES-GCM のナンス
+CREATE TABLE IF NOT EXISTS vault_secret_versions (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    secret_id INT NOT NULL,
+    version INT NOT NULL,
+    key_id CHAR(16) NOT NULL,
+    nonce VARBINARY(12) NOT NULL,
+    ciphertext MEDIUMBLOB NOT NULL,
+    created_by INT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE KEY uq_secret_version (secret_id, version),
+    FOREIGN KEY (secret_id) REFERENCES vault_secrets(id) ON DELETE CASCADE,
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+-- 共有先（ユーザーとロールで別々のテーブルにする）
+-- 共有されたユーザーは値の表示と更新ができる
+CREATE TABLE IF NOT EXISTS vault_secret_user_grants (
+    secret_id INT NOT NULL,
+    user_id INT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (secret_id, user_id),
+    FOREIGN KEY (secret_id) REFERENCES vault_secrets(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+CREATE TABLE IF NOT EXISTS vault_secret_role_grants (
+    secret_id INT NOT NULL,
+    role VARCHAR(50) NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (secret_id, role),
+    FOREIGN KEY (secret_id) REFERENCES vault_secrets(id) ON DELETE CASCADE,
+    FOREIGN KEY (role) REFERENCES roles(name) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+INSERT IGNORE INTO role_permissions (role, permission) VALUES
+    ('admin', 'vault.use'),
+    ('user', 'vault.use');
@@ -0,0 +1,106 @@
+//! 保存期間を過ぎた監査ログのアーカイブ
+//!
+//! 古い順に連続した行を gzip 圧縮した JSON Lines ファイルに書き出し、
+//! `audit_archives` に記録してから `audit_logs` から削除する。
+//! ハッシュチェーンの途中を削らないよう、保存期間内の行が現れた時点で止める。
+
+use crate::config::AuditRetentionConfig;
+use crate::db;
+use chrono::Utc;
+use flate2::{Compression, write::GzEncoder};
+use sha2::{Digest, Sha256};
+use sqlx::MySqlPool;
+use std::io::Write;
+
+// 1ファイルに書き出す最大件数
+const ARCHIVE_BATCH_SIZE: i64 = 10000;
+
+/// アーカイブ処理の結果
+#[derive(Debug, Default)]
+pub struct ArchiveSummary {
+    pub files: Vec<String>,
+    pub rows: u64,
+    pub first_id: Option<i32>,
+    pub last_id: Option<i32>,
+}
+
+/// 保存期間を過ぎた監査ログをアーカイブする
+///
+/// 1件以上アーカイブした場合は、その内容を監査ログに記録する。
+pub async fn run(
+    pool: &MySqlPool,
+    events: &db::AuditLogSender,
+    config: &AuditRetentionConfig,
+) -> anyhow::Result<ArchiveSummary> {
+    let mut summary = ArchiveSummary::default();
+    let Some(retention) = config.retention else {
+        return Ok(summary);
+    };
+    let cutoff = Utc::now() - retention;
+
+    tokio::fs::create_dir_all(&config.archive_dir).await?;
+
+    loop {
+        let mut logs = db::list_audit_logs_after(pool, 0, ARCHIVE_BATCH_SIZE).await?;
+        let fetched = logs.len();
+
+        let expired = logs.iter().take_while(|log| log.created_at < cutoff).count();
+        logs.truncate(expired);
+        let (Some(first), Some(last)) = (logs.first(), logs.last()) else {
+            break;
+        };
+
+        let file_name = format!("audit_logs_{:010}-{:010}.ndjson.gz", first.id, last.id);
+        let data = encode(&logs)?;
+        let file_sha256 = format!("{:x}", Sha256::digest(&data));
+
+        // 書き込み途中のファイルを残さないよう、一時ファイルから置き換える
+        let path = config.archive_dir.join(&file_name);
+        let tmp_path = config.archive_dir.join(format!("{}.tmp", file_name));
+        tokio::fs::write(&tmp_path, &data).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+
+        db::archive_audit_logs(pool, &file_name, &file_sha256, &logs).await?;
+
+        summary.first_id.get_or_insert(first.id);
+        summary.last_id = Some(last.id);
+        summary.rows += logs.len() as u64;
+        summary.files.push(file_name);
+
+        // 保存期間内の行に達したか、残りがなくなったら終了
+        if expired < fetched || (fetched as i64) < ARCHIVE_BATCH_SIZE {
+            break;
+        }
+    }
+
+    if let (Some(first_id), Some(last_id)) = (summary.first_id, summary.last_id) {
+        let _ = db::create_audit_log(
+            pool,
+            events,
+            &db::ClientInfo::default(),
+            None,
+            "system",
+            "audit_archive",
+            Some(&config.archive_dir.display().to_string()),
+            Some(&format!(
+                "Archived {} audit log entries (id {}-{}, older than {}) to {}",
+                summary.rows,
+                first_id,
+                last_id,
+                cutoff.to_rfc3339(),
+                summary.files.join(", "),
+            )),
+        ).await;
+    }
+
+    Ok(summary)
+}
+
+fn encode(logs: &[db::AuditLog]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    for log in logs {
+        serde_json::to_writer(&mut encoder, log)?;
+        encoder.write_all(b"\n")?;
+    }
+    encoder.finish()
+}
@@ -1,5 +1,3 @@
-use argon2::Argon2;
-use password_hash::{PasswordHasher, SaltString, rand_core::OsRng};
 use std::io::{self, Write};
 
 #[tokio::main]
@@ -28,19 +26,45 @@ async fn main() {
     io::stdin().read_line(&mut password).unwrap();
     let password = password.trim();
 
-    // パスワードのハッシュ化
-    let salt = SaltString::generate(&mut OsRng);
-    let hash = Argon2::default()
-        .hash_password(password.as_bytes(), &salt)
-        .unwrap()
-        .to_string();
+    // パスワードポリシーの確認（Webのユーザー管理と同じ設定を使う）
+    let policy = rust_dashboard::config::PasswordPolicy::from_env();
+    if let Err(error) = policy.validate(password, username) {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+
+    // ロールの入力（空の場合は user）
+    let roles: Vec<String> = rust_dashboard::db::list_roles(&pool)
+        .await
+        .expect("Failed to load roles")
+        .into_iter()
+        .map(|role| role.name)
+        .collect();
+    print!("Role ({}) [{}]: ", roles.join("/"), rust_dashboard::db::ROLE_USER);
+    io::stdout().flush().unwrap();
+    let mut role = String::new();
+    io::stdin().read_line(&mut role).unwrap();
+    let role = match role.trim().to_lowercase().as_str() {
+        "" => rust_dashboard::db::ROLE_USER.to_string(),
+        input if roles.iter().any(|role| role == input) => input.to_string(),
+        input => {
+            eprintln!("Unknown role: {}", input);
+            std::process::exit(1);
+        }
+    };
+
+    // パスワードのハッシュ化（ARGON2_* のパラメータを使う）
+    let hash = rust_dashboard::config::PasswordHashing::from_env()
+        .hash(password)
+        .unwrap();
 
     // ユーザーの作成
-    match rust_dashboard::db::create_user(&pool, username, &hash).await {
+    match rust_dashboard::db::create_user(&pool, username, &hash, &role).await {
         Ok(user) => {
             println!("User created successfully:");
             println!("  ID: {}", user.id);
             println!("  Username: {}", user.username);
+            println!("  Role: {}", user.role());
         }
         Err(e) => {
             eprintln!("Error creating user: {}", e);
@@ -1,8 +1,8 @@
-use argon2::Argon2;
-use password_hash::{PasswordHasher, SaltString, rand_core::OsRng};
 use std::io::{self, Write};
 
 fn main() {
+    dotenvy::dotenv().ok();
+
     print!("Password: ");
     io::stdout().flush().unwrap();
 
@@ -10,11 +10,10 @@ fn main() {
     io::stdin().read_line(&mut password).unwrap();
     let password = password.trim();
 
-    let salt = SaltString::generate(&mut OsRng);
-    let hash = Argon2::default()
-        .hash_password(password.as_bytes(), &salt)
-        .unwrap()
-        .to_string();
+    // ARGON2_* のパラメータでハッシュ化する
+    let hash = rust_dashboard::config::PasswordHashing::from_env()
+        .hash(password)
+        .unwrap();
 
     println!("Hashed password: {}", hash);
 }
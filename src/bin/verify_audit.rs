@@ -0,0 +1,32 @@
+use std::process::ExitCode;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    dotenvy::dotenv().ok();
+
+    // データベース接続
+    let database_url = std::env::var("DATABASE_URL")
+        .expect("DATABASE_URL must be set in .env file");
+
+    let pool = rust_dashboard::db::create_pool(&database_url)
+        .await
+        .expect("Failed to connect to database");
+
+    let report = rust_dashboard::db::verify_audit_chain(&pool)
+        .await
+        .expect("Failed to read audit logs");
+
+    println!("Checked entries: {}", report.checked);
+    println!("Legacy entries (no hash): {}", report.legacy);
+
+    match report.broken {
+        Some(broken) => {
+            println!("Chain broken at entry {}: {}", broken.id, broken.reason);
+            ExitCode::FAILURE
+        }
+        None => {
+            println!("Audit log chain is intact");
+            ExitCode::SUCCESS
+        }
+    }
+}
@@ -0,0 +1,527 @@
+use chrono::Duration;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+// 環境変数を読み取り、未設定または不正な値の場合は既定値を使う
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+/// ログイン試行のスロットリング設定
+///
+/// `window` の間に失敗が上限に達すると、アカウントまたは送信元IPを
+/// `lockout_duration` の間ロックする。上限を 0 にするとその単位のロックは無効。
+#[derive(Debug, Clone)]
+pub struct LockoutConfig {
+    pub max_failures_per_account: i64,
+    pub max_failures_per_ip: i64,
+    pub window: Duration,
+    pub lockout_duration: Duration,
+}
+
+impl LockoutConfig {
+    pub fn from_env() -> Self {
+        Self {
+            max_failures_per_account: env_or("LOGIN_MAX_FAILURES_PER_ACCOUNT", 5),
+            max_failures_per_ip: env_or("LOGIN_MAX_FAILURES_PER_IP", 20),
+            window: Duration::seconds(env_or("LOGIN_FAILURE_WINDOW_SECS", 900)),
+            lockout_duration: Duration::seconds(env_or("LOGIN_LOCKOUT_SECS", 900)),
+        }
+    }
+}
+
+/// リバースプロキシの設定
+///
+/// 接続元が `trusted_proxies` に含まれる場合のみ `X-Forwarded-For` を信頼する。
+#[derive(Debug, Clone, Default)]
+pub struct ProxyConfig {
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl ProxyConfig {
+    pub fn from_env() -> Self {
+        let trusted_proxies = std::env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .filter_map(|value| match value.parse() {
+                Ok(ip) => Some(ip),
+                Err(_) => {
+                    eprintln!("TRUSTED_PROXIES の値を無視します: {}", value);
+                    None
+                }
+            })
+            .collect();
+
+        Self { trusted_proxies }
+    }
+
+    pub fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.contains(ip)
+    }
+}
+
+/// 監査ログの保存期間とアーカイブの設定
+///
+/// `retention` より古いログは `interval` ごとに `archive_dir` のファイルへ移してから
+/// テーブルから削除する。保存期間を 0 日にするとアーカイブは行わない。
+#[derive(Debug, Clone)]
+pub struct AuditRetentionConfig {
+    pub retention: Option<Duration>,
+    pub archive_dir: PathBuf,
+    pub interval: std::time::Duration,
+}
+
+impl AuditRetentionConfig {
+    pub fn from_env() -> Self {
+        let retention_days: i64 = env_or("AUDIT_RETENTION_DAYS", 0);
+        Self {
+            retention: (retention_days > 0).then(|| Duration::days(retention_days)),
+            archive_dir: PathBuf::from(env_or("AUDIT_ARCHIVE_DIR", "audit_archives".to_string())),
+            interval: std::time::Duration::from_secs(env_or("AUDIT_ARCHIVE_INTERVAL_SECS", 3600)),
+        }
+    }
+}
+
+/// 外部の認証基盤の値（IDトークンのクレームやグループ）からロールへの対応
+///
+/// `"外部の値=ロール,外部の値=ロール"` の形式で指定し、先に書いたものほど優先する。
+/// どれにも一致しない場合は `default_role` を使い、それもなければログインを拒否する。
+#[derive(Debug, Clone, Default)]
+pub struct RoleMapping {
+    pub rules: Vec<(String, String)>,
+    pub default_role: Option<String>,
+}
+
+impl RoleMapping {
+    pub fn from_env(mapping_key: &str, default_role_key: &str) -> Self {
+        let rules = std::env::var(mapping_key)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .filter_map(|rule| match rule.rsplit_once('=') {
+                Some((value, role)) if !value.trim().is_empty() && !role.trim().is_empty() => {
+                    Some((value.trim().to_string(), role.trim().to_string()))
+                }
+                _ => {
+                    eprintln!("{} の値を無視します: {}", mapping_key, rule);
+                    None
+                }
+            })
+            .collect();
+
+        let default_role = std::env::var(default_role_key)
+            .ok()
+            .map(|role| role.trim().to_string())
+            .filter(|role| !role.is_empty());
+
+        Self { rules, default_role }
+    }
+
+    /// 外部の値に対応するロールを返す
+    pub fn resolve(&self, values: &[String]) -> Option<&str> {
+        self.rules
+            .iter()
+            .find(|(value, _)| values.contains(value))
+            .map(|(_, role)| role.as_str())
+            .or(self.default_role.as_deref())
+    }
+}
+
+/// OpenID Connect によるシングルサインオンの設定
+///
+/// `OIDC_ISSUER_URL` が設定されている場合のみ有効になる。
+/// ロールは IDトークンの `role_claim`（`realm_access.roles` のような `.` 区切りも可）の値で決める。
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    pub issuer_url: String,
+    pub client_id: String,
+    /// 未設定の場合は公開クライアントとして PKCE のみで認可コードを交換する
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    pub scopes: String,
+    pub username_claim: String,
+    pub role_claim: String,
+    pub role_mapping: RoleMapping,
+    /// ログイン画面のボタンに表示する名前
+    pub label: String,
+}
+
+impl OidcConfig {
+    pub fn from_env() -> Option<Self> {
+        let issuer_url = std::env::var("OIDC_ISSUER_URL").ok().filter(|value| !value.trim().is_empty())?;
+        let (Ok(client_id), Ok(redirect_url)) = (std::env::var("OIDC_CLIENT_ID"), std::env::var("OIDC_REDIRECT_URL")) else {
+            eprintln!("OIDC_CLIENT_ID と OIDC_REDIRECT_URL が未設定のため OIDC ログインを無効にします");
+            return None;
+        };
+
+        Some(Self {
+            issuer_url: issuer_url.trim().trim_end_matches('/').to_string(),
+            client_id,
+            client_secret: std::env::var("OIDC_CLIENT_SECRET").ok().filter(|value| !value.is_empty()),
+            redirect_url,
+            scopes: env_or("OIDC_SCOPES", "openid profile email".to_string()),
+            username_claim: env_or("OIDC_USERNAME_CLAIM", "preferred_username".to_string()),
+            role_claim: env_or("OIDC_ROLE_CLAIM", "roles".to_string()),
+            role_mapping: RoleMapping::from_env("OIDC_ROLE_MAP", "OIDC_DEFAULT_ROLE"),
+            label: env_or("OIDC_LABEL", "SSO".to_string()),
+        })
+    }
+}
+
+/// ログインフォームのパスワードを確認する方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthBackend {
+    /// `users.password_hash` の Argon2 ハッシュ
+    Local,
+    /// LDAP ディレクトリへの simple bind
+    Ldap,
+}
+
+/// ログインフォームの認証設定
+///
+/// `AUTH_BACKENDS` に書いた順にバックエンドを試し、最初に認証できたものを使う。
+/// 例えば `ldap,local` とすると、ディレクトリに障害があってもローカルの管理者でログインできる。
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    pub backends: Vec<AuthBackend>,
+    pub ldap: Option<LdapConfig>,
+}
+
+impl AuthConfig {
+    pub fn from_env() -> Self {
+        let ldap = LdapConfig::from_env();
+        let mut backends: Vec<AuthBackend> = env_or("AUTH_BACKENDS", "local".to_string())
+            .split(',')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .filter_map(|value| match value {
+                "local" => Some(AuthBackend::Local),
+                "ldap" if ldap.is_some() => Some(AuthBackend::Ldap),
+                "ldap" => {
+                    eprintln!("LDAP_URL と LDAP_BIND_DN_TEMPLATE が未設定のため LDAP 認証を無効にします");
+                    None
+                }
+                _ => {
+                    eprintln!("AUTH_BACKENDS の値を無視します: {}", value);
+                    None
+                }
+            })
+            .collect();
+        backends.dedup();
+        if backends.is_empty() {
+            backends.push(AuthBackend::Local);
+        }
+
+        Self { backends, ldap }
+    }
+}
+
+/// LDAP ディレクトリによる認証の設定
+///
+/// ユーザー名を `bind_dn_template` の `{username}` に埋め込んだ DN で simple bind する。
+/// `group_base` を指定した場合は bind したユーザーの権限でグループを検索し、
+/// `group_attribute` の値を `role_mapping` でロールに対応させる。
+#[derive(Debug, Clone)]
+pub struct LdapConfig {
+    /// `ldap://` または `ldaps://` のURL
+    pub url: String,
+    pub starttls: bool,
+    pub bind_dn_template: String,
+    pub group_base: Option<String>,
+    /// `{dn}`（bind したDN）と `{username}` を置き換える
+    pub group_filter: String,
+    pub group_attribute: String,
+    pub role_mapping: RoleMapping,
+    /// ローカルのユーザーを初回ログイン時に作成し、ログインのたびにロールを更新する
+    /// （false の場合は登録済みのユーザーのパスワード確認にのみ使う）
+    pub sync_users: bool,
+    pub timeout: std::time::Duration,
+}
+
+impl LdapConfig {
+    pub fn from_env() -> Option<Self> {
+        let url = std::env::var("LDAP_URL").ok().filter(|value| !value.trim().is_empty())?;
+        let bind_dn_template = std::env::var("LDAP_BIND_DN_TEMPLATE")
+            .ok()
+            .filter(|value| value.contains("{username}"))?;
+
+        Some(Self {
+            url: url.trim().to_string(),
+            starttls: env_or("LDAP_STARTTLS", false),
+            bind_dn_template,
+            group_base: std::env::var("LDAP_GROUP_BASE").ok().filter(|value| !value.trim().is_empty()),
+            group_filter: env_or("LDAP_GROUP_FILTER", "(member={dn})".to_string()),
+            group_attribute: env_or("LDAP_GROUP_ATTRIBUTE", "cn".to_string()),
+            role_mapping: RoleMapping::from_env("LDAP_ROLE_MAP", "LDAP_DEFAULT_ROLE"),
+            sync_users: env_or("LDAP_SYNC_USERS", true),
+            timeout: std::time::Duration::from_secs(env_or("LDAP_TIMEOUT_SECS", 10)),
+        })
+    }
+}
+
+// よく使われるため文字数の条件を満たしていても拒否するパスワード
+const COMMON_PASSWORDS: [&str; 12] = [
+    "password", "password1", "password123", "12345678", "123456789", "1234567890",
+    "qwerty123", "qwertyuiop", "iloveyou", "admin123", "letmein1", "welcome1",
+];
+
+/// パスワードポリシー
+///
+/// 新しいパスワードを保存する前（ユーザー作成・リセット・本人による変更）に確認する。
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// 英字と数字の両方を含める
+    pub require_letters_and_digits: bool,
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> Self {
+        Self {
+            min_length: env_or("PASSWORD_MIN_LENGTH", 8),
+            max_length: env_or("PASSWORD_MAX_LENGTH", 128),
+            require_letters_and_digits: env_or("PASSWORD_REQUIRE_LETTERS_AND_DIGITS", false),
+        }
+    }
+
+    /// ポリシーを満たさない場合は理由を返す
+    pub fn validate(&self, password: &str, username: &str) -> Result<(), String> {
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(format!("パスワードは{}文字以上にしてください", self.min_length));
+        }
+        if length > self.max_length {
+            return Err(format!("パスワードは{}文字以下にしてください", self.max_length));
+        }
+        if self.require_letters_and_digits
+            && !(password.chars().any(|c| c.is_alphabetic()) && password.chars().any(|c| c.is_ascii_digit()))
+        {
+            return Err("パスワードには英字と数字の両方を含めてください".into());
+        }
+
+        let lowercase = password.to_lowercase();
+        let username = username.trim().to_lowercase();
+        if !username.is_empty() && lowercase.contains(&username) {
+            return Err("パスワードにユーザー名を含めないでください".into());
+        }
+        if COMMON_PASSWORDS.contains(&lowercase.as_str()) {
+            return Err("推測されやすいパスワードは使用できません".into());
+        }
+        let mut chars = password.chars();
+        if let Some(first) = chars.next() && chars.all(|c| c == first) {
+            return Err("同じ文字だけのパスワードは使用できません".into());
+        }
+
+        Ok(())
+    }
+}
+
+/// パスワードハッシュ（Argon2id）のパラメータ
+///
+/// 新しいハッシュはこのパラメータで作成する。ログイン時に保存済みのハッシュが
+/// これより弱いパラメータで作られていた場合は、各パラメータを保存済みの値と
+/// この値の大きい方にして作り直す（作り直しでどのパラメータも下がらない）。
+#[derive(Debug, Clone)]
+pub struct PasswordHashing {
+    pub params: argon2::Params,
+}
+
+impl PasswordHashing {
+    pub fn from_env() -> Self {
+        let memory_kib = env_or("ARGON2_MEMORY_KIB", argon2::Params::DEFAULT_M_COST);
+        let iterations = env_or("ARGON2_ITERATIONS", argon2::Params::DEFAULT_T_COST);
+        let parallelism = env_or("ARGON2_PARALLELISM", argon2::Params::DEFAULT_P_COST);
+
+        let params = argon2::Params::new(memory_kib, iterations, parallelism, None).unwrap_or_else(|e| {
+            eprintln!("Argon2のパラメータが不正なため既定値を使用します: {}", e);
+            argon2::Params::default()
+        });
+        Self { params }
+    }
+
+    /// パスワードを PHC形式の Argon2id ハッシュにする
+    pub fn hash(&self, password: &str) -> Result<String, password_hash::Error> {
+        self.hash_with_params(password, self.params.clone())
+    }
+
+    /// `rehash_params` で決めたパラメータでハッシュにする
+    pub fn hash_with_params(&self, password: &str, params: argon2::Params) -> Result<String, password_hash::Error> {
+        use password_hash::{PasswordHasher, SaltString, rand_core::OsRng};
+
+        let hasher = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+        let salt = SaltString::generate(&mut OsRng);
+        Ok(hasher.hash_password(password.as_bytes(), &salt)?.to_string())
+    }
+
+    /// 保存済みのハッシュを作り直すときのパラメータ（作り直す必要がなければ None）
+    ///
+    /// 現在のパラメータより弱いものが1つでもあれば、パラメータごとに保存済みの値と
+    /// 現在の値の大きい方を返す。PHC形式でないハッシュは作り直せないため None。
+    pub fn rehash_params(&self, password_hash: &str) -> Option<argon2::Params> {
+        let hash = password_hash::PasswordHash::new(password_hash).ok()?;
+        let outdated = hash.algorithm != argon2::Algorithm::Argon2id.ident()
+            || hash.version != Some(argon2::Version::V0x13.into());
+
+        // Argon2 以外の PHC形式のハッシュは現在のパラメータで作り直す
+        let Ok(stored) = argon2::Params::try_from(&hash) else {
+            return outdated.then(|| self.params.clone());
+        };
+        let weaker = stored.m_cost() < self.params.m_cost()
+            || stored.t_cost() < self.params.t_cost()
+            || stored.p_cost() < self.params.p_cost();
+        if !outdated && !weaker {
+            return None;
+        }
+
+        argon2::Params::new(
+            stored.m_cost().max(self.params.m_cost()),
+            stored.t_cost().max(self.params.t_cost()),
+            stored.p_cost().max(self.params.p_cost()),
+            self.params.output_len(),
+        )
+        .ok()
+    }
+
+    /// 監査ログなどに残すためのパラメータの表記
+    pub fn describe(password_hash: &str) -> String {
+        password_hash::PasswordHash::new(password_hash)
+            .ok()
+            .and_then(|hash| {
+                let params = argon2::Params::try_from(&hash).ok()?;
+                Some(format!(
+                    "{} v={} m={},t={},p={}",
+                    hash.algorithm,
+                    hash.version.unwrap_or_default(),
+                    params.m_cost(),
+                    params.t_cost(),
+                    params.p_cost()
+                ))
+            })
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+}
+
+/// シークレット保管庫の設定
+///
+/// `VAULT_MASTER_KEY`（32バイトの値を Base64 で表したもの）が設定されている場合のみ有効になる。
+/// 鍵を出力しないよう Debug は実装しない。
+#[derive(Clone)]
+pub struct VaultConfig {
+    pub master_key: [u8; 32],
+    /// 暗号化に使った鍵を見分けるための値（鍵の SHA-256 の先頭8バイト）
+    pub key_id: String,
+}
+
+impl VaultConfig {
+    pub fn from_env() -> Option<Self> {
+        use base64::Engine;
+        use sha2::{Digest, Sha256};
+
+        let value = std::env::var("VAULT_MASTER_KEY").ok().filter(|value| !value.trim().is_empty())?;
+        let master_key: [u8; 32] = match base64::engine::general_purpose::STANDARD.decode(value.trim()) {
+            Ok(bytes) if bytes.len() == 32 => bytes.try_into().unwrap(),
+            _ => {
+                eprintln!("VAULT_MASTER_KEY が32バイトの Base64 ではないためシークレット保管庫を無効にします");
+                return None;
+            }
+        };
+
+        let digest = Sha256::digest(master_key);
+        Some(Self {
+            master_key,
+            key_id: digest[..8].iter().map(|b| format!("{:02x}", b)).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashing(m_cost: u32, t_cost: u32, p_cost: u32) -> PasswordHashing {
+        PasswordHashing { params: argon2::Params::new(m_cost, t_cost, p_cost, None).unwrap() }
+    }
+
+    fn costs(params: &argon2::Params) -> (u32, u32, u32) {
+        (params.m_cost(), params.t_cost(), params.p_cost())
+    }
+
+    #[test]
+    fn same_params_need_no_rehash() {
+        let hash = hashing(64, 2, 1).hash("password").unwrap();
+        assert!(hashing(64, 2, 1).rehash_params(&hash).is_none());
+    }
+
+    #[test]
+    fn stronger_params_need_no_rehash() {
+        let hash = hashing(128, 3, 2).hash("password").unwrap();
+        assert!(hashing(64, 2, 1).rehash_params(&hash).is_none());
+    }
+
+    #[test]
+    fn weaker_params_are_raised_to_policy() {
+        let hash = hashing(32, 1, 1).hash("password").unwrap();
+        let params = hashing(64, 2, 1).rehash_params(&hash).unwrap();
+        assert_eq!(costs(&params), (64, 2, 1));
+    }
+
+    #[test]
+    fn higher_memory_with_lower_iterations_keeps_memory() {
+        let hash = hashing(256, 1, 1).hash("password").unwrap();
+        let params = hashing(64, 2, 1).rehash_params(&hash).unwrap();
+        assert_eq!(costs(&params), (256, 2, 1));
+    }
+
+    #[test]
+    fn higher_iterations_with_lower_memory_keeps_iterations() {
+        let hash = hashing(32, 4, 1).hash("password").unwrap();
+        let params = hashing(64, 2, 1).rehash_params(&hash).unwrap();
+        assert_eq!(costs(&params), (64, 4, 1));
+    }
+
+    #[test]
+    fn higher_parallelism_is_kept() {
+        let hash = hashing(64, 1, 4).hash("password").unwrap();
+        let params = hashing(64, 2, 1).rehash_params(&hash).unwrap();
+        assert_eq!(costs(&params), (64, 2, 4));
+    }
+
+    #[test]
+    fn rehashed_password_never_weakens_any_param() {
+        let policy = hashing(64, 2, 1);
+        let stored = hashing(256, 1, 1).hash("password").unwrap();
+        let params = policy.rehash_params(&stored).unwrap();
+        let rehashed = policy.hash_with_params("password", params).unwrap();
+
+        let rehashed = argon2::Params::try_from(&password_hash::PasswordHash::new(&rehashed).unwrap()).unwrap();
+        assert_eq!(costs(&rehashed), (256, 2, 1));
+        assert!(policy.rehash_params(&policy.hash_with_params("password", rehashed).unwrap()).is_none());
+    }
+
+    #[test]
+    fn other_argon2_variant_is_rehashed_with_at_least_stored_params() {
+        let argon2i = argon2::Argon2::new(
+            argon2::Algorithm::Argon2i,
+            argon2::Version::V0x13,
+            argon2::Params::new(256, 3, 1, None).unwrap(),
+        );
+        let salt = password_hash::SaltString::encode_b64(b"fixed-test-salt").unwrap();
+        let hash = password_hash::PasswordHasher::hash_password(&argon2i, b"password", &salt).unwrap().to_string();
+
+        let params = hashing(64, 2, 1).rehash_params(&hash).unwrap();
+        assert_eq!(costs(&params), (256, 3, 1));
+    }
+
+    #[test]
+    fn non_phc_hash_is_not_rehashed() {
+        assert!(hashing(64, 2, 1).rehash_params("ldap").is_none());
+        assert!(hashing(64, 2, 1).rehash_params("").is_none());
+    }
+}
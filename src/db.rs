@@ -1,64 +1,108 @@
-use sqlx::{MySqlPool, FromRow};
+use sqlx::{FromRow, MySql, MySqlPool, QueryBuilder};
 use serde::Serialize;
-use chrono::{DateTime, Utc};
-
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
-pub enum Role {
-    Admin,
-    User,
-    Viewer,
-}
-
-impl Role {
-    pub fn from_str(s: &str) -> Self {
-        match s.to_lowercase().as_str() {
-            "admin" => Role::Admin,
-            "user" => Role::User,
-            "viewer" => Role::Viewer,
-            _ => Role::User, // Default to User for unknown values
-        }
-    }
+use chrono::{DateTime, SubsecRound, Utc};
+use sha2::{Digest, Sha256};
+use tokio::sync::broadcast;
 
-    pub fn as_str(&self) -> &str {
-        match self {
-            Role::Admin => "admin",
-            Role::User => "user",
-            Role::Viewer => "viewer",
-        }
-    }
+/// 組み込みロールの名前
+pub const ROLE_ADMIN: &str = "admin";
+pub const ROLE_USER: &str = "user";
+
+/// ロールに付与できる権限
+///
+/// 権限の種類はコードで定義し、どのロールにどの権限を与えるかは
+/// `role_permissions` テーブルで管理する。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Permission {
+    SysinfoView,
+    CryptoUse,
+    UsersManage,
+    AuditView,
+    VaultUse,
+}
 
-    pub fn can_access_sysinfo(&self) -> bool {
-        matches!(self, Role::Admin | Role::User)
+impl Permission {
+    pub fn all() -> [Permission; 5] {
+        [
+            Permission::SysinfoView,
+            Permission::CryptoUse,
+            Permission::UsersManage,
+            Permission::AuditView,
+            Permission::VaultUse,
+        ]
     }
 
-    pub fn can_access_crypto(&self) -> bool {
-        matches!(self, Role::Admin | Role::User)
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::all().into_iter().find(|permission| permission.as_str() == s)
     }
 
-    pub fn can_access_users(&self) -> bool {
-        matches!(self, Role::Admin)
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::SysinfoView => "sysinfo.view",
+            Permission::CryptoUse => "crypto.use",
+            Permission::UsersManage => "users.manage",
+            Permission::AuditView => "audit.view",
+            Permission::VaultUse => "vault.use",
+        }
     }
 
-    pub fn can_access_audit(&self) -> bool {
-        matches!(self, Role::Admin)
+    pub fn description(&self) -> &'static str {
+        match self {
+            Permission::SysinfoView => "システム情報の閲覧",
+            Permission::CryptoUse => "暗号化/復号化ツールの利用",
+            Permission::UsersManage => "ユーザー・ロール・ログインロックの管理",
+            Permission::AuditView => "監査ログの閲覧・エクスポート",
+            Permission::VaultUse => "シークレット保管庫の利用",
+        }
     }
 }
 
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct Role {
+    pub name: String,
+    pub description: String,
+    /// 組み込みロール（admin / user / viewer）は削除できない
+    pub is_builtin: bool,
+}
+
+/// ロール名として使える文字列か（英小文字・数字・`_`・`-`、1〜50文字）
+pub fn is_valid_role_name(name: &str) -> bool {
+    (1..=50).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
 #[derive(Debug, Clone, FromRow, Serialize)]
 pub struct User {
     pub id: i32,
     pub username: String,
     pub password_hash: String,
+    /// 次のログインでパスワードの変更を求める
+    pub must_change_password: bool,
     #[sqlx(rename = "role")]
-    role_str: String,
+    role_name: String,
+    pub disabled_at: Option<DateTime<Utc>>,
 }
 
 impl User {
-    pub fn role(&self) -> Role {
-        Role::from_str(&self.role_str)
+    pub fn role(&self) -> &str {
+        &self.role_name
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.disabled_at.is_some()
+    }
+
+    /// ローカルのパスワードを持つか（外部の認証基盤のユーザーは持たない）
+    pub fn has_local_password(&self) -> bool {
+        self.password_hash != EXTERNAL_PASSWORD_HASH
     }
 }
 
+const USER_COLUMNS: &str = "SELECT id, username, password_hash, must_change_password, role, disabled_at FROM users";
+
+/// 外部の認証基盤でログインするユーザーの password_hash（PHC形式ではないため、パスワードでは常にログインできない）
+pub const EXTERNAL_PASSWORD_HASH: &str = "!external";
+
 #[derive(Debug, Clone, FromRow, Serialize)]
 pub struct AuditLog {
     pub id: i32,
@@ -70,6 +114,8 @@ pub struct AuditLog {
     pub ip_address: Option<String>,
     pub user_agent: Option<String>,
     pub created_at: DateTime<Utc>,
+    pub prev_hash: Option<String>,
+    pub entry_hash: Option<String>,
 }
 
 pub async fn create_pool(database_url: &str) -> Result<MySqlPool, sqlx::Error> {
@@ -77,22 +123,30 @@ pub async fn create_pool(database_url: &str) -> Result<MySqlPool, sqlx::Error> {
 }
 
 pub async fn get_user_by_username(pool: &MySqlPool, username: &str) -> Result<Option<User>, sqlx::Error> {
-    let user = sqlx::query_as::<_, User>(
-        "SELECT id, username, password_hash, role FROM users WHERE username = ?"
-    )
-    .bind(username)
-    .fetch_optional(pool)
-    .await?;
+    let user = sqlx::query_as::<_, User>(&format!("{} WHERE username = ?", USER_COLUMNS))
+        .bind(username)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(user)
+}
+
+pub async fn get_user_by_id(pool: &MySqlPool, id: i32) -> Result<Option<User>, sqlx::Error> {
+    let user = sqlx::query_as::<_, User>(&format!("{} WHERE id = ?", USER_COLUMNS))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
 
     Ok(user)
 }
 
-pub async fn create_user(pool: &MySqlPool, username: &str, password_hash: &str) -> Result<User, sqlx::Error> {
+pub async fn create_user(pool: &MySqlPool, username: &str, password_hash: &str, role: &str) -> Result<User, sqlx::Error> {
     let result = sqlx::query(
-        "INSERT INTO users (username, password_hash, role) VALUES (?, ?, 'user')"
+        "INSERT INTO users (username, password_hash, role) VALUES (?, ?, ?)"
     )
     .bind(username)
     .bind(password_hash)
+    .bind(role)
     .execute(pool)
     .await?;
 
@@ -102,67 +156,1652 @@ pub async fn create_user(pool: &MySqlPool, username: &str, password_hash: &str)
         id: user_id,
         username: username.to_string(),
         password_hash: password_hash.to_string(),
-        role_str: "user".to_string(),
+        must_change_password: false,
+        role_name: role.to_string(),
+        disabled_at: None,
     };
 
     Ok(user)
 }
 
-pub async fn list_users(pool: &MySqlPool) -> Result<Vec<User>, sqlx::Error> {
-    let users = sqlx::query_as::<_, User>(
-        "SELECT id, username, password_hash, role FROM users ORDER BY id"
+/// 外部の認証基盤のアカウントに対応するユーザーを返す
+pub async fn get_user_by_identity(pool: &MySqlPool, provider: &str, subject: &str) -> Result<Option<User>, sqlx::Error> {
+    let user = sqlx::query_as::<_, User>(
+        "SELECT u.id, u.username, u.password_hash, u.must_change_password, u.role, u.disabled_at FROM users u \
+         JOIN user_identities i ON i.user_id = u.id WHERE i.provider = ? AND i.subject = ?"
     )
-    .fetch_all(pool)
+    .bind(provider)
+    .bind(subject)
+    .fetch_optional(pool)
     .await?;
 
+    Ok(user)
+}
+
+/// 外部の認証基盤のアカウントに対応するユーザーを作成する（パスワードではログインできない）
+pub async fn create_external_user(
+    pool: &MySqlPool,
+    username: &str,
+    role: &str,
+    provider: &str,
+    subject: &str,
+) -> Result<User, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query("INSERT INTO users (username, password_hash, role) VALUES (?, ?, ?)")
+        .bind(username)
+        .bind(EXTERNAL_PASSWORD_HASH)
+        .bind(role)
+        .execute(&mut *tx)
+        .await?;
+    let user_id = result.last_insert_id() as i32;
+
+    sqlx::query("INSERT INTO user_identities (user_id, provider, subject, last_login_at) VALUES (?, ?, ?, ?)")
+        .bind(user_id)
+        .bind(provider)
+        .bind(subject)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(User {
+        id: user_id,
+        username: username.to_string(),
+        password_hash: EXTERNAL_PASSWORD_HASH.to_string(),
+        must_change_password: false,
+        role_name: role.to_string(),
+        disabled_at: None,
+    })
+}
+
+pub async fn touch_user_identity(pool: &MySqlPool, provider: &str, subject: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE user_identities SET last_login_at = ? WHERE provider = ? AND subject = ?")
+        .bind(Utc::now())
+        .bind(provider)
+        .bind(subject)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn update_user_role(pool: &MySqlPool, id: i32, role: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET role = ? WHERE id = ?")
+        .bind(role)
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// パスワードを更新する（`must_change_password` が true の場合は次のログインで変更を求める）
+pub async fn update_user_password(
+    pool: &MySqlPool,
+    id: i32,
+    password_hash: &str,
+    must_change_password: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET password_hash = ?, must_change_password = ? WHERE id = ?")
+        .bind(password_hash)
+        .bind(must_change_password)
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// パスワードハッシュを同じパスワードの新しいハッシュに置き換える
+///
+/// 保存されているハッシュが `current_hash` のままの場合のみ更新し、更新したかを返す。
+pub async fn replace_password_hash(
+    pool: &MySqlPool,
+    id: i32,
+    current_hash: &str,
+    new_hash: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE users SET password_hash = ? WHERE id = ? AND password_hash = ?")
+        .bind(new_hash)
+        .bind(id)
+        .bind(current_hash)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn set_must_change_password(pool: &MySqlPool, id: i32, must_change_password: bool) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET must_change_password = ? WHERE id = ?")
+        .bind(must_change_password)
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// ユーザーを無効化（disabled = true）または再度有効化する
+pub async fn set_user_disabled(pool: &MySqlPool, id: i32, disabled: bool) -> Result<(), sqlx::Error> {
+    let query = if disabled {
+        "UPDATE users SET disabled_at = CURRENT_TIMESTAMP WHERE id = ? AND disabled_at IS NULL"
+    } else {
+        "UPDATE users SET disabled_at = NULL WHERE id = ?"
+    };
+    sqlx::query(query)
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_user(pool: &MySqlPool, id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM users WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn list_users(pool: &MySqlPool) -> Result<Vec<User>, sqlx::Error> {
+    let users = sqlx::query_as::<_, User>(&format!("{} ORDER BY id", USER_COLUMNS))
+        .fetch_all(pool)
+        .await?;
+
     Ok(users)
 }
 
+/// 監査ログに記録するリクエスト元の情報
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// 書き込まれた監査ログを購読者（`/audit/live` など）に配信するチャネル
+pub type AuditLogSender = broadcast::Sender<AuditLog>;
+
+/// 監査ログを書き込み、コミット後に events へ配信する
+#[allow(clippy::too_many_arguments)]
 pub async fn create_audit_log(
     pool: &MySqlPool,
+    events: &AuditLogSender,
+    client: &ClientInfo,
     user_id: Option<i32>,
     username: &str,
     action: &str,
     resource: Option<&str>,
     details: Option<&str>,
-    ip_address: Option<&str>,
-    user_agent: Option<&str>,
 ) -> Result<(), sqlx::Error> {
-    sqlx::query(
-        "INSERT INTO audit_logs (user_id, username, action, resource, details, ip_address, user_agent) VALUES (?, ?, ?, ?, ?, ?, ?)"
+    let mut tx = pool.begin().await?;
+
+    // チェーンの末尾をロックして直前のハッシュを取得する
+    let (prev_hash,): (String,) = sqlx::query_as(
+        "SELECT last_hash FROM audit_chain_state WHERE id = 1 FOR UPDATE"
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    // TIMESTAMP 列は秒単位で保存されるため、ハッシュ計算前にそろえておく
+    let created_at = Utc::now().trunc_subsecs(0);
+
+    let result = sqlx::query(
+        "INSERT INTO audit_logs (user_id, username, action, resource, details, ip_address, user_agent, created_at, prev_hash) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
     )
     .bind(user_id)
     .bind(username)
     .bind(action)
     .bind(resource)
     .bind(details)
+    .bind(&client.ip_address)
+    .bind(&client.user_agent)
+    .bind(created_at)
+    .bind(&prev_hash)
+    .execute(&mut *tx)
+    .await?;
+
+    let mut log = AuditLog {
+        id: result.last_insert_id() as i32,
+        user_id,
+        username: username.to_string(),
+        action: action.to_string(),
+        resource: resource.map(str::to_string),
+        details: details.map(str::to_string),
+        ip_address: client.ip_address.clone(),
+        user_agent: client.user_agent.clone(),
+        created_at,
+        prev_hash: Some(prev_hash.clone()),
+        entry_hash: None,
+    };
+    let entry_hash = audit_log_hash(&prev_hash, &log);
+
+    sqlx::query("UPDATE audit_logs SET entry_hash = ? WHERE id = ?")
+        .bind(&entry_hash)
+        .bind(log.id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("UPDATE audit_chain_state SET last_id = ?, last_hash = ? WHERE id = 1")
+        .bind(log.id)
+        .bind(&entry_hash)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    // 購読者がいない場合のエラーは無視する
+    log.entry_hash = Some(entry_hash);
+    let _ = events.send(log);
+
+    Ok(())
+}
+
+/// ハッシュチェーンの起点（最初の行の prev_hash）
+pub const AUDIT_CHAIN_GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// 監査ログ1行分のハッシュを計算する
+///
+/// 各値を長さ付きで連結するため、列の境界をずらした改ざんも検出できる。
+/// user_id はユーザー削除時に外部キーで NULL に更新されるため対象外（username で代替）。
+pub fn audit_log_hash(prev_hash: &str, log: &AuditLog) -> String {
+    fn field(hasher: &mut Sha256, value: Option<&str>) {
+        match value {
+            Some(value) => {
+                hasher.update([1u8]);
+                hasher.update((value.len() as u64).to_be_bytes());
+                hasher.update(value.as_bytes());
+            }
+            None => hasher.update([0u8]),
+        }
+    }
+
+    let mut hasher = Sha256::new();
+    field(&mut hasher, Some(prev_hash));
+    field(&mut hasher, Some(&log.id.to_string()));
+    field(&mut hasher, Some(&log.username));
+    field(&mut hasher, Some(&log.action));
+    field(&mut hasher, log.resource.as_deref());
+    field(&mut hasher, log.details.as_deref());
+    field(&mut hasher, log.ip_address.as_deref());
+    field(&mut hasher, log.user_agent.as_deref());
+    field(&mut hasher, Some(&log.created_at.timestamp().to_string()));
+    format!("{:x}", hasher.finalize())
+}
+
+/// ハッシュチェーン検証の結果
+#[derive(Debug, Clone)]
+pub struct AuditChainReport {
+    /// 検証した行数
+    pub checked: u64,
+    /// チェーン導入前（ハッシュなし）の行数
+    pub legacy: u64,
+    /// 最初に見つかった不整合
+    pub broken: Option<AuditChainBreak>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditChainBreak {
+    /// 不整合が見つかった行の ID（末尾の行が削除された場合は最後に残っている行の ID）
+    pub id: i32,
+    pub reason: String,
+}
+
+const AUDIT_CHAIN_BATCH_SIZE: i64 = 1000;
+
+/// 監査ログを古い順に1行ずつ受け取ってハッシュチェーンを検証する
+struct AuditChainVerifier {
+    report: AuditChainReport,
+    expected_prev: Option<String>,
+    tail_id: i32,
+}
+
+impl AuditChainVerifier {
+    /// `tail_id` と `expected_prev` は検証を始める直前の行（アーカイブ済みの最後の行）の ID とハッシュ
+    fn new(tail_id: i32, expected_prev: Option<String>) -> Self {
+        Self {
+            report: AuditChainReport { checked: 0, legacy: 0, broken: None },
+            expected_prev,
+            tail_id,
+        }
+    }
+
+    /// 次の行を検証する（不整合が見つかった場合は `false` を返し、以降の行は検証しない）
+    fn check(&mut self, log: &AuditLog) -> bool {
+        let broken = |reason: String| Some(AuditChainBreak { id: log.id, reason });
+
+        let (Some(prev_hash), Some(entry_hash)) = (&log.prev_hash, &log.entry_hash) else {
+            // チェーン開始後にハッシュのない行が現れるのは改ざん
+            if self.expected_prev.is_some() {
+                self.report.broken = broken("hash columns are missing".into());
+                return false;
+            }
+            self.report.legacy += 1;
+            return true;
+        };
+
+        let expected = self.expected_prev.as_deref().unwrap_or(AUDIT_CHAIN_GENESIS);
+        if prev_hash != expected {
+            self.report.broken = broken(format!(
+                "prev_hash does not match the previous entry (expected {}, found {}); an entry may have been deleted or inserted",
+                expected, prev_hash
+            ));
+            return false;
+        }
+
+        if audit_log_hash(prev_hash, log) != *entry_hash {
+            self.report.broken = broken("entry_hash does not match the row contents; the entry has been modified".into());
+            return false;
+        }
+
+        self.expected_prev = Some(entry_hash.clone());
+        self.tail_id = log.id;
+        self.report.checked += 1;
+        true
+    }
+
+    /// 末尾の行が削除されていないか、記録された末尾（`audit_chain_state`）と比べて結果を返す
+    fn finish(mut self, last_id: i32, last_hash: &str) -> AuditChainReport {
+        if self.report.broken.is_some() {
+            return self.report;
+        }
+
+        let tail_hash = self.expected_prev.as_deref().unwrap_or(AUDIT_CHAIN_GENESIS);
+        if self.tail_id != last_id || tail_hash != last_hash {
+            self.report.broken = Some(AuditChainBreak {
+                id: self.tail_id,
+                reason: format!(
+                    "chain ends at entry {} but the recorded tail is entry {}; trailing entries may have been deleted",
+                    self.tail_id, last_id
+                ),
+            });
+        }
+        self.report
+    }
+}
+
+/// 監査ログを古い順に辿ってハッシュチェーンを検証し、最初の不整合を報告する
+pub async fn verify_audit_chain(pool: &MySqlPool) -> Result<AuditChainReport, sqlx::Error> {
+    let (last_id, last_hash): (i32, String) = sqlx::query_as(
+        "SELECT last_id, last_hash FROM audit_chain_state WHERE id = 1"
+    )
+    .fetch_one(pool)
+    .await?;
+
+    // アーカイブ済みの行があれば、最後にアーカイブした行のハッシュから検証を始める
+    let (mut verifier, mut after_id) = match latest_audit_archive(pool).await? {
+        Some(archive) => (AuditChainVerifier::new(archive.last_id, archive.last_entry_hash), archive.last_id),
+        None => (AuditChainVerifier::new(0, None), 0),
+    };
+
+    loop {
+        let logs = list_audit_logs_after(pool, after_id, AUDIT_CHAIN_BATCH_SIZE).await?;
+
+        let Some(last) = logs.last() else {
+            break;
+        };
+        after_id = last.id;
+
+        for log in &logs {
+            if !verifier.check(log) {
+                return Ok(verifier.report);
+            }
+        }
+    }
+
+    Ok(verifier.finish(last_id, &last_hash))
+}
+
+/// 監査ログの検索条件（すべて省略可能）
+#[derive(Debug, Clone, Default)]
+pub struct AuditLogFilter {
+    pub username: Option<String>,
+    pub action: Option<String>,
+    /// 前方一致で比較する
+    pub resource: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    /// この時刻を含まない
+    pub to: Option<DateTime<Utc>>,
+    /// details の部分一致検索
+    pub search: Option<String>,
+}
+
+/// キーセットページネーションの位置
+#[derive(Debug, Clone, Copy)]
+pub enum AuditLogCursor {
+    /// 最新のログから
+    Latest,
+    /// 指定IDより古いログ
+    Before(i32),
+    /// 指定IDより新しいログ
+    After(i32),
+}
+
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// WHERE 句に検索条件を追加する（idx_username, idx_action, idx_created_at を利用）
+pub fn push_audit_log_filter<'a>(builder: &mut QueryBuilder<'a, MySql>, filter: &'a AuditLogFilter) {
+    builder.push(" WHERE 1 = 1");
+    if let Some(username) = &filter.username {
+        builder.push(" AND username = ").push_bind(username);
+    }
+    if let Some(action) = &filter.action {
+        builder.push(" AND action = ").push_bind(action);
+    }
+    if let Some(resource) = &filter.resource {
+        builder.push(" AND resource LIKE ").push_bind(format!("{}%", escape_like(resource)));
+    }
+    if let Some(from) = filter.from {
+        builder.push(" AND created_at >= ").push_bind(from);
+    }
+    if let Some(to) = filter.to {
+        builder.push(" AND created_at < ").push_bind(to);
+    }
+    if let Some(search) = &filter.search {
+        builder.push(" AND details LIKE ").push_bind(format!("%{}%", escape_like(search)));
+    }
+}
+
+const AUDIT_LOG_COLUMNS: &str = "SELECT id, user_id, username, action, resource, details, ip_address, user_agent, created_at, prev_hash, entry_hash FROM audit_logs";
+
+/// after_id より後の監査ログを古い順に最大 limit 件返す
+pub async fn list_audit_logs_after(
+    pool: &MySqlPool,
+    after_id: i32,
+    limit: i64,
+) -> Result<Vec<AuditLog>, sqlx::Error> {
+    sqlx::query_as(&format!("{} WHERE id > ? ORDER BY id ASC LIMIT ?", AUDIT_LOG_COLUMNS))
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+}
+
+/// 条件に一致する監査ログを新しい順に最大 limit 件返す
+pub async fn search_audit_logs(
+    pool: &MySqlPool,
+    filter: &AuditLogFilter,
+    cursor: AuditLogCursor,
+    limit: i64,
+) -> Result<Vec<AuditLog>, sqlx::Error> {
+    let mut builder = QueryBuilder::<MySql>::new(AUDIT_LOG_COLUMNS);
+    push_audit_log_filter(&mut builder, filter);
+
+    match cursor {
+        AuditLogCursor::Latest => {
+            builder.push(" ORDER BY id DESC");
+        }
+        AuditLogCursor::Before(id) => {
+            builder.push(" AND id < ").push_bind(id).push(" ORDER BY id DESC");
+        }
+        AuditLogCursor::After(id) => {
+            builder.push(" AND id > ").push_bind(id).push(" ORDER BY id ASC");
+        }
+    }
+    builder.push(" LIMIT ").push_bind(limit);
+
+    let mut logs = builder
+        .build_query_as::<AuditLog>()
+        .fetch_all(pool)
+        .await?;
+
+    // 新しい方向に辿った場合も表示は新しい順にそろえる
+    if matches!(cursor, AuditLogCursor::After(_)) {
+        logs.reverse();
+    }
+
+    Ok(logs)
+}
+
+pub async fn list_audit_actions(pool: &MySqlPool) -> Result<Vec<String>, sqlx::Error> {
+    let actions: Vec<(String,)> = sqlx::query_as("SELECT DISTINCT action FROM audit_logs ORDER BY action")
+        .fetch_all(pool)
+        .await?;
+
+    Ok(actions.into_iter().map(|(action,)| action).collect())
+}
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct LoginLockout {
+    pub id: i32,
+    pub scope: String,
+    pub subject: String,
+    pub failure_count: i32,
+    pub locked_until: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub cleared_at: Option<DateTime<Utc>>,
+    pub cleared_by: Option<String>,
+}
+
+pub const LOCKOUT_SCOPE_ACCOUNT: &str = "account";
+pub const LOCKOUT_SCOPE_IP: &str = "ip";
+
+/// ロックの判定に数える失敗（ログインと、パスワード変更時の現在のパスワードの誤り）
+const LOCKOUT_FAILURE_ACTIONS: &str = "('login_failed', 'password_change_failed')";
+
+pub async fn count_failed_logins_by_username(pool: &MySqlPool, username: &str, since: DateTime<Utc>) -> Result<i64, sqlx::Error> {
+    let (count,): (i64,) = sqlx::query_as(&format!(
+        "SELECT COUNT(*) FROM audit_logs WHERE username = ? AND action IN {} AND created_at >= ?",
+        LOCKOUT_FAILURE_ACTIONS
+    ))
+    .bind(username)
+    .bind(since)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count)
+}
+
+pub async fn count_failed_logins_by_ip(pool: &MySqlPool, ip_address: &str, since: DateTime<Utc>) -> Result<i64, sqlx::Error> {
+    let (count,): (i64,) = sqlx::query_as(&format!(
+        "SELECT COUNT(*) FROM audit_logs WHERE ip_address = ? AND action IN {} AND created_at >= ?",
+        LOCKOUT_FAILURE_ACTIONS
+    ))
     .bind(ip_address)
-    .bind(user_agent)
+    .bind(since)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count)
+}
+
+pub async fn get_active_lockout(pool: &MySqlPool, scope: &str, subject: &str) -> Result<Option<LoginLockout>, sqlx::Error> {
+    let lockout = sqlx::query_as::<_, LoginLockout>(
+        "SELECT id, scope, subject, failure_count, locked_until, created_at, cleared_at, cleared_by FROM login_lockouts WHERE scope = ? AND subject = ? AND cleared_at IS NULL AND locked_until > ? ORDER BY locked_until DESC LIMIT 1"
+    )
+    .bind(scope)
+    .bind(subject)
+    .bind(Utc::now())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(lockout)
+}
+
+/// 直近のロック（作成または解除）の時刻。これ以前の失敗は再ロックの判定に数えない
+pub async fn last_lockout_reset(pool: &MySqlPool, scope: &str, subject: &str) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let row: Option<(DateTime<Utc>, Option<DateTime<Utc>>)> = sqlx::query_as(
+        "SELECT created_at, cleared_at FROM login_lockouts WHERE scope = ? AND subject = ? ORDER BY id DESC LIMIT 1"
+    )
+    .bind(scope)
+    .bind(subject)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|(created_at, cleared_at)| cleared_at.unwrap_or(created_at)))
+}
+
+pub async fn create_lockout(pool: &MySqlPool, scope: &str, subject: &str, failure_count: i64, locked_until: DateTime<Utc>) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO login_lockouts (scope, subject, failure_count, locked_until) VALUES (?, ?, ?, ?)"
+    )
+    .bind(scope)
+    .bind(subject)
+    .bind(failure_count)
+    .bind(locked_until)
     .execute(pool)
     .await?;
 
     Ok(())
 }
 
-pub async fn list_audit_logs(pool: &MySqlPool, limit: i64) -> Result<Vec<AuditLog>, sqlx::Error> {
-    let logs = sqlx::query_as::<_, AuditLog>(
-        "SELECT id, user_id, username, action, resource, details, ip_address, user_agent, created_at FROM audit_logs ORDER BY created_at DESC LIMIT ?"
+pub async fn list_active_lockouts(pool: &MySqlPool) -> Result<Vec<LoginLockout>, sqlx::Error> {
+    let lockouts = sqlx::query_as::<_, LoginLockout>(
+        "SELECT id, scope, subject, failure_count, locked_until, created_at, cleared_at, cleared_by FROM login_lockouts WHERE cleared_at IS NULL AND locked_until > ? ORDER BY created_at DESC"
     )
-    .bind(limit)
+    .bind(Utc::now())
     .fetch_all(pool)
     .await?;
 
-    Ok(logs)
+    Ok(lockouts)
 }
 
-pub async fn list_audit_logs_by_user(pool: &MySqlPool, username: &str, limit: i64) -> Result<Vec<AuditLog>, sqlx::Error> {
-    let logs = sqlx::query_as::<_, AuditLog>(
-        "SELECT id, user_id, username, action, resource, details, ip_address, user_agent, created_at FROM audit_logs WHERE username = ? ORDER BY created_at DESC LIMIT ?"
+pub async fn get_lockout(pool: &MySqlPool, id: i32) -> Result<Option<LoginLockout>, sqlx::Error> {
+    let lockout = sqlx::query_as::<_, LoginLockout>(
+        "SELECT id, scope, subject, failure_count, locked_until, created_at, cleared_at, cleared_by FROM login_lockouts WHERE id = ?"
     )
-    .bind(username)
-    .bind(limit)
-    .fetch_all(pool)
+    .bind(id)
+    .fetch_optional(pool)
     .await?;
 
-    Ok(logs)
+    Ok(lockout)
+}
+
+pub async fn clear_lockout(pool: &MySqlPool, id: i32, cleared_by: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE login_lockouts SET cleared_at = ?, cleared_by = ? WHERE id = ? AND cleared_at IS NULL"
+    )
+    .bind(Utc::now())
+    .bind(cleared_by)
+    .bind(id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct UserTotp {
+    pub user_id: i32,
+    pub secret: String,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+    pub enabled_at: Option<DateTime<Utc>>,
+    /// 最後に受け付けた確認コードのタイムステップ
+    pub last_used_step: Option<i64>,
+}
+
+pub const SETTING_REQUIRE_2FA_ADMIN: &str = "require_2fa_admin";
+
+pub async fn get_user_totp(pool: &MySqlPool, user_id: i32) -> Result<Option<UserTotp>, sqlx::Error> {
+    let totp = sqlx::query_as::<_, UserTotp>(
+        "SELECT user_id, secret, enabled, created_at, enabled_at, last_used_step FROM user_totp WHERE user_id = ?"
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(totp)
+}
+
+/// 登録途中のシークレットを保存する（既存の設定は登録途中のものも上書きしない）
+///
+/// 別のタブで読み取り中のQRコードを無効にしないよう、すでに行があれば何もしない。
+pub async fn save_pending_totp(pool: &MySqlPool, user_id: i32, secret: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT IGNORE INTO user_totp (user_id, secret, enabled) VALUES (?, ?, FALSE)"
+    )
+    .bind(user_id)
+    .bind(secret)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn enable_totp(pool: &MySqlPool, user_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE user_totp SET enabled = TRUE, enabled_at = ? WHERE user_id = ?")
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// 確認コードのタイムステップを使用済みとして記録する
+///
+/// 以前に受け付けたステップ以下の場合は記録せず `false` を返す（同じコードの再利用を拒否する）。
+pub async fn record_totp_step(pool: &MySqlPool, user_id: i32, step: u64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE user_totp SET last_used_step = ? \
+         WHERE user_id = ? AND (last_used_step IS NULL OR last_used_step < ?)"
+    )
+    .bind(step)
+    .bind(user_id)
+    .bind(step)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// TOTP設定とリカバリーコードを削除する
+pub async fn delete_totp(pool: &MySqlPool, user_id: i32) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("DELETE FROM user_recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM user_totp WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await
+}
+
+pub async fn list_totp_enabled_user_ids(pool: &MySqlPool) -> Result<Vec<i32>, sqlx::Error> {
+    let ids: Vec<(i32,)> = sqlx::query_as("SELECT user_id FROM user_totp WHERE enabled = TRUE")
+        .fetch_all(pool)
+        .await?;
+
+    Ok(ids.into_iter().map(|(id,)| id).collect())
+}
+
+/// 既存のリカバリーコードを破棄し、新しいコードのハッシュに置き換える
+pub async fn replace_recovery_codes(pool: &MySqlPool, user_id: i32, code_hashes: &[String]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("DELETE FROM user_recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    for code_hash in code_hashes {
+        sqlx::query("INSERT INTO user_recovery_codes (user_id, code_hash) VALUES (?, ?)")
+            .bind(user_id)
+            .bind(code_hash)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await
+}
+
+/// 未使用のリカバリーコードであれば使用済みにして true を返す
+pub async fn consume_recovery_code(pool: &MySqlPool, user_id: i32, code_hash: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE user_recovery_codes SET used_at = ? WHERE user_id = ? AND code_hash = ? AND used_at IS NULL"
+    )
+    .bind(Utc::now())
+    .bind(user_id)
+    .bind(code_hash)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn count_unused_recovery_codes(pool: &MySqlPool, user_id: i32) -> Result<i64, sqlx::Error> {
+    let (count,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM user_recovery_codes WHERE user_id = ? AND used_at IS NULL"
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count)
+}
+
+pub async fn get_setting(pool: &MySqlPool, name: &str) -> Result<Option<String>, sqlx::Error> {
+    let value: Option<(String,)> = sqlx::query_as("SELECT value FROM security_settings WHERE name = ?")
+        .bind(name)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(value.map(|(value,)| value))
+}
+
+pub async fn set_setting(pool: &MySqlPool, name: &str, value: &str, updated_by: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO security_settings (name, value, updated_by) VALUES (?, ?, ?) \
+         ON DUPLICATE KEY UPDATE value = VALUES(value), updated_by = VALUES(updated_by)"
+    )
+    .bind(name)
+    .bind(value)
+    .bind(updated_by)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct AuditArchive {
+    pub id: i32,
+    pub file_name: String,
+    pub first_id: i32,
+    pub last_id: i32,
+    pub row_count: i32,
+    pub oldest_at: Option<DateTime<Utc>>,
+    pub newest_at: Option<DateTime<Utc>>,
+    pub last_entry_hash: Option<String>,
+    pub file_sha256: String,
+    pub created_at: DateTime<Utc>,
+}
+
+const AUDIT_ARCHIVE_COLUMNS: &str = "SELECT id, file_name, first_id, last_id, row_count, oldest_at, newest_at, last_entry_hash, file_sha256, created_at FROM audit_archives";
+
+/// アーカイブファイルに書き出した監査ログを記録し、audit_logs から削除する
+///
+/// logs は ID の昇順で連続している必要がある。
+pub async fn archive_audit_logs(
+    pool: &MySqlPool,
+    file_name: &str,
+    file_sha256: &str,
+    logs: &[AuditLog],
+) -> Result<(), sqlx::Error> {
+    let (Some(first), Some(last)) = (logs.first(), logs.last()) else {
+        return Ok(());
+    };
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        "INSERT INTO audit_archives (file_name, first_id, last_id, row_count, oldest_at, newest_at, last_entry_hash, file_sha256) VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(file_name)
+    .bind(first.id)
+    .bind(last.id)
+    .bind(logs.len() as i32)
+    .bind(logs.iter().map(|log| log.created_at).min())
+    .bind(logs.iter().map(|log| log.created_at).max())
+    .bind(&last.entry_hash)
+    .bind(file_sha256)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM audit_logs WHERE id >= ? AND id <= ?")
+        .bind(first.id)
+        .bind(last.id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await
+}
+
+/// アーカイブの記録を新しい順に最大 limit 件返す
+pub async fn list_audit_archives(pool: &MySqlPool, limit: i64) -> Result<Vec<AuditArchive>, sqlx::Error> {
+    sqlx::query_as(&format!("{} ORDER BY last_id DESC LIMIT ?", AUDIT_ARCHIVE_COLUMNS))
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn latest_audit_archive(pool: &MySqlPool) -> Result<Option<AuditArchive>, sqlx::Error> {
+    sqlx::query_as(&format!("{} ORDER BY last_id DESC LIMIT 1", AUDIT_ARCHIVE_COLUMNS))
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn list_roles(pool: &MySqlPool) -> Result<Vec<Role>, sqlx::Error> {
+    sqlx::query_as::<_, Role>(
+        "SELECT name, description, is_builtin FROM roles ORDER BY is_builtin DESC, name"
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_role(pool: &MySqlPool, name: &str) -> Result<Option<Role>, sqlx::Error> {
+    sqlx::query_as::<_, Role>("SELECT name, description, is_builtin FROM roles WHERE name = ?")
+        .bind(name)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn create_role(pool: &MySqlPool, name: &str, description: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO roles (name, description) VALUES (?, ?)")
+        .bind(name)
+        .bind(description)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// 組み込みでないロールを削除する（ユーザーが割り当てられている場合は外部キー制約でエラーになる）
+pub async fn delete_role(pool: &MySqlPool, name: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM roles WHERE name = ? AND is_builtin = FALSE")
+        .bind(name)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn count_users_with_role(pool: &MySqlPool, role: &str) -> Result<i64, sqlx::Error> {
+    let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM users WHERE role = ?")
+        .bind(role)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(count)
+}
+
+/// ロールに付与されている権限を返す（未知の権限名は無視する）
+pub async fn list_role_permissions(pool: &MySqlPool, role: &str) -> Result<Vec<Permission>, sqlx::Error> {
+    let rows: Vec<(String,)> = sqlx::query_as("SELECT permission FROM role_permissions WHERE role = ?")
+        .bind(role)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.iter().filter_map(|(name,)| Permission::parse(name)).collect())
+}
+
+pub async fn role_has_permission(pool: &MySqlPool, role: &str, permission: Permission) -> Result<bool, sqlx::Error> {
+    let row: Option<(i32,)> = sqlx::query_as(
+        "SELECT 1 FROM role_permissions WHERE role = ? AND permission = ?"
+    )
+    .bind(role)
+    .bind(permission.as_str())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.is_some())
+}
+
+/// ロールの権限をまとめて置き換える
+pub async fn set_role_permissions(pool: &MySqlPool, role: &str, permissions: &[Permission]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("DELETE FROM role_permissions WHERE role = ?")
+        .bind(role)
+        .execute(&mut *tx)
+        .await?;
+
+    for permission in permissions {
+        sqlx::query("INSERT INTO role_permissions (role, permission) VALUES (?, ?)")
+            .bind(role)
+            .bind(permission.as_str())
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await
+}
+
+/// REST API 用の個人APIトークン（トークン自体ではなくハッシュを保存する）
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct ApiToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    /// 一覧でトークンを見分けるための先頭部分
+    pub token_prefix: String,
+    /// トークンの権限の上限（発行したユーザーのロールの権限を超えることはない）
+    pub role: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_at.is_some()
+    }
+}
+
+const API_TOKEN_COLUMNS: &str = "SELECT id, user_id, name, token_hash, token_prefix, role, created_at, last_used_at, revoked_at FROM api_tokens";
+
+pub async fn create_api_token(
+    pool: &MySqlPool,
+    user_id: i32,
+    name: &str,
+    token_hash: &str,
+    token_prefix: &str,
+    role: &str,
+) -> Result<ApiToken, sqlx::Error> {
+    let result = sqlx::query(
+        "INSERT INTO api_tokens (user_id, name, token_hash, token_prefix, role) VALUES (?, ?, ?, ?, ?)"
+    )
+    .bind(user_id)
+    .bind(name)
+    .bind(token_hash)
+    .bind(token_prefix)
+    .bind(role)
+    .execute(pool)
+    .await?;
+
+    let token = sqlx::query_as::<_, ApiToken>(&format!("{} WHERE id = ?", API_TOKEN_COLUMNS))
+        .bind(result.last_insert_id() as i32)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(token)
+}
+
+/// 有効なユーザーのトークンをハッシュで探す（無効化・削除されたユーザーのトークンは返さない）
+pub async fn get_active_user_api_token_by_hash(pool: &MySqlPool, token_hash: &str) -> Result<Option<ApiToken>, sqlx::Error> {
+    let token = sqlx::query_as::<_, ApiToken>(
+        "SELECT t.id, t.user_id, t.name, t.token_hash, t.token_prefix, t.role, t.created_at, t.last_used_at, t.revoked_at \
+         FROM api_tokens t JOIN users u ON u.id = t.user_id \
+         WHERE t.token_hash = ? AND u.disabled_at IS NULL"
+    )
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(token)
+}
+
+/// ユーザーが発行したトークンを新しい順に返す（失効済みを含む）
+pub async fn list_api_tokens(pool: &MySqlPool, user_id: i32) -> Result<Vec<ApiToken>, sqlx::Error> {
+    let tokens = sqlx::query_as::<_, ApiToken>(&format!("{} WHERE user_id = ? ORDER BY id DESC", API_TOKEN_COLUMNS))
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(tokens)
+}
+
+/// ユーザー自身の有効なトークンを失効させる（失効させた場合は true）
+pub async fn revoke_api_token(pool: &MySqlPool, id: i32, user_id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE api_tokens SET revoked_at = ? WHERE id = ? AND user_id = ? AND revoked_at IS NULL"
+    )
+    .bind(Utc::now())
+    .bind(id)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// ユーザーの有効なトークンをすべて失効させ、失効させた数を返す
+pub async fn revoke_user_api_tokens(pool: &MySqlPool, user_id: i32) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("UPDATE api_tokens SET revoked_at = ? WHERE user_id = ? AND revoked_at IS NULL")
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+pub async fn touch_api_token(pool: &MySqlPool, id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE api_tokens SET last_used_at = ? WHERE id = ?")
+        .bind(Utc::now())
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// ログイン中のセッション（`sessions` テーブルのうち user_id が設定された行）
+///
+/// セッションIDはそれ自体がログイン情報のため画面には出さず、SHA-256 の値（handle）で指定する。
+#[derive(Debug, Clone, FromRow)]
+pub struct UserSession {
+    pub handle: String,
+    pub created_at: DateTime<Utc>,
+    pub last_activity_at: Option<DateTime<Utc>>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub expiry_date: DateTime<Utc>,
+}
+
+/// セッションIDから一覧の handle を求める（`SHA2(id, 256)` と同じ値）
+pub fn session_handle(session_id: &str) -> String {
+    format!("{:x}", Sha256::digest(session_id.as_bytes()))
+}
+
+/// ユーザーの有効なセッションを最近使われた順に返す
+pub async fn list_user_sessions(pool: &MySqlPool, user_id: i32) -> Result<Vec<UserSession>, sqlx::Error> {
+    let sessions = sqlx::query_as::<_, UserSession>(
+        "SELECT SHA2(id, 256) AS handle, created_at, last_activity_at, ip_address, user_agent, expiry_date \
+         FROM sessions WHERE user_id = ? AND expiry_date > ? \
+         ORDER BY COALESCE(last_activity_at, created_at) DESC"
+    )
+    .bind(user_id)
+    .bind(Utc::now())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(sessions)
+}
+
+/// ユーザーのセッションを1つ失効させる（失効させた場合は true）
+pub async fn delete_user_session(pool: &MySqlPool, user_id: i32, handle: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM sessions WHERE user_id = ? AND SHA2(id, 256) = ?")
+        .bind(user_id)
+        .bind(handle)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// ユーザーのセッションをすべて失効させ、失効させた数を返す（`keep_session_id` は残す）
+pub async fn delete_user_sessions(
+    pool: &MySqlPool,
+    user_id: i32,
+    keep_session_id: Option<&str>,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM sessions WHERE user_id = ? AND id <> ?")
+        .bind(user_id)
+        .bind(keep_session_id.unwrap_or_default())
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// セッションの最終アクセス日時と接続元を更新する（`interval` 以内に更新済みの場合は何もしない）
+pub async fn touch_session(
+    pool: &MySqlPool,
+    session_id: &str,
+    client: &ClientInfo,
+    interval: chrono::Duration,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    sqlx::query(
+        "UPDATE sessions SET last_activity_at = ?, ip_address = ?, user_agent = ? \
+         WHERE id = ? AND (last_activity_at IS NULL OR last_activity_at < ?)"
+    )
+    .bind(now)
+    .bind(&client.ip_address)
+    .bind(&client.user_agent)
+    .bind(session_id)
+    .bind(now - interval)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// キーリングの鍵の種類（公開鍵暗号の X25519 / age と署名の Ed25519）
+pub const KEY_TYPE_X25519: &str = "x25519";
+pub const KEY_TYPE_ED25519: &str = "ed25519";
+
+/// キーリングに登録したユーザーの鍵
+#[derive(Debug, Clone, FromRow)]
+pub struct KeyringEntry {
+    pub user_id: i32,
+    pub username: String,
+    pub key_type: String,
+    pub public_key: String,
+    /// パスフレーズで暗号化した秘密鍵（公開鍵だけを登録した場合は `None`）
+    pub encrypted_private_key: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl KeyringEntry {
+    pub fn has_private_key(&self) -> bool {
+        self.encrypted_private_key.is_some()
+    }
+}
+
+const KEYRING_COLUMNS: &str = "SELECT k.user_id, u.username, k.key_type, k.public_key, k.encrypted_private_key, k.created_at \
+     FROM keyring k JOIN users u ON u.id = k.user_id";
+
+pub async fn get_keyring_entry(pool: &MySqlPool, user_id: i32, key_type: &str) -> Result<Option<KeyringEntry>, sqlx::Error> {
+    let entry = sqlx::query_as::<_, KeyringEntry>(&format!("{} WHERE k.user_id = ? AND k.key_type = ?", KEYRING_COLUMNS))
+        .bind(user_id)
+        .bind(key_type)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(entry)
+}
+
+/// 鍵を登録しているユーザーをユーザー名の順に返す（無効化したユーザーは除く）
+pub async fn list_keyring(pool: &MySqlPool, key_type: &str) -> Result<Vec<KeyringEntry>, sqlx::Error> {
+    let entries = sqlx::query_as::<_, KeyringEntry>(&format!(
+        "{} WHERE k.key_type = ? AND u.disabled_at IS NULL ORDER BY u.username",
+        KEYRING_COLUMNS
+    ))
+    .bind(key_type)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(entries)
+}
+
+/// 鍵を登録する（同じ種類の鍵が登録済みの場合は登録せず false）
+pub async fn create_keyring_entry(
+    pool: &MySqlPool,
+    user_id: i32,
+    key_type: &str,
+    public_key: &str,
+    encrypted_private_key: Option<&str>,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "INSERT IGNORE INTO keyring (user_id, key_type, public_key, encrypted_private_key, created_at) VALUES (?, ?, ?, ?, ?)"
+    )
+    .bind(user_id)
+    .bind(key_type)
+    .bind(public_key)
+    .bind(encrypted_private_key)
+    .bind(Utc::now())
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// 登録済みの鍵を置き換える（現在の公開鍵が `current_public_key` でない場合は置き換えず false）
+pub async fn replace_keyring_entry(
+    pool: &MySqlPool,
+    user_id: i32,
+    key_type: &str,
+    current_public_key: &str,
+    public_key: &str,
+    encrypted_private_key: Option<&str>,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE keyring SET public_key = ?, encrypted_private_key = ?, created_at = ? \
+         WHERE user_id = ? AND key_type = ? AND public_key = ?"
+    )
+    .bind(public_key)
+    .bind(encrypted_private_key)
+    .bind(Utc::now())
+    .bind(user_id)
+    .bind(key_type)
+    .bind(current_public_key)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// 登録済みの鍵を削除する（現在の公開鍵が `current_public_key` でない場合は削除せず false）
+pub async fn delete_keyring_entry(
+    pool: &MySqlPool,
+    user_id: i32,
+    key_type: &str,
+    current_public_key: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM keyring WHERE user_id = ? AND key_type = ? AND public_key = ?")
+        .bind(user_id)
+        .bind(key_type)
+        .bind(current_public_key)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// シークレット保管庫のシークレット（値は含まない）
+#[derive(Debug, Clone, FromRow)]
+pub struct VaultSecret {
+    pub id: i32,
+    pub name: String,
+    pub description: String,
+    pub owner_id: Option<i32>,
+    pub owner_username: Option<String>,
+    pub current_version: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// シークレットの値の1つのバージョン（暗号化したまま）
+#[derive(Debug, Clone, FromRow)]
+pub struct VaultSecretVersion {
+    pub version: i32,
+    pub key_id: String,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    pub created_by_username: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// シークレットの共有先
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VaultGrant {
+    User { user_id: i32, username: String },
+    Role(String),
+}
+
+const VAULT_SECRET_COLUMNS: &str = "SELECT s.id, s.name, s.description, s.owner_id, u.username AS owner_username, \
+     s.current_version, s.created_at, s.updated_at FROM vault_secrets s LEFT JOIN users u ON u.id = s.owner_id";
+
+// ユーザーがシークレットの所有者か、ユーザーまたはそのロールに共有されている
+const VAULT_ACCESS_CONDITION: &str = "(s.owner_id = ? \
+     OR EXISTS (SELECT 1 FROM vault_secret_user_grants g WHERE g.secret_id = s.id AND g.user_id = ?) \
+     OR EXISTS (SELECT 1 FROM vault_secret_role_grants r WHERE r.secret_id = s.id AND r.role = ?))";
+
+pub async fn get_vault_secret(pool: &MySqlPool, id: i32) -> Result<Option<VaultSecret>, sqlx::Error> {
+    let secret = sqlx::query_as::<_, VaultSecret>(&format!("{} WHERE s.id = ?", VAULT_SECRET_COLUMNS))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(secret)
+}
+
+/// すべてのシークレットを名前の順に返す（管理者用、値は含まない）
+pub async fn list_vault_secrets(pool: &MySqlPool) -> Result<Vec<VaultSecret>, sqlx::Error> {
+    let secrets = sqlx::query_as::<_, VaultSecret>(&format!("{} ORDER BY s.name", VAULT_SECRET_COLUMNS))
+        .fetch_all(pool)
+        .await?;
+
+    Ok(secrets)
+}
+
+/// ユーザーが所有しているか共有されているシークレットを名前の順に返す
+pub async fn list_accessible_vault_secrets(pool: &MySqlPool, user_id: i32, role: &str) -> Result<Vec<VaultSecret>, sqlx::Error> {
+    let secrets = sqlx::query_as::<_, VaultSecret>(&format!(
+        "{} WHERE {} ORDER BY s.name",
+        VAULT_SECRET_COLUMNS, VAULT_ACCESS_CONDITION
+    ))
+    .bind(user_id)
+    .bind(user_id)
+    .bind(role)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(secrets)
+}
+
+pub async fn can_access_vault_secret(pool: &MySqlPool, secret_id: i32, user_id: i32, role: &str) -> Result<bool, sqlx::Error> {
+    let count: (i64,) = sqlx::query_as(&format!(
+        "SELECT COUNT(*) FROM vault_secrets s WHERE s.id = ? AND {}",
+        VAULT_ACCESS_CONDITION
+    ))
+    .bind(secret_id)
+    .bind(user_id)
+    .bind(user_id)
+    .bind(role)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count.0 > 0)
+}
+
+/// シークレットを作成し、値を最初のバージョンとして保存する
+pub async fn create_vault_secret(
+    pool: &MySqlPool,
+    name: &str,
+    description: &str,
+    owner_id: i32,
+    key_id: &str,
+    nonce: &[u8],
+    ciphertext: &[u8],
+) -> Result<i32, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let now = Utc::now();
+
+    let result = sqlx::query(
+        "INSERT INTO vault_secrets (name, description, owner_id, current_version, created_at, updated_at) VALUES (?, ?, ?, 1, ?, ?)"
+    )
+    .bind(name)
+    .bind(description)
+    .bind(owner_id)
+    .bind(now)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+    let id = result.last_insert_id() as i32;
+
+    sqlx::query(
+        "INSERT INTO vault_secret_versions (secret_id, version, key_id, nonce, ciphertext, created_by, created_at) \
+         VALUES (?, 1, ?, ?, ?, ?, ?)"
+    )
+    .bind(id)
+    .bind(key_id)
+    .bind(nonce)
+    .bind(ciphertext)
+    .bind(owner_id)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(id)
+}
+
+/// 新しいバージョンの値を追加する
+///
+/// 現在のバージョンが `version - 1` でない場合（同時に更新された場合）は何もせずに false を返す。
+pub async fn add_vault_secret_version(
+    pool: &MySqlPool,
+    secret_id: i32,
+    version: i32,
+    created_by: i32,
+    key_id: &str,
+    nonce: &[u8],
+    ciphertext: &[u8],
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let now = Utc::now();
+
+    let result = sqlx::query(
+        "UPDATE vault_secrets SET current_version = ?, updated_at = ? WHERE id = ? AND current_version = ?"
+    )
+    .bind(version)
+    .bind(now)
+    .bind(secret_id)
+    .bind(version - 1)
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query(
+        "INSERT INTO vault_secret_versions (secret_id, version, key_id, nonce, ciphertext, created_by, created_at) \
+         VALUES (?, ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(secret_id)
+    .bind(version)
+    .bind(key_id)
+    .bind(nonce)
+    .bind(ciphertext)
+    .bind(created_by)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+const VAULT_VERSION_COLUMNS: &str = "SELECT v.version, v.key_id, v.nonce, v.ciphertext, u.username AS created_by_username, v.created_at \
+     FROM vault_secret_versions v LEFT JOIN users u ON u.id = v.created_by";
+
+/// シークレットのバージョンを新しい順に返す
+pub async fn list_vault_secret_versions(pool: &MySqlPool, secret_id: i32) -> Result<Vec<VaultSecretVersion>, sqlx::Error> {
+    let versions = sqlx::query_as::<_, VaultSecretVersion>(&format!(
+        "{} WHERE v.secret_id = ? ORDER BY v.version DESC",
+        VAULT_VERSION_COLUMNS
+    ))
+    .bind(secret_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(versions)
+}
+
+pub async fn get_vault_secret_version(pool: &MySqlPool, secret_id: i32, version: i32) -> Result<Option<VaultSecretVersion>, sqlx::Error> {
+    let version = sqlx::query_as::<_, VaultSecretVersion>(&format!(
+        "{} WHERE v.secret_id = ? AND v.version = ?",
+        VAULT_VERSION_COLUMNS
+    ))
+    .bind(secret_id)
+    .bind(version)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(version)
+}
+
+pub async fn delete_vault_secret(pool: &MySqlPool, id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM vault_secrets WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// 共有先をロール、ユーザーの順に名前の順で返す
+pub async fn list_vault_grants(pool: &MySqlPool, secret_id: i32) -> Result<Vec<VaultGrant>, sqlx::Error> {
+    let roles: Vec<(String,)> = sqlx::query_as("SELECT role FROM vault_secret_role_grants WHERE secret_id = ? ORDER BY role")
+        .bind(secret_id)
+        .fetch_all(pool)
+        .await?;
+    let users: Vec<(i32, String)> = sqlx::query_as(
+        "SELECT u.id, u.username FROM vault_secret_user_grants g \
+         JOIN users u ON u.id = g.user_id WHERE g.secret_id = ? ORDER BY u.username"
+    )
+    .bind(secret_id)
+    .fetch_all(pool)
+    .await?;
+
+    let roles = roles.into_iter().map(|(role,)| VaultGrant::Role(role));
+    let users = users.into_iter().map(|(user_id, username)| VaultGrant::User { user_id, username });
+    Ok(roles.chain(users).collect())
+}
+
+/// ユーザーに共有する（共有済みの場合は何もせずに false）
+pub async fn add_vault_user_grant(pool: &MySqlPool, secret_id: i32, user_id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("INSERT IGNORE INTO vault_secret_user_grants (secret_id, user_id) VALUES (?, ?)")
+        .bind(secret_id)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// ロールに共有する（共有済みの場合は何もせずに false）
+pub async fn add_vault_role_grant(pool: &MySqlPool, secret_id: i32, role: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("INSERT IGNORE INTO vault_secret_role_grants (secret_id, role) VALUES (?, ?)")
+        .bind(secret_id)
+        .bind(role)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn delete_vault_user_grant(pool: &MySqlPool, secret_id: i32, user_id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM vault_secret_user_grants WHERE secret_id = ? AND user_id = ?")
+        .bind(secret_id)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn delete_vault_role_grant(pool: &MySqlPool, secret_id: i32, role: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM vault_secret_role_grants WHERE secret_id = ? AND role = ?")
+        .bind(secret_id)
+        .bind(role)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audit_log(id: i32, action: &str) -> AuditLog {
+        AuditLog {
+            id,
+            user_id: Some(1),
+            username: "alice".into(),
+            action: action.into(),
+            resource: Some("/users".into()),
+            details: Some(format!("entry {}", id)),
+            ip_address: Some("192.0.2.1".into()),
+            user_agent: None,
+            created_at: DateTime::from_timestamp(1_700_000_000 + i64::from(id), 0).unwrap(),
+            prev_hash: None,
+            entry_hash: None,
+        }
+    }
+
+    /// create_audit_log と同じ手順でハッシュを付けた監査ログを作る
+    fn chain(count: i32) -> Vec<AuditLog> {
+        let mut prev_hash = AUDIT_CHAIN_GENESIS.to_string();
+        (1..=count)
+            .map(|id| {
+                let mut log = audit_log(id, "login");
+                let entry_hash = audit_log_hash(&prev_hash, &log);
+                log.prev_hash = Some(prev_hash.clone());
+                log.entry_hash = Some(entry_hash.clone());
+                prev_hash = entry_hash;
+                log
+            })
+            .collect()
+    }
+
+    /// verify_audit_chain と同じ順序で検証する（末尾は最後の行で記録されているものとする）
+    fn verify(logs: &[AuditLog], recorded_tail: &AuditLog) -> AuditChainReport {
+        let mut verifier = AuditChainVerifier::new(0, None);
+        for log in logs {
+            if !verifier.check(log) {
+                return verifier.report;
+            }
+        }
+        verifier.finish(recorded_tail.id, recorded_tail.entry_hash.as_deref().unwrap())
+    }
+
+    #[test]
+    fn valid_chain_verifies() {
+        let logs = chain(5);
+        let report = verify(&logs, &logs[4]);
+        assert!(report.broken.is_none());
+        assert_eq!(report.checked, 5);
+    }
+
+    #[test]
+    fn legacy_rows_before_the_chain_are_counted() {
+        let mut logs = vec![audit_log(1, "login"), audit_log(2, "logout")];
+        let mut prev_hash = AUDIT_CHAIN_GENESIS.to_string();
+        for id in 3..=4 {
+            let mut log = audit_log(id, "login");
+            let entry_hash = audit_log_hash(&prev_hash, &log);
+            log.prev_hash = Some(prev_hash);
+            log.entry_hash = Some(entry_hash.clone());
+            prev_hash = entry_hash;
+            logs.push(log);
+        }
+
+        let report = verify(&logs, &logs[3]);
+        assert!(report.broken.is_none());
+        assert_eq!((report.legacy, report.checked), (2, 2));
+    }
+
+    #[test]
+    fn edited_field_fails() {
+        let mut logs = chain(5);
+        logs[2].details = Some("entry 3 (edited)".into());
+        let report = verify(&logs, &logs[4]);
+        assert_eq!(report.broken.map(|broken| broken.id), Some(3));
+        assert_eq!(report.checked, 2);
+    }
+
+    #[test]
+    fn shifted_field_boundary_fails() {
+        let mut logs = chain(3);
+        logs[1].resource = Some("/users".to_string() + "entry 2");
+        logs[1].details = Some(String::new());
+        let report = verify(&logs, &logs[2]);
+        assert_eq!(report.broken.map(|broken| broken.id), Some(2));
+    }
+
+    #[test]
+    fn deleted_row_fails() {
+        let mut logs = chain(5);
+        let tail = logs[4].clone();
+        logs.remove(2);
+        let report = verify(&logs, &tail);
+        assert_eq!(report.broken.map(|broken| broken.id), Some(4));
+    }
+
+    #[test]
+    fn deleted_tail_row_fails() {
+        let mut logs = chain(5);
+        let tail = logs.pop().unwrap();
+        let report = verify(&logs, &tail);
+        let broken = report.broken.unwrap();
+        assert_eq!(broken.id, 4);
+        assert!(broken.reason.contains("trailing entries"));
+    }
+
+    #[test]
+    fn reordered_rows_fail() {
+        let mut logs = chain(5);
+        logs.swap(1, 2);
+        let report = verify(&logs, &logs[3]);
+        assert_eq!(report.broken.map(|broken| broken.id), Some(3));
+    }
+
+    #[test]
+    fn missing_hash_after_chain_start_fails() {
+        let mut logs = chain(3);
+        logs[2].prev_hash = None;
+        logs[2].entry_hash = None;
+        let tail = chain(3).pop().unwrap();
+        let report = verify(&logs, &tail);
+        assert_eq!(report.broken.map(|broken| broken.id), Some(3));
+    }
 }
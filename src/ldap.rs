@@ -0,0 +1,62 @@
+//! LDAP ディレクトリによるパスワード認証
+
+use ldap3::{LdapConnAsync, LdapConnSettings, Scope, SearchEntry, dn_escape, ldap_escape};
+use crate::config::LdapConfig;
+
+// simple bind でパスワードが違う場合の結果コード（invalidCredentials）
+const RC_INVALID_CREDENTIALS: u32 = 49;
+
+/// 認証に成功したディレクトリのアカウント
+#[derive(Debug, Clone)]
+pub struct LdapAccount {
+    pub dn: String,
+    /// 所属するグループ（`group_attribute` の値）
+    pub groups: Vec<String>,
+}
+
+/// ユーザー名とパスワードで simple bind する
+///
+/// パスワードが違う場合は `Ok(None)`、ディレクトリに接続できない場合などは `Err` を返す。
+pub async fn authenticate(config: &LdapConfig, username: &str, password: &str) -> anyhow::Result<Option<LdapAccount>> {
+    // パスワードが空の simple bind は匿名 bind として成功してしまうため必ず拒否する
+    if username.is_empty() || password.is_empty() {
+        return Ok(None);
+    }
+
+    let dn = config.bind_dn_template.replace("{username}", &dn_escape(username));
+    let settings = LdapConnSettings::new()
+        .set_conn_timeout(config.timeout)
+        .set_starttls(config.starttls);
+    let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &config.url).await?;
+    ldap3::drive!(conn);
+
+    let result = ldap.with_timeout(config.timeout).simple_bind(&dn, password).await?;
+    if result.rc == RC_INVALID_CREDENTIALS {
+        let _ = ldap.unbind().await;
+        return Ok(None);
+    }
+    result.success()?;
+
+    let mut groups = Vec::new();
+    if let Some(group_base) = &config.group_base {
+        let filter = config
+            .group_filter
+            .replace("{dn}", &ldap_escape(&dn))
+            .replace("{username}", &ldap_escape(username));
+        let (entries, _) = ldap
+            .with_timeout(config.timeout)
+            .search(group_base, Scope::Subtree, &filter, vec![config.group_attribute.as_str()])
+            .await?
+            .success()?;
+
+        for entry in entries {
+            let mut entry = SearchEntry::construct(entry);
+            if let Some(values) = entry.attrs.remove(&config.group_attribute) {
+                groups.extend(values);
+            }
+        }
+    }
+
+    let _ = ldap.unbind().await;
+    Ok(Some(LdapAccount { dn, groups }))
+}
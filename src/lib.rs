@@ -1 +1,6 @@
+pub mod audit_archive;
+pub mod config;
 pub mod db;
+pub mod ldap;
+pub mod oidc;
+pub mod session_store;
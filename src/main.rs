@@ -1,26 +1,51 @@
 mod routes;
-mod db;
+use rust_dashboard::db::{self, Permission};
 use axum::{
-    Router, extract::Request, middleware, middleware::Next, response::Redirect, response::Response,
-    routing::get,
+    Router, extract::{DefaultBodyLimit, Request, State}, middleware, middleware::Next, response::Redirect, response::Response,
+    routing::{get, post},
 };
+use routes::api::RequireApiPermissionExt;
+use routes::authz::RequirePermissionExt;
+use routes::context::{ClientContext, CurrentUser};
 use sqlx::MySqlPool;
 use tower_http::services::ServeDir;
-use tower_sessions::{MemoryStore, SessionManagerLayer};
+use rust_dashboard::audit_archive;
+use rust_dashboard::config::{AuditRetentionConfig, AuthConfig, LockoutConfig, OidcConfig, PasswordHashing, PasswordPolicy, ProxyConfig, VaultConfig};
+use rust_dashboard::session_store::MySqlStore;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tower_sessions::{SessionManagerLayer, session_store::ExpiredDeletion};
+
+// 期限切れセッションを削除する間隔
+const SESSION_CLEANUP_INTERVAL: Duration = Duration::from_secs(300);
+// 監査ログ配信チャネルのバッファ件数（これを超えて遅れた購読者はログを取りこぼす）
+const AUDIT_EVENT_CAPACITY: usize = 256;
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: MySqlPool,
+    pub lockout: LockoutConfig,
+    pub proxy: ProxyConfig,
+    pub audit_retention: AuditRetentionConfig,
+    pub audit_events: db::AuditLogSender,
+    pub oidc: Option<OidcConfig>,
+    pub auth: AuthConfig,
+    pub password_policy: PasswordPolicy,
+    pub password_hashing: PasswordHashing,
+    pub vault: Option<VaultConfig>,
 }
 
 async fn auth_middleware(
+    State(state): State<AppState>,
+    ClientContext(client): ClientContext,
     session: tower_sessions::Session,
-    request: Request,
+    mut request: Request,
     next: Next,
 ) -> Result<Response, Redirect> {
     let path = request.uri().path();
 
-    if path == "/login" || path.starts_with("/static/") {
+    // REST API はセッションではなく APIトークンで認証する（routes::api::authenticate）
+    if path == "/login" || path.starts_with("/login/") || path.starts_with("/static/") || path.starts_with("/api/") {
         return Ok(next.run(request).await);
     }
 
@@ -28,6 +53,42 @@ async fn auth_middleware(
     eprintln!("認証チェック: path={}, authenticated={}", path, is_auth);
 
     if is_auth {
+        // ユーザーはリクエストごとに1回だけ読み込み、ハンドラは CurrentUser で受け取る
+        let user = match routes::auth::load_session_user(&session, &state.db).await {
+            Ok(user) => user,
+            Err(e) => {
+                eprintln!("ユーザー読み込みエラー: {:?}", e);
+                return Err(Redirect::to("/login"));
+            }
+        };
+        // 削除・無効化されたユーザーのセッションは即座に破棄する
+        let user = match user {
+            Some(user) if !user.is_disabled() => user,
+            _ => {
+                eprintln!("無効なユーザーのセッションを破棄して /login にリダイレクト");
+                let _ = session.delete().await;
+                return Err(Redirect::to("/login"));
+            }
+        };
+
+        // パスワードの変更を求められているユーザーは変更画面とログアウトのみ許可
+        if user.must_change_password
+            && user.has_local_password()
+            && path != "/account/password"
+            && path != "/logout"
+        {
+            return Err(Redirect::to("/account/password"));
+        }
+
+        // 二要素認証の登録が必須のユーザーは登録画面とログアウトのみ許可
+        if !path.starts_with("/account/2fa")
+            && path != "/logout"
+            && routes::auth::is_2fa_setup_required(&session).await
+        {
+            return Err(Redirect::to("/account/2fa"));
+        }
+        routes::sessions::track(&state, &session, &client).await;
+        request.extensions_mut().insert(CurrentUser(user));
         Ok(next.run(request).await)
     } else {
         eprintln!("未認証のため /login にリダイレクト");
@@ -48,10 +109,52 @@ async fn main() {
         .expect("Failed to connect to database");
 
     let app_state = AppState {
-        db: db_pool,
+        db: db_pool.clone(),
+        lockout: LockoutConfig::from_env(),
+        proxy: ProxyConfig::from_env(),
+        audit_retention: AuditRetentionConfig::from_env(),
+        audit_events: tokio::sync::broadcast::channel(AUDIT_EVENT_CAPACITY).0,
+        oidc: OidcConfig::from_env(),
+        auth: AuthConfig::from_env(),
+        password_policy: PasswordPolicy::from_env(),
+        password_hashing: PasswordHashing::from_env(),
+        vault: VaultConfig::from_env(),
     };
 
-    let session_store = MemoryStore::default();
+    // 保存期間を過ぎた監査ログを定期的にアーカイブするバックグラウンドタスク
+    if app_state.audit_retention.retention.is_some() {
+        let archive_pool = db_pool.clone();
+        let archive_config = app_state.audit_retention.clone();
+        let archive_events = app_state.audit_events.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(archive_config.interval);
+            loop {
+                interval.tick().await;
+                match audit_archive::run(&archive_pool, &archive_events, &archive_config).await {
+                    Ok(summary) if summary.rows > 0 => {
+                        eprintln!("監査ログを {} 件アーカイブしました: {}", summary.rows, summary.files.join(", "));
+                    }
+                    Ok(_) => {}
+                    Err(e) => eprintln!("監査ログのアーカイブに失敗: {:?}", e),
+                }
+            }
+        });
+    }
+
+    let session_store = MySqlStore::new(db_pool);
+
+    // 期限切れセッションを定期的に削除するバックグラウンドタスク
+    let cleanup_store = session_store.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SESSION_CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = cleanup_store.delete_expired().await {
+                eprintln!("期限切れセッションの削除に失敗: {:?}", e);
+            }
+        }
+    });
+
     let session_layer = SessionManagerLayer::new(session_store)
         .with_secure(false)  // HTTP でも動作するように設定（本番環境では true に）
         .with_same_site(tower_sessions::cookie::SameSite::Lax)
@@ -59,27 +162,163 @@ async fn main() {
             tower_sessions::cookie::time::Duration::hours(24)
         ));
 
-    let app = Router::new()
-        .route("/", get(routes::home::index))
-        .route("/about", get(routes::home::about))
-        .route("/time", get(routes::time::time))
+    // 必要な権限はルートの登録時に宣言する（routes::authz）
+    let sysinfo_routes = Router::new()
         .route("/sysinfo", get(routes::sysinfo::index))
         .route("/sysinfo/live", get(routes::sysinfo::live))
+        .require_permission(&app_state, Permission::SysinfoView);
+
+    let crypto_routes = Router::new()
+        .route("/crypto", get(routes::crypto::index))
+        .route("/crypto/encrypt", post(routes::crypto::encrypt))
+        .route("/crypto/decrypt", post(routes::crypto::decrypt))
+        .route(
+            "/crypto/encrypt-file",
+            post(routes::crypto_file::encrypt_file).layer(DefaultBodyLimit::max(routes::crypto_file::MAX_UPLOAD_BYTES)),
+        )
+        .route(
+            "/crypto/decrypt-file",
+            post(routes::crypto_file::decrypt_file).layer(DefaultBodyLimit::max(routes::crypto_file::MAX_UPLOAD_BYTES)),
+        )
+        .route("/crypto/public", get(routes::public_key::index))
+        .route("/crypto/public/keys/generate", post(routes::public_key::generate_key))
+        .route("/crypto/public/keys/import", post(routes::public_key::import_key))
+        .route("/crypto/public/keys/replace", post(routes::public_key::replace_key))
+        .route("/crypto/public/keys/delete", post(routes::public_key::delete_key))
+        .route("/crypto/public/encrypt", post(routes::public_key::encrypt))
+        .route("/crypto/public/decrypt", post(routes::public_key::decrypt))
+        .route(
+            "/crypto/sign",
+            get(routes::signing::index)
+                .post(routes::signing::sign)
+                .layer(DefaultBodyLimit::max(routes::signing::MAX_SIGN_FILE_BYTES)),
+        )
+        .route("/crypto/sign/keys/generate", post(routes::signing::generate_key))
+        .route("/crypto/sign/keys/import", post(routes::signing::import_key))
+        .route("/crypto/sign/keys/replace", post(routes::signing::replace_key))
+        .route("/crypto/sign/keys/delete", post(routes::signing::delete_key))
+        .route(
+            "/crypto/sign/verify",
+            post(routes::signing::verify).layer(DefaultBodyLimit::max(routes::signing::MAX_SIGN_FILE_BYTES)),
+        )
+        .require_permission(&app_state, Permission::CryptoUse);
+
+    let vault_routes = Router::new()
+        .route("/vault", get(routes::vault::list))
+        .route("/vault/create", post(routes::vault::create))
+        .route("/vault/{id}", get(routes::vault::detail))
+        .route("/vault/{id}/reveal", post(routes::vault::reveal))
+        .route("/vault/{id}/update", post(routes::vault::update))
+        .route("/vault/{id}/grants", post(routes::vault::add_grant))
+        .route("/vault/{id}/grants/delete", post(routes::vault::delete_grant))
+        .route("/vault/{id}/delete", post(routes::vault::delete))
+        .require_permission(&app_state, Permission::VaultUse);
+
+    let admin_routes = Router::new()
         .route("/users", get(routes::users::list_users))
+        .route("/users/create", post(routes::users::create_user))
+        .route("/users/{id}/role", post(routes::users::update_role))
+        .route("/users/{id}/password", post(routes::users::reset_password))
+        .route("/users/{id}/require-password-change", post(routes::users::require_password_change))
+        .route("/users/{id}/delete", post(routes::users::delete_user))
+        .route("/users/{id}/disable", post(routes::users::disable_user))
+        .route("/users/{id}/enable", post(routes::users::enable_user))
+        .route("/users/{id}/2fa/reset", post(routes::users::reset_two_factor))
+        .route("/users/{id}/sessions", get(routes::sessions::user_page))
+        .route("/users/{id}/sessions/revoke-all", post(routes::sessions::user_revoke_all))
+        .route("/users/{id}/sessions/{handle}/revoke", post(routes::sessions::user_revoke))
+        .route("/users/settings/2fa", post(routes::users::update_two_factor_settings))
+        .route("/roles", get(routes::roles::list_roles))
+        .route("/roles/create", post(routes::roles::create_role))
+        .route("/roles/{name}/permissions", post(routes::roles::update_permissions))
+        .route("/roles/{name}/delete", post(routes::roles::delete_role))
+        .route("/lockouts", get(routes::lockouts::list_lockouts))
+        .route("/lockouts/{id}/clear", post(routes::lockouts::clear_lockout))
+        .require_permission(&app_state, Permission::UsersManage);
+
+    let audit_routes = Router::new()
         .route("/audit", get(routes::audit::list_audit_logs))
-        .route("/crypto", get(routes::crypto::index))
-        .route("/crypto/encrypt", axum::routing::post(routes::crypto::encrypt))
-        .route("/crypto/decrypt", axum::routing::post(routes::crypto::decrypt))
+        .route("/audit/monitor", get(routes::audit::live_page))
+        .route("/audit/live", get(routes::audit::live))
+        .route("/audit/verify", get(routes::audit::verify_audit_logs))
+        .route("/audit/export.csv", get(routes::audit::export_csv))
+        .route("/audit/export.ndjson", get(routes::audit::export_ndjson))
+        .require_permission(&app_state, Permission::AuditView);
+
+    // REST API（/api/v1）はAPIトークンで認証し、権限はトークンのロールでも制限する
+    let api_routes = Router::new()
+        .route("/api/v1/me", get(routes::api::me))
+        .merge(
+            Router::new()
+                .route("/api/v1/users", get(routes::api::list_users))
+                .route("/api/v1/users/{id}", get(routes::api::get_user))
+                .require_api_permission(&app_state, Permission::UsersManage),
+        )
+        .merge(
+            Router::new()
+                .route("/api/v1/audit-logs", get(routes::api::list_audit_logs))
+                .require_api_permission(&app_state, Permission::AuditView),
+        )
+        .merge(
+            Router::new()
+                .route("/api/v1/sysinfo", get(routes::api::sysinfo))
+                .require_api_permission(&app_state, Permission::SysinfoView),
+        )
+        .merge(
+            Router::new()
+                .route("/api/v1/crypto/encrypt", post(routes::api::encrypt))
+                .route("/api/v1/crypto/decrypt", post(routes::api::decrypt))
+                .require_api_permission(&app_state, Permission::CryptoUse),
+        )
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), routes::api::authenticate));
+
+    let app = Router::new()
+        .route("/", get(routes::home::index))
+        .route("/about", get(routes::home::about))
+        .route("/time", get(routes::time::time))
+        .merge(sysinfo_routes)
+        .merge(crypto_routes)
+        .merge(vault_routes)
+        .merge(admin_routes)
+        .merge(audit_routes)
+        .merge(api_routes)
         .route(
             "/login",
             get(routes::auth::login_page).post(routes::auth::login),
         )
-        .route("/logout", get(routes::auth::logout))
+        .route("/login/oidc", get(routes::oidc::start))
+        .route("/login/oidc/callback", get(routes::oidc::callback))
+        .route(
+            "/login/2fa",
+            get(routes::two_factor::login_2fa_page).post(routes::two_factor::login_2fa),
+        )
+        .route("/logout", post(routes::auth::logout))
+        .route("/account/2fa", get(routes::two_factor::account_page))
+        .route("/account/2fa/setup", post(routes::two_factor::start_setup))
+        .route("/account/2fa/enable", post(routes::two_factor::enable))
+        .route("/account/2fa/disable", post(routes::two_factor::disable))
+        .route(
+            "/account/2fa/recovery-codes",
+            post(routes::two_factor::regenerate_recovery_codes),
+        )
+        .route(
+            "/account/password",
+            get(routes::password::page).post(routes::password::change),
+        )
+        .route("/account/sessions", get(routes::sessions::account_page))
+        .route("/account/sessions/revoke-others", post(routes::sessions::account_revoke_others))
+        .route("/account/sessions/{handle}/revoke", post(routes::sessions::account_revoke))
+        .route("/account/tokens", get(routes::api_tokens::list_tokens))
+        .route("/account/tokens/create", post(routes::api_tokens::create_token))
+        .route("/account/tokens/{id}/revoke", post(routes::api_tokens::revoke_token))
         .nest_service("/static", ServeDir::new("static"))
-        .layer(middleware::from_fn(auth_middleware))
+        .layer(middleware::from_fn_with_state(app_state.clone(), auth_middleware))
+        .layer(middleware::from_fn_with_state(app_state.clone(), routes::csrf::verify))
         .layer(session_layer)
         .with_state(app_state);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
 }
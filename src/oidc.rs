@@ -0,0 +1,199 @@
+//! OpenID Connect の認可コードフロー（PKCE 付き）
+//!
+//! `begin` で認可リクエストの URL と、コールバックで照合する値（`OidcFlow`）を作る。
+//! `OidcFlow` は呼び出し側がセッションに保存し、コールバックで `state` を照合してから
+//! `finish` で認可コードを IDトークンに交換する。IDトークンは発行者の JWKS で署名を検証し、
+//! `iss`・`aud`・`exp`・`nonce` を確認する。
+
+use base64::Engine;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, jwk::JwkSet};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use crate::config::OidcConfig;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+// IDトークンの署名に受け入れるアルゴリズム（共有鍵の HS256 などは受け入れない）
+const ALLOWED_ALGORITHMS: [Algorithm; 7] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// 認可リクエストからコールバックまでの間に保存しておく値
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcFlow {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+/// 検証済みの IDトークン
+#[derive(Debug, Clone)]
+pub struct VerifiedIdToken {
+    /// ディスカバリーで確認した発行者（ユーザーの対応付けに使う）
+    pub issuer: String,
+    pub claims: Map<String, Value>,
+}
+
+/// ディスカバリー（`/.well-known/openid-configuration`）で取得する発行者の情報
+#[derive(Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+fn http_client() -> anyhow::Result<reqwest::Client> {
+    Ok(reqwest::Client::builder().timeout(HTTP_TIMEOUT).build()?)
+}
+
+async fn discover(http: &reqwest::Client, config: &OidcConfig) -> anyhow::Result<ProviderMetadata> {
+    let url = format!("{}/.well-known/openid-configuration", config.issuer_url);
+    let metadata: ProviderMetadata = http.get(&url).send().await?.error_for_status()?.json().await?;
+
+    // 別の発行者の情報を返された場合は使わない
+    if metadata.issuer.trim_end_matches('/') != config.issuer_url {
+        anyhow::bail!("issuer が一致しません: {}", metadata.issuer);
+    }
+    Ok(metadata)
+}
+
+/// 認可コードを IDトークンに交換する
+async fn exchange_code(
+    http: &reqwest::Client,
+    config: &OidcConfig,
+    metadata: &ProviderMetadata,
+    code: &str,
+    code_verifier: &str,
+) -> anyhow::Result<String> {
+    let mut params = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", config.redirect_url.as_str()),
+        ("client_id", config.client_id.as_str()),
+        ("code_verifier", code_verifier),
+    ];
+    if let Some(client_secret) = &config.client_secret {
+        params.push(("client_secret", client_secret.as_str()));
+    }
+
+    let response = http.post(&metadata.token_endpoint).form(&params).send().await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        anyhow::bail!("トークンエンドポイントがエラーを返しました: {} {}", status, body);
+    }
+
+    let token: TokenResponse = response.json().await?;
+    token.id_token.ok_or_else(|| anyhow::anyhow!("IDトークンが含まれていません"))
+}
+
+/// IDトークンの署名とクレームを検証し、クレームを返す
+async fn verify_id_token(
+    http: &reqwest::Client,
+    config: &OidcConfig,
+    metadata: &ProviderMetadata,
+    id_token: &str,
+    nonce: &str,
+) -> anyhow::Result<Map<String, Value>> {
+    let header = jsonwebtoken::decode_header(id_token)?;
+    if !ALLOWED_ALGORITHMS.contains(&header.alg) {
+        anyhow::bail!("IDトークンの署名アルゴリズムに対応していません: {:?}", header.alg);
+    }
+
+    let jwks: JwkSet = http.get(&metadata.jwks_uri).send().await?.error_for_status()?.json().await?;
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None => jwks.keys.first(),
+    }
+    .ok_or_else(|| anyhow::anyhow!("IDトークンの署名鍵が見つかりません"))?;
+    let key = DecodingKey::from_jwk(jwk)?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[&config.client_id]);
+    validation.set_issuer(&[&metadata.issuer]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+    let claims = jsonwebtoken::decode::<Map<String, Value>>(id_token, &key, &validation)?.claims;
+
+    // 別の認可リクエストで発行されたトークンの使い回しを防ぐ
+    if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+        anyhow::bail!("nonce が一致しません");
+    }
+    Ok(claims)
+}
+
+/// ディスカバリーを行い、認可エンドポイントの URL とコールバックで照合する値を返す
+pub async fn begin(config: &OidcConfig) -> anyhow::Result<(String, OidcFlow)> {
+    let metadata = discover(&http_client()?, config).await?;
+
+    let flow = OidcFlow {
+        state: random_token(),
+        nonce: random_token(),
+        code_verifier: random_token(),
+    };
+    let query = serde_urlencoded::to_string([
+        ("response_type", "code"),
+        ("client_id", config.client_id.as_str()),
+        ("redirect_uri", config.redirect_url.as_str()),
+        ("scope", config.scopes.as_str()),
+        ("state", flow.state.as_str()),
+        ("nonce", flow.nonce.as_str()),
+        ("code_challenge", pkce_challenge(&flow.code_verifier).as_str()),
+        ("code_challenge_method", "S256"),
+    ])?;
+    let separator = if metadata.authorization_endpoint.contains('?') { '&' } else { '?' };
+
+    Ok((format!("{}{}{}", metadata.authorization_endpoint, separator, query), flow))
+}
+
+/// コールバックで受け取った認可コードを IDトークンに交換して検証する
+///
+/// `state` の照合は呼び出し側でこの関数より前に行う。
+pub async fn finish(config: &OidcConfig, flow: &OidcFlow, code: &str) -> anyhow::Result<VerifiedIdToken> {
+    let http = http_client()?;
+    let metadata = discover(&http, config).await?;
+    let id_token = exchange_code(&http, config, &metadata, code, &flow.code_verifier).await?;
+    let claims = verify_id_token(&http, config, &metadata, &id_token, &flow.nonce).await?;
+    Ok(VerifiedIdToken { issuer: metadata.issuer, claims })
+}
+
+/// クレームの値を文字列の一覧で返す（`.` 区切りでネストしたクレームも指定できる）
+pub fn claim_values(claims: &Map<String, Value>, path: &str) -> Vec<String> {
+    let mut parts = path.split('.');
+    let mut value = parts.next().and_then(|name| claims.get(name));
+    for name in parts {
+        value = value.and_then(|value| value.get(name));
+    }
+
+    match value {
+        Some(Value::String(value)) => vec![value.clone()],
+        Some(Value::Array(values)) => values
+            .iter()
+            .filter_map(|value| value.as_str().map(str::to_string))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
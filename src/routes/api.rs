@@ -0,0 +1,412 @@
+//! REST API（`/api/v1`）
+//!
+//! HTML画面と同じ機能をスクリプトから使えるように JSON で提供する。
+//! 認証はセッションではなく `/account/tokens` で発行した個人APIトークンで行う。
+//!
+//! ```text
+//! curl -H "Authorization: Bearer rdt_..." http://localhost:3000/api/v1/me
+//! ```
+//!
+//! トークンの利用は成功・失敗ともに監査ログ（api_request / api_auth_failed）に記録する。
+//! 必要な権限は HTML画面の `require_permission` と同じく `main.rs` で宣言し、
+//! トークンのロールと発行したユーザーのロールの両方に付与されている権限のみ使える。
+
+use axum::{
+    Json, Router,
+    extract::{FromRequestParts, Path, Query, Request, State},
+    http::{HeaderMap, StatusCode, header, request::Parts},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::{AppState, db};
+use crate::routes::{api_tokens, audit, auth, crypto, sysinfo};
+use crate::routes::context::{self, ClientContext};
+
+// 監査ログ一覧で1回に返す件数（既定値と上限）
+const AUDIT_LOG_DEFAULT_LIMIT: i64 = 100;
+const AUDIT_LOG_MAX_LIMIT: i64 = 1000;
+
+/// APIのエラー（`{"error": "..."}` の JSON で返す）
+#[derive(Debug)]
+pub enum ApiError {
+    Unauthorized,
+    Forbidden(db::Permission),
+    NotFound,
+    BadRequest(String),
+    Internal,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            ApiError::Unauthorized => (StatusCode::UNAUTHORIZED, "invalid or missing API token".to_string()),
+            ApiError::Forbidden(permission) => {
+                (StatusCode::FORBIDDEN, format!("permission '{}' is required", permission.as_str()))
+            }
+            ApiError::NotFound => (StatusCode::NOT_FOUND, "not found".to_string()),
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            ApiError::Internal => (StatusCode::INTERNAL_SERVER_ERROR, "internal server error".to_string()),
+        };
+
+        let mut response = (status, Json(serde_json::json!({ "error": message }))).into_response();
+        if status == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        eprintln!("APIのデータベースエラー: {:?}", e);
+        ApiError::Internal
+    }
+}
+
+/// APIトークンで認証されたユーザー
+///
+/// `authenticate` ミドルウェアがリクエストの Extension に入れる。
+#[derive(Clone)]
+pub struct ApiCaller {
+    pub user: db::User,
+    pub token: db::ApiToken,
+}
+
+impl ApiCaller {
+    /// トークンのロールとユーザーのロールの両方に付与されているか（確認できない場合は拒否）
+    pub async fn has_permission(&self, db: &sqlx::MySqlPool, permission: db::Permission) -> bool {
+        auth::has_permission(db, &self.user, permission).await
+            && matches!(db::role_has_permission(db, &self.token.role, permission).await, Ok(true))
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ApiCaller {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<ApiCaller>()
+            .cloned()
+            .ok_or(ApiError::Unauthorized)
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+/// `Authorization: Bearer` のトークンを検証するミドルウェア
+pub async fn authenticate(
+    State(state): State<AppState>,
+    ClientContext(client): ClientContext,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let resource = request.uri().path().to_string();
+    let Some(raw_token) = bearer_token(request.headers()) else {
+        return Err(ApiError::Unauthorized);
+    };
+
+    let token = db::get_active_user_api_token_by_hash(&state.db, &api_tokens::hash_token(raw_token)).await?;
+    let Some(token) = token else {
+        // 推測や漏洩したトークンの試行を追えるよう、未知のトークンや無効化されたユーザーのトークンも記録する
+        let _ = db::create_audit_log(
+            &state.db,
+            &state.audit_events,
+            &client,
+            None,
+            "anonymous",
+            "api_auth_failed",
+            Some(&resource),
+            Some("Rejected unknown API token or token of a disabled or deleted user"),
+        ).await;
+        return Err(ApiError::Unauthorized);
+    };
+
+    // 無効化されたユーザーのトークンは上のクエリで除かれるため、ここでは失効だけを確認する
+    let Some(user) = db::get_user_by_id(&state.db, token.user_id).await? else {
+        return Err(ApiError::Unauthorized);
+    };
+    if token.is_revoked() {
+        let _ = db::create_audit_log(
+            &state.db,
+            &state.audit_events,
+            &client,
+            Some(user.id),
+            &user.username,
+            "api_auth_failed",
+            Some(&resource),
+            Some(&format!("Rejected revoked API token '{}' (id: {})", token.name, token.id)),
+        ).await;
+        return Err(ApiError::Unauthorized);
+    }
+
+    db::touch_api_token(&state.db, token.id).await?;
+
+    // トークンの利用を監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(user.id),
+        &user.username,
+        "api_request",
+        Some(&resource),
+        Some(&format!("{} {} via API token '{}' (id: {})", request.method(), resource, token.name, token.id)),
+    ).await;
+
+    request.extensions_mut().insert(ApiCaller { user, token });
+    Ok(next.run(request).await)
+}
+
+#[derive(Clone)]
+pub struct RequiredApiPermission {
+    state: AppState,
+    permission: db::Permission,
+}
+
+pub trait RequireApiPermissionExt {
+    /// このルーターに登録済みのすべてのAPIルートに権限チェックを追加する
+    fn require_api_permission(self, state: &AppState, permission: db::Permission) -> Self;
+}
+
+impl RequireApiPermissionExt for Router<AppState> {
+    fn require_api_permission(self, state: &AppState, permission: db::Permission) -> Self {
+        let required = RequiredApiPermission {
+            state: state.clone(),
+            permission,
+        };
+        self.route_layer(middleware::from_fn_with_state(required, authorize))
+    }
+}
+
+async fn authorize(
+    State(required): State<RequiredApiPermission>,
+    caller: ApiCaller,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let RequiredApiPermission { state, permission } = required;
+
+    if !caller.has_permission(&state.db, permission).await {
+        let (parts, _) = request.into_parts();
+        let client = context::client_info(&parts, &state);
+        let resource = parts.uri.path();
+
+        // 権限エラーを監査ログに記録
+        let _ = db::create_audit_log(
+            &state.db,
+            &state.audit_events,
+            &client,
+            Some(caller.user.id),
+            &caller.user.username,
+            "access_denied",
+            Some(resource),
+            Some(&format!(
+                "Attempted {} {} via API token '{}' (id: {}) without permission '{}'",
+                parts.method,
+                resource,
+                caller.token.name,
+                caller.token.id,
+                permission.as_str()
+            )),
+        ).await;
+
+        return Err(ApiError::Forbidden(permission));
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// API で返すユーザー情報（パスワードハッシュは含めない）
+#[derive(Serialize)]
+pub struct ApiUser {
+    pub id: i32,
+    pub username: String,
+    pub role: String,
+    pub disabled_at: Option<DateTime<Utc>>,
+}
+
+impl From<db::User> for ApiUser {
+    fn from(user: db::User) -> Self {
+        Self {
+            id: user.id,
+            role: user.role().to_string(),
+            username: user.username,
+            disabled_at: user.disabled_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct MeResponse {
+    pub user: ApiUser,
+    pub token: db::ApiToken,
+    /// このトークンで使える権限
+    pub permissions: Vec<&'static str>,
+}
+
+pub async fn me(
+    State(state): State<AppState>,
+    caller: ApiCaller,
+) -> Result<Json<MeResponse>, ApiError> {
+    let mut permissions = Vec::new();
+    for permission in db::Permission::all() {
+        if caller.has_permission(&state.db, permission).await {
+            permissions.push(permission.as_str());
+        }
+    }
+
+    Ok(Json(MeResponse {
+        user: caller.user.into(),
+        token: caller.token,
+        permissions,
+    }))
+}
+
+pub async fn list_users(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ApiUser>>, ApiError> {
+    let users = db::list_users(&state.db).await?;
+    Ok(Json(users.into_iter().map(|user| user.into()).collect()))
+}
+
+pub async fn get_user(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<Json<ApiUser>, ApiError> {
+    match db::get_user_by_id(&state.db, id).await? {
+        Some(user) => Ok(Json(user.into())),
+        None => Err(ApiError::NotFound),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LimitQuery {
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct AuditLogsResponse {
+    pub logs: Vec<db::AuditLog>,
+    /// さらに古いログがある場合、次のページを取得するための `before` の値
+    pub next_before: Option<i32>,
+}
+
+/// 監査ログを新しい順に返す（条件は `/audit` 画面と同じクエリパラメータで指定する）
+pub async fn list_audit_logs(
+    State(state): State<AppState>,
+    Query(query): Query<audit::AuditLogQuery>,
+    Query(LimitQuery { limit }): Query<LimitQuery>,
+) -> Result<Json<AuditLogsResponse>, ApiError> {
+    let filter = query.filter().map_err(ApiError::BadRequest)?;
+    let limit = limit.unwrap_or(AUDIT_LOG_DEFAULT_LIMIT).clamp(1, AUDIT_LOG_MAX_LIMIT);
+    let cursor = match query.before {
+        Some(id) => db::AuditLogCursor::Before(id),
+        None => db::AuditLogCursor::Latest,
+    };
+
+    // 次のページがあるか判定するために1件多く取得する
+    let mut logs = db::search_audit_logs(&state.db, &filter, cursor, limit + 1).await?;
+    let has_more = logs.len() as i64 > limit;
+    if has_more {
+        logs.pop();
+    }
+    let next_before = if has_more { logs.last().map(|log| log.id) } else { None };
+
+    Ok(Json(AuditLogsResponse { logs, next_before }))
+}
+
+pub async fn sysinfo() -> Json<sysinfo::SysInfoData> {
+    Json(sysinfo::snapshot())
+}
+
+#[derive(Deserialize)]
+pub struct EncryptRequest {
+    plaintext: String,
+    password: String,
+}
+
+#[derive(Serialize)]
+pub struct EncryptResponse {
+    pub ciphertext: String,
+}
+
+#[derive(Deserialize)]
+pub struct DecryptRequest {
+    ciphertext: String,
+    password: String,
+}
+
+#[derive(Serialize)]
+pub struct DecryptResponse {
+    pub plaintext: String,
+}
+
+pub async fn encrypt(
+    State(state): State<AppState>,
+    caller: ApiCaller,
+    ClientContext(client): ClientContext,
+    Json(request): Json<EncryptRequest>,
+) -> Result<Json<EncryptResponse>, ApiError> {
+    let plaintext_len = request.plaintext.len();
+    let result = crypto::blocking(move || crypto::encrypt_string(&request.plaintext, &request.password)).await;
+    let (action, details) = match &result {
+        Ok(_) => ("encrypt", format!("Encrypted text (length: {})", plaintext_len)),
+        Err(e) => ("encrypt_failed", format!("Encryption failed: {}", e)),
+    };
+
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(caller.user.id),
+        &caller.user.username,
+        action,
+        Some("/api/v1/crypto/encrypt"),
+        Some(&details),
+    ).await;
+
+    match result {
+        Ok(ciphertext) => Ok(Json(EncryptResponse { ciphertext })),
+        Err(e) => Err(ApiError::BadRequest(e.to_string())),
+    }
+}
+
+pub async fn decrypt(
+    State(state): State<AppState>,
+    caller: ApiCaller,
+    ClientContext(client): ClientContext,
+    Json(request): Json<DecryptRequest>,
+) -> Result<Json<DecryptResponse>, ApiError> {
+    let result = crypto::blocking(move || crypto::decrypt_string(&request.ciphertext, &request.password)).await;
+    let (action, details) = match &result {
+        Ok(plaintext) => ("decrypt", format!("Decrypted text (length: {})", plaintext.len())),
+        Err(e) => ("decrypt_failed", format!("Decryption failed: {}", e)),
+    };
+
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(caller.user.id),
+        &caller.user.username,
+        action,
+        Some("/api/v1/crypto/decrypt"),
+        Some(&details),
+    ).await;
+
+    match result {
+        Ok(plaintext) => Ok(Json(DecryptResponse { plaintext })),
+        Err(e) => Err(ApiError::BadRequest(e.to_string())),
+    }
+}
@@ -0,0 +1,214 @@
+use askama::Template;
+use axum::{
+    extract::{Form, Path, State},
+    response::{Html, IntoResponse, Redirect},
+};
+use base64::Engine;
+use chrono::{DateTime, Local, Utc};
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use crate::{AppState, db};
+use crate::routes::context::{ClientContext, CurrentUser};
+
+// トークンであることを見分けるための接頭辞（シークレットスキャナーなどで検出しやすくする）
+const TOKEN_PREFIX: &str = "rdt_";
+// 一覧に表示する先頭部分の長さ（接頭辞を含む）
+const DISPLAY_PREFIX_LEN: usize = 12;
+const MAX_TOKEN_NAME_LENGTH: usize = 100;
+
+#[derive(Template)]
+#[template(path = "api_tokens.html")]
+pub struct ApiTokensTemplate {
+    pub tokens: Vec<ApiTokenDisplay>,
+    pub roles: Vec<String>,
+    pub current_role: String,
+    /// 発行直後のトークン（この画面でしか表示しない）
+    pub new_token: Option<String>,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+pub struct ApiTokenDisplay {
+    pub id: i32,
+    pub name: String,
+    pub token_prefix: String,
+    pub role: String,
+    pub created_at: String,
+    pub last_used_at: String,
+    pub revoked: bool,
+}
+
+#[derive(Deserialize)]
+pub struct CreateTokenForm {
+    name: String,
+    role: String,
+}
+
+fn format_local(time: DateTime<Utc>) -> String {
+    let local_time: DateTime<Local> = DateTime::from(time);
+    local_time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+impl From<db::ApiToken> for ApiTokenDisplay {
+    fn from(token: db::ApiToken) -> Self {
+        Self {
+            revoked: token.is_revoked(),
+            id: token.id,
+            name: token.name,
+            token_prefix: token.token_prefix,
+            role: token.role,
+            created_at: format_local(token.created_at),
+            last_used_at: token.last_used_at.map(format_local).unwrap_or_else(|| "-".to_string()),
+        }
+    }
+}
+
+/// APIトークンを保存・照合するためのハッシュ
+///
+/// トークンは十分な長さの乱数なので、パスワードと違い低速なハッシュは使わない。
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+    format!("{}{}", TOKEN_PREFIX, base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes))
+}
+
+async fn render_tokens_page(
+    state: &AppState,
+    current_user: &db::User,
+    new_token: Option<String>,
+    message: Option<String>,
+    error: Option<String>,
+) -> Result<Html<String>, Redirect> {
+    let tokens = db::list_api_tokens(&state.db, current_user.id)
+        .await
+        .map_err(|_| Redirect::to("/"))?;
+
+    let roles = db::list_roles(&state.db)
+        .await
+        .map_err(|_| Redirect::to("/"))?;
+
+    let template = ApiTokensTemplate {
+        tokens: tokens.into_iter().map(|token| token.into()).collect(),
+        roles: roles.into_iter().map(|role| role.name).collect(),
+        current_role: current_user.role().to_string(),
+        new_token,
+        message,
+        error,
+    };
+
+    match template.render() {
+        Ok(html) => Ok(Html(html)),
+        Err(_) => Err(Redirect::to("/")),
+    }
+}
+
+pub async fn list_tokens(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+) -> Result<impl IntoResponse, Redirect> {
+    render_tokens_page(&state, &current_user, None, None, None).await
+}
+
+pub async fn create_token(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Form(form): Form<CreateTokenForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let name = form.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_TOKEN_NAME_LENGTH {
+        let error = format!("トークン名は1〜{}文字で入力してください", MAX_TOKEN_NAME_LENGTH);
+        return render_tokens_page(&state, &current_user, None, None, Some(error)).await;
+    }
+    let role = match db::get_role(&state.db, &form.role).await {
+        Ok(Some(role)) => role.name,
+        _ => return render_tokens_page(&state, &current_user, None, None, Some("不正なロールです".into())).await,
+    };
+
+    let token = generate_token();
+    let token_prefix = &token[..DISPLAY_PREFIX_LEN];
+    let created = match db::create_api_token(&state.db, current_user.id, name, &hash_token(&token), token_prefix, &role).await {
+        Ok(created) => created,
+        Err(_) => {
+            return render_tokens_page(&state, &current_user, None, None, Some("データベースエラーが発生しました".into())).await;
+        }
+    };
+
+    // 監査ログに記録（トークン自体は記録しない）
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "api_token_create",
+        Some("/account/tokens/create"),
+        Some(&format!("Created API token '{}' (id: {}, role: {})", created.name, created.id, created.role)),
+    ).await;
+
+    let message = format!("APIトークン '{}' を発行しました。このトークンは再表示できないため、今すぐコピーしてください", created.name);
+    render_tokens_page(&state, &current_user, Some(token), Some(message), None).await
+}
+
+pub async fn revoke_token(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, Redirect> {
+    let resource = format!("/account/tokens/{}/revoke", id);
+
+    match db::revoke_api_token(&state.db, id, current_user.id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return render_tokens_page(&state, &current_user, None, None, Some("トークンが見つかりません".into())).await;
+        }
+        Err(_) => {
+            return render_tokens_page(&state, &current_user, None, None, Some("データベースエラーが発生しました".into())).await;
+        }
+    }
+
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "api_token_revoke",
+        Some(&resource),
+        Some(&format!("Revoked API token (id: {})", id)),
+    ).await;
+
+    render_tokens_page(&state, &current_user, None, Some("APIトークンを失効させました".into()), None).await
+}
+
+/// ユーザーの有効なトークンをすべて失効させる（パスワードのリセットや無効化のとき）
+pub async fn revoke_all(state: &AppState, client: &db::ClientInfo, actor: &db::User, target: &db::User, reason: &str) {
+    let revoked = match db::revoke_user_api_tokens(&state.db, target.id).await {
+        Ok(revoked) => revoked,
+        Err(e) => {
+            eprintln!("APIトークンの失効に失敗: {} {:?}", target.username, e);
+            return;
+        }
+    };
+    if revoked == 0 {
+        return;
+    }
+
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        client,
+        Some(actor.id),
+        &actor.username,
+        "api_token_revoke_all",
+        None,
+        Some(&format!("Revoked {} API token(s) of '{}' ({})", revoked, target.username, reason)),
+    ).await;
+}
@@ -1,17 +1,117 @@
 use askama::Template;
-use axum::{extract::State, response::{Html, Redirect, IntoResponse}};
-use chrono::{DateTime, Local};
-use tower_sessions::Session;
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::header,
+    response::{Html, IntoResponse, Redirect, Response},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use futures::{Stream, StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
 use crate::{AppState, db};
-use crate::routes::auth;
+use crate::routes::context::{ClientContext, CurrentUser};
+
+const PAGE_SIZE: i64 = 100;
+// エクスポート時に1回のクエリで読み出す件数
+const EXPORT_BATCH_SIZE: i64 = 1000;
+// 監査ログ画面に表示するアーカイブの件数
+const ARCHIVE_DISPLAY_LIMIT: i64 = 10;
+// ライブ表示で最初に選択しておくアクション
+const LIVE_DEFAULT_ACTIONS: [&str; 2] = ["login_failed", "access_denied"];
+// ライブ表示の画面に残しておく件数
+const LIVE_MAX_ROWS: usize = 200;
 
 #[derive(Template)]
 #[template(path = "audit.html")]
 pub struct AuditLogsTemplate {
     pub logs: Vec<AuditLogDisplay>,
+    pub actions: Vec<String>,
+    pub query: AuditLogQuery,
+    pub filter_query: String,
+    pub error: Option<String>,
+    pub page_size: i64,
+    pub has_newer: bool,
+    pub has_older: bool,
+    pub newest_id: i32,
+    pub oldest_id: i32,
+    pub retention_days: Option<i64>,
+    pub archive_dir: String,
+    pub archives: Vec<AuditArchiveDisplay>,
+}
+
+/// 監査ログ画面のクエリパラメータ
+///
+/// 空文字列の項目は条件なしとして扱う。日付は `YYYY-MM-DD`（ローカル時刻）で、
+/// `to` はその日の終わりまでを含む。
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AuditLogQuery {
+    pub username: String,
+    pub action: String,
+    pub resource: String,
+    pub from: String,
+    pub to: String,
+    pub q: String,
+    #[serde(skip_serializing)]
+    pub before: Option<i32>,
+    #[serde(skip_serializing)]
+    pub after: Option<i32>,
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+fn parse_local_date(value: &str, label: &str) -> Result<Option<NaiveDate>, String> {
+    match non_empty(value) {
+        Some(value) => NaiveDate::parse_from_str(&value, "%Y-%m-%d")
+            .map(Some)
+            .map_err(|_| format!("{}の日付の形式が正しくありません: {}", label, value)),
+        None => Ok(None),
+    }
+}
+
+fn local_midnight_utc(date: NaiveDate) -> Option<DateTime<Utc>> {
+    date.and_hms_opt(0, 0, 0)?
+        .and_local_timezone(Local)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
 }
 
-#[derive(Clone)]
+impl AuditLogQuery {
+    pub fn filter(&self) -> Result<db::AuditLogFilter, String> {
+        let from = parse_local_date(&self.from, "開始日")?;
+        let to = parse_local_date(&self.to, "終了日")?;
+
+        Ok(db::AuditLogFilter {
+            username: non_empty(&self.username),
+            action: non_empty(&self.action),
+            resource: non_empty(&self.resource),
+            from: from.and_then(local_midnight_utc),
+            to: to.and_then(|date| date.succ_opt()).and_then(local_midnight_utc),
+            search: non_empty(&self.q),
+        })
+    }
+
+    pub fn cursor(&self) -> db::AuditLogCursor {
+        match (self.before, self.after) {
+            (Some(id), _) => db::AuditLogCursor::Before(id),
+            (None, Some(id)) => db::AuditLogCursor::After(id),
+            (None, None) => db::AuditLogCursor::Latest,
+        }
+    }
+
+    /// ページ位置を除いた検索条件のクエリ文字列
+    pub fn filter_query(&self) -> String {
+        serde_urlencoded::to_string(self).unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Serialize)]
 pub struct AuditLogDisplay {
     pub id: i32,
     pub username: String,
@@ -19,9 +119,39 @@ pub struct AuditLogDisplay {
     pub resource: String,
     pub details: String,
     pub ip_address: String,
+    pub user_agent: String,
+    pub created_at: String,
+}
+
+/// アーカイブ済みの監査ログの保存先（表示用）
+pub struct AuditArchiveDisplay {
+    pub file_name: String,
+    pub first_id: i32,
+    pub last_id: i32,
+    pub row_count: i32,
+    pub period: String,
     pub created_at: String,
 }
 
+impl From<db::AuditArchive> for AuditArchiveDisplay {
+    fn from(archive: db::AuditArchive) -> Self {
+        let format_date = |time: Option<DateTime<Utc>>| {
+            time.map(|time| DateTime::<Local>::from(time).format("%Y-%m-%d").to_string())
+                .unwrap_or_default()
+        };
+        let created_at: DateTime<Local> = DateTime::from(archive.created_at);
+
+        Self {
+            period: format!("{} 〜 {}", format_date(archive.oldest_at), format_date(archive.newest_at)),
+            file_name: archive.file_name,
+            first_id: archive.first_id,
+            last_id: archive.last_id,
+            row_count: archive.row_count,
+            created_at: created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+        }
+    }
+}
+
 impl From<db::AuditLog> for AuditLogDisplay {
     fn from(log: db::AuditLog) -> Self {
         let local_time: DateTime<Local> = DateTime::from(log.created_at);
@@ -32,6 +162,7 @@ impl From<db::AuditLog> for AuditLogDisplay {
             resource: log.resource.unwrap_or_else(|| "-".to_string()),
             details: log.details.unwrap_or_else(|| "-".to_string()),
             ip_address: log.ip_address.unwrap_or_else(|| "-".to_string()),
+            user_agent: log.user_agent.unwrap_or_else(|| "-".to_string()),
             created_at: local_time.format("%Y-%m-%d %H:%M:%S").to_string(),
         }
     }
@@ -39,42 +170,63 @@ impl From<db::AuditLog> for AuditLogDisplay {
 
 pub async fn list_audit_logs(
     State(state): State<AppState>,
-    session: Session,
+    Query(query): Query<AuditLogQuery>,
 ) -> Result<impl IntoResponse, impl IntoResponse> {
-    // 現在のユーザーを取得
-    let current_user = match auth::get_current_user(&session, &state.db).await {
-        Some(user) => user,
-        None => return Err(Redirect::to("/login")),
+    let (filter, error) = match query.filter() {
+        Ok(filter) => (filter, None),
+        Err(error) => (db::AuditLogFilter::default(), Some(error)),
     };
+    let cursor = query.cursor();
 
-    // Admin権限をチェック
-    if !current_user.role().can_access_audit() {
-        // 権限エラーを監査ログに記録
-        let _ = db::create_audit_log(
-            &state.db,
-            Some(current_user.id),
-            &current_user.username,
-            "access_denied",
-            Some("/audit"),
-            Some("Attempted to access audit logs without permission"),
-            None,
-            None,
-        ).await;
+    // 次のページがあるか判定するために1件多く取得する
+    let mut logs = db::search_audit_logs(&state.db, &filter, cursor, PAGE_SIZE + 1)
+        .await
+        .map_err(|_| Redirect::to("/"))?;
 
-        return Err(Redirect::to("/"));
+    let has_more = logs.len() as i64 > PAGE_SIZE;
+    if has_more {
+        match cursor {
+            db::AuditLogCursor::After(_) => {
+                logs.remove(0);
+            }
+            _ => {
+                logs.pop();
+            }
+        }
     }
+    let (has_newer, has_older) = match cursor {
+        db::AuditLogCursor::Latest => (false, has_more),
+        db::AuditLogCursor::Before(_) => (true, has_more),
+        db::AuditLogCursor::After(_) => (has_more, true),
+    };
 
-    let logs = db::list_audit_logs(&state.db, 100)
-        .await
-        .map_err(|_| Redirect::to("/"))?;
+    let newest_id = logs.first().map(|log| log.id).unwrap_or(0);
+    let oldest_id = logs.last().map(|log| log.id).unwrap_or(0);
 
     let logs_display: Vec<AuditLogDisplay> = logs
         .into_iter()
         .map(|log| log.into())
         .collect();
 
+    let actions = db::list_audit_actions(&state.db).await.unwrap_or_default();
+    let archives = db::list_audit_archives(&state.db, ARCHIVE_DISPLAY_LIMIT)
+        .await
+        .unwrap_or_default();
+
     let template = AuditLogsTemplate {
         logs: logs_display,
+        actions,
+        filter_query: query.filter_query(),
+        query,
+        error,
+        page_size: PAGE_SIZE,
+        has_newer: has_newer && newest_id != 0,
+        has_older: has_older && oldest_id != 0,
+        newest_id,
+        oldest_id,
+        retention_days: state.audit_retention.retention.map(|retention| retention.num_days()),
+        archive_dir: state.audit_retention.archive_dir.display().to_string(),
+        archives: archives.into_iter().map(|archive| archive.into()).collect(),
     };
 
     match template.render() {
@@ -82,3 +234,458 @@ pub async fn list_audit_logs(
         Err(_) => Err(Redirect::to("/")),
     }
 }
+
+#[derive(Template)]
+#[template(path = "audit_verify.html")]
+pub struct AuditVerifyTemplate {
+    pub checked: u64,
+    pub legacy: u64,
+    pub broken: Option<db::AuditChainBreak>,
+    pub verified_at: String,
+}
+
+pub async fn verify_audit_logs(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+) -> Result<impl IntoResponse, Redirect> {
+    let report = db::verify_audit_chain(&state.db)
+        .await
+        .map_err(|_| Redirect::to("/audit"))?;
+
+    // 監査ログに記録
+    let details = match &report.broken {
+        Some(broken) => format!("Audit chain broken at entry {}: {}", broken.id, broken.reason),
+        None => format!("Audit chain intact ({} entries checked)", report.checked),
+    };
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "audit_verify",
+        Some("/audit/verify"),
+        Some(&details),
+    ).await;
+
+    let template = AuditVerifyTemplate {
+        checked: report.checked,
+        legacy: report.legacy,
+        broken: report.broken,
+        verified_at: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+    };
+
+    match template.render() {
+        Ok(html) => Ok(Html(html)),
+        Err(_) => Err(Redirect::to("/audit")),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "audit_live.html")]
+pub struct AuditLiveTemplate {
+    pub actions: Vec<LiveActionOption>,
+    pub max_rows: usize,
+}
+
+pub struct LiveActionOption {
+    pub name: String,
+    pub checked: bool,
+}
+
+/// `/audit/live` のクエリパラメータ
+///
+/// `action` はカンマ区切りで複数指定できる。空の項目は条件なしとして扱う。
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AuditLiveQuery {
+    pub action: String,
+    pub username: String,
+}
+
+#[derive(Debug, Clone)]
+struct AuditLiveFilter {
+    actions: Vec<String>,
+    username: Option<String>,
+}
+
+impl AuditLiveFilter {
+    fn matches(&self, log: &db::AuditLog) -> bool {
+        (self.actions.is_empty() || self.actions.contains(&log.action))
+            && self.username.as_ref().is_none_or(|username| username == &log.username)
+    }
+}
+
+impl From<AuditLiveQuery> for AuditLiveFilter {
+    fn from(query: AuditLiveQuery) -> Self {
+        Self {
+            actions: query
+                .action
+                .split(',')
+                .filter_map(non_empty)
+                .collect(),
+            username: non_empty(&query.username),
+        }
+    }
+}
+
+pub async fn live_page(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Redirect> {
+    let mut actions = db::list_audit_actions(&state.db).await.unwrap_or_default();
+    for action in LIVE_DEFAULT_ACTIONS {
+        if !actions.iter().any(|name| name == action) {
+            actions.push(action.to_string());
+        }
+    }
+    actions.sort();
+
+    let template = AuditLiveTemplate {
+        actions: actions
+            .into_iter()
+            .map(|name| LiveActionOption {
+                checked: LIVE_DEFAULT_ACTIONS.contains(&name.as_str()),
+                name,
+            })
+            .collect(),
+        max_rows: LIVE_MAX_ROWS,
+    };
+
+    match template.render() {
+        Ok(html) => Ok(Html(html)),
+        Err(_) => Err(Redirect::to("/audit")),
+    }
+}
+
+/// 書き込まれた監査ログをリアルタイムに配信する（SSE）
+///
+/// 購読者の処理が追いつかずログを取りこぼした場合は `lagged` イベントで件数を通知する。
+pub async fn live(
+    State(state): State<AppState>,
+    Query(query): Query<AuditLiveQuery>,
+) -> impl IntoResponse {
+    let filter = AuditLiveFilter::from(query);
+    let receiver = state.audit_events.subscribe();
+
+    let stream = stream::unfold(receiver, move |mut receiver| {
+        let filter = filter.clone();
+        async move {
+            loop {
+                let event = match receiver.recv().await {
+                    Ok(log) if filter.matches(&log) => Event::default()
+                        .event("audit")
+                        .id(log.id.to_string())
+                        .json_data(AuditLogDisplay::from(log)),
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => {
+                        Ok(Event::default().event("lagged").data(skipped.to_string()))
+                    }
+                    Err(RecvError::Closed) => return None,
+                };
+                return Some((event, receiver));
+            }
+        }
+    });
+
+    Sse::new(stream).keep_alive(
+        KeepAlive::new()
+            .interval(Duration::from_secs(30))
+            .text("keep-alive-text"),
+    )
+}
+
+#[derive(Clone, Copy)]
+enum ExportFormat {
+    Csv,
+    Ndjson,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    fn header(self) -> Option<Bytes> {
+        match self {
+            ExportFormat::Csv => Some(Bytes::from_static(
+                b"id,user_id,username,action,resource,details,ip_address,user_agent,created_at\n",
+            )),
+            ExportFormat::Ndjson => None,
+        }
+    }
+
+    fn encode(self, logs: &[db::AuditLog]) -> std::io::Result<Bytes> {
+        match self {
+            ExportFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(Vec::new());
+                for log in logs {
+                    writer
+                        .write_record([
+                            log.id.to_string(),
+                            log.user_id.map(|id| id.to_string()).unwrap_or_default(),
+                            csv_safe(&log.username),
+                            csv_safe(&log.action),
+                            csv_safe(log.resource.as_deref().unwrap_or_default()),
+                            csv_safe(log.details.as_deref().unwrap_or_default()),
+                            csv_safe(log.ip_address.as_deref().unwrap_or_default()),
+                            csv_safe(log.user_agent.as_deref().unwrap_or_default()),
+                            log.created_at.to_rfc3339(),
+                        ])
+                        .map_err(std::io::Error::other)?;
+                }
+                writer
+                    .into_inner()
+                    .map(Bytes::from)
+                    .map_err(|e| std::io::Error::other(e.to_string()))
+            }
+            ExportFormat::Ndjson => {
+                let mut buffer = Vec::new();
+                for log in logs {
+                    serde_json::to_writer(&mut buffer, log)?;
+                    buffer.push(b'\n');
+                }
+                Ok(Bytes::from(buffer))
+            }
+        }
+    }
+}
+
+// 表計算ソフトで数式として解釈されないように先頭に ' を付ける
+fn csv_safe(value: &str) -> String {
+    if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// 条件に一致する監査ログを EXPORT_BATCH_SIZE 件ずつ読み出してエンコードするストリーム
+fn export_stream(
+    pool: sqlx::MySqlPool,
+    filter: db::AuditLogFilter,
+    format: ExportFormat,
+) -> impl Stream<Item = std::io::Result<Bytes>> {
+    let header = stream::iter(format.header().map(Ok));
+
+    let rows = stream::try_unfold(Some(db::AuditLogCursor::Latest), move |cursor| {
+        let pool = pool.clone();
+        let filter = filter.clone();
+        async move {
+            let Some(cursor) = cursor else {
+                return Ok(None);
+            };
+
+            let logs = db::search_audit_logs(&pool, &filter, cursor, EXPORT_BATCH_SIZE)
+                .await
+                .map_err(std::io::Error::other)?;
+            let Some(last) = logs.last() else {
+                return Ok(None);
+            };
+
+            let next = if (logs.len() as i64) < EXPORT_BATCH_SIZE {
+                None
+            } else {
+                Some(db::AuditLogCursor::Before(last.id))
+            };
+
+            Ok(Some((format.encode(&logs)?, next)))
+        }
+    });
+
+    header.chain(rows)
+}
+
+async fn export_audit_logs(
+    state: AppState,
+    client: db::ClientInfo,
+    current_user: db::User,
+    query: AuditLogQuery,
+    format: ExportFormat,
+) -> Result<Response, Redirect> {
+    let resource = format!("/audit/export.{}", format.extension());
+
+    let filter = query.filter().map_err(|_| Redirect::to("/audit"))?;
+
+    // 監査ログに記録
+    let filter_query = query.filter_query();
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "audit_export",
+        Some(&resource),
+        Some(&format!(
+            "Exported audit logs as {} (filter: {})",
+            format.extension(),
+            if filter_query.is_empty() { "none" } else { &filter_query },
+        )),
+    ).await;
+
+    let filename = format!(
+        "audit_logs_{}.{}",
+        Local::now().format("%Y%m%d%H%M%S"),
+        format.extension(),
+    );
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        Body::from_stream(export_stream(state.db.clone(), filter, format)),
+    )
+        .into_response())
+}
+
+pub async fn export_csv(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Query(query): Query<AuditLogQuery>,
+) -> Result<Response, Redirect> {
+    export_audit_logs(state, client, current_user, query, ExportFormat::Csv).await
+}
+
+pub async fn export_ndjson(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Query(query): Query<AuditLogQuery>,
+) -> Result<Response, Redirect> {
+    export_audit_logs(state, client, current_user, query, ExportFormat::Ndjson).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveTime, TimeZone};
+
+    fn audit_log(action: &str, username: &str) -> db::AuditLog {
+        db::AuditLog {
+            id: 1,
+            user_id: Some(1),
+            username: username.into(),
+            action: action.into(),
+            resource: None,
+            details: None,
+            ip_address: None,
+            user_agent: None,
+            created_at: Utc::now(),
+            prev_hash: None,
+            entry_hash: None,
+        }
+    }
+
+    #[test]
+    fn csv_safe_escapes_formula_prefixes() {
+        for value in ["=SUM(A1:A2)", "+1", "-1", "@cmd", "\tdata", "\rdata"] {
+            assert_eq!(csv_safe(value), format!("'{}", value));
+        }
+    }
+
+    #[test]
+    fn csv_safe_keeps_ordinary_values() {
+        for value in ["", "alice", "login_failed", "/users/create", "a=b", "192.0.2.1"] {
+            assert_eq!(csv_safe(value), value);
+        }
+    }
+
+    #[test]
+    fn filter_treats_blank_fields_as_no_condition() {
+        let query = AuditLogQuery {
+            username: "  ".into(),
+            action: " login ".into(),
+            ..Default::default()
+        };
+        let filter = query.filter().unwrap();
+        assert_eq!(filter.username, None);
+        assert_eq!(filter.action.as_deref(), Some("login"));
+        assert_eq!(filter.resource, None);
+        assert_eq!(filter.search, None);
+        assert_eq!(filter.from, None);
+        assert_eq!(filter.to, None);
+    }
+
+    #[test]
+    fn filter_converts_local_dates_to_utc_range() {
+        let query = AuditLogQuery {
+            from: "2024-01-15".into(),
+            to: "2024-01-16".into(),
+            ..Default::default()
+        };
+        let filter = query.filter().unwrap();
+        let midnight = |day| {
+            Local
+                .from_local_datetime(&NaiveDate::from_ymd_opt(2024, 1, day).unwrap().and_time(NaiveTime::MIN))
+                .earliest()
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+
+        // 開始日はその日のローカル時刻の 0 時から、終了日は翌日の 0 時の手前まで
+        let from = filter.from.unwrap();
+        let to = filter.to.unwrap();
+        assert_eq!(from, midnight(15));
+        assert_eq!(to, midnight(17));
+        assert_eq!(to - from, Duration::days(2));
+        assert_eq!(DateTime::<Local>::from(from).time(), NaiveTime::MIN);
+    }
+
+    #[test]
+    fn filter_rejects_malformed_dates() {
+        for date in ["2024/01/15", "2024-13-01", "yesterday"] {
+            let query = AuditLogQuery { from: date.into(), ..Default::default() };
+            assert!(query.filter().is_err(), "{}", date);
+            let query = AuditLogQuery { to: date.into(), ..Default::default() };
+            assert!(query.filter().is_err(), "{}", date);
+        }
+    }
+
+    #[test]
+    fn live_filter_parses_comma_separated_actions() {
+        let filter = AuditLiveFilter::from(AuditLiveQuery {
+            action: "login_failed, access_denied,,".into(),
+            username: " ".into(),
+        });
+        assert_eq!(filter.actions, ["login_failed", "access_denied"]);
+        assert_eq!(filter.username, None);
+
+        assert!(filter.matches(&audit_log("login_failed", "alice")));
+        assert!(filter.matches(&audit_log("access_denied", "bob")));
+        assert!(!filter.matches(&audit_log("login", "alice")));
+    }
+
+    #[test]
+    fn live_filter_matches_username_exactly() {
+        let filter = AuditLiveFilter::from(AuditLiveQuery {
+            action: String::new(),
+            username: "alice".into(),
+        });
+        assert!(filter.actions.is_empty());
+        assert!(filter.matches(&audit_log("login", "alice")));
+        assert!(!filter.matches(&audit_log("login", "alice2")));
+        assert!(!filter.matches(&audit_log("login", "Alice")));
+    }
+
+    #[test]
+    fn empty_live_filter_matches_everything() {
+        let filter = AuditLiveFilter::from(AuditLiveQuery::default());
+        assert!(filter.matches(&audit_log("login", "alice")));
+        assert!(filter.matches(&audit_log("vault_read", "bob")));
+    }
+}
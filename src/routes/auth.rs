@@ -5,15 +5,40 @@ use axum::{Form, response::Redirect, extract::State};
 use password_hash::{PasswordHash, PasswordVerifier};
 use serde::Deserialize;
 use tower_sessions::Session;
+use rust_dashboard::config::{AuthBackend, PasswordHashing};
+use rust_dashboard::{ldap, session_store};
 use crate::AppState;
 use crate::db;
+use crate::routes::{csrf, lockouts, sessions, two_factor};
+use crate::routes::context::{ClientContext, CurrentUser};
 
 const SESSION_USER_KEY: &str = "user";
+// パスワード確認済みで TOTP 入力待ちのユーザー名
+const SESSION_PENDING_2FA_KEY: &str = "pending_2fa_user_id";
+// 二要素認証の登録が完了するまで他の画面に進めないことを示すフラグ
+const SESSION_2FA_SETUP_KEY: &str = "2fa_setup_required";
+
+pub const DISABLED_ACCOUNT_ERROR: &str = "このアカウントは無効化されています";
 
 #[derive(Template, WebTemplate)]
 #[template(path = "login.html")]
 pub struct LoginTemplate {
     pub error: Option<String>,
+    /// シングルサインオンのボタンの表示名（OIDCが設定されている場合のみ）
+    pub sso_label: Option<String>,
+}
+
+impl LoginTemplate {
+    pub fn new(state: &AppState, error: Option<String>) -> Self {
+        Self {
+            error,
+            sso_label: state.oidc.as_ref().map(|oidc| oidc.label.clone()),
+        }
+    }
+
+    pub fn error(state: &AppState, error: impl Into<String>) -> Self {
+        Self::new(state, Some(error.into()))
+    }
 }
 
 #[derive(Deserialize)]
@@ -22,102 +47,348 @@ pub struct LoginForm {
     password: String,
 }
 
-pub async fn login_page() -> LoginTemplate {
-    LoginTemplate { error: None }
+pub async fn login_page(State(state): State<AppState>) -> LoginTemplate {
+    LoginTemplate::new(&state, None)
 }
 
 pub async fn login(
     State(state): State<AppState>,
+    ClientContext(client): ClientContext,
     session: Session,
     Form(form): Form<LoginForm>,
 ) -> Result<Redirect, LoginTemplate> {
-    // データベースからユーザーを取得
-    let user = match db::get_user_by_username(&state.db, &form.username).await {
-        Ok(Some(user)) => user,
-        Ok(None) => {
-            return Err(LoginTemplate {
-                error: Some("ユーザー名またはパスワードが間違っています".into()),
-            });
+    // アカウントまたは送信元IPがロック中であれば認証を行わない
+    match lockouts::find_active_lockout(&state, &client, &form.username).await {
+        Ok(Some(lockout)) => {
+            eprintln!("ログイン拒否: {} (ロック中: {} {})", form.username, lockout.scope, lockout.subject);
+            let _ = db::create_audit_log(
+                &state.db,
+                &state.audit_events,
+                &client,
+                None,
+                &form.username,
+                "login_blocked",
+                None,
+                Some(&format!("Login attempt rejected while {} '{}' is locked", lockout.scope, lockout.subject)),
+            ).await;
+
+            return Err(LoginTemplate::error(&state, "ログイン失敗が続いたため一時的にロックされています。しばらくしてから再度お試しください"));
         }
+        Ok(None) => {}
         Err(_) => {
-            return Err(LoginTemplate {
-                error: Some("データベースエラーが発生しました".into()),
-            });
+            return Err(LoginTemplate::error(&state, "データベースエラーが発生しました"));
         }
+    }
+
+    // 設定された認証バックエンドでパスワードを検証
+    let user = match authenticate(&state, &client, &form.username, &form.password).await {
+        Ok(user) => user,
+        Err(error) => return Err(LoginTemplate::error(&state, error)),
     };
 
-    // パスワードを検証
-    let is_valid = PasswordHash::new(&user.password_hash)
-        .ok()
-        .map(|hash| {
-            Argon2::default()
-                .verify_password(form.password.as_bytes(), &hash)
-                .is_ok()
-        })
-        .unwrap_or(false);
-
-    if is_valid {
-        // セッションに保存
-        if let Err(e) = session.insert(SESSION_USER_KEY, &form.username).await {
-            eprintln!("セッション保存エラー: {:?}", e);
-            return Err(LoginTemplate {
-                error: Some("セッションエラーが発生しました".into()),
-            });
+    match user {
+        Some(user) => {
+            // 無効化されたユーザーはパスワードが正しくてもログインさせない
+            if user.is_disabled() {
+                eprintln!("ログイン拒否: {} (無効化済み)", user.username);
+                let _ = db::create_audit_log(
+                    &state.db,
+                    &state.audit_events,
+                    &client,
+                    Some(user.id),
+                    &user.username,
+                    "login_blocked",
+                    None,
+                    Some("Login attempt rejected for disabled account"),
+                ).await;
+
+                return Err(LoginTemplate::error(&state, DISABLED_ACCOUNT_ERROR));
+            }
+
+            continue_login(&state, &client, &session, &user)
+                .await
+                .map_err(|error| LoginTemplate::error(&state, error))
         }
+        None => {
+            record_failed_login(&state, &client, &form.username, "Failed login attempt").await;
 
-        eprintln!("ログイン成功: {}", form.username);
+            Err(LoginTemplate::error(&state, "ユーザー名またはパスワードが間違っています"))
+        }
+    }
+}
 
-        // 監査ログに記録
-        let _ = db::create_audit_log(
-            &state.db,
-            Some(user.id),
-            &form.username,
-            "login",
-            None,
-            Some("User logged in successfully"),
-            None,
-            None,
-        ).await;
+/// `AUTH_BACKENDS` の順にパスワードを検証し、最初に認証できたユーザーを返す
+///
+/// どのバックエンドでも認証できなければ `Ok(None)`、認証できず接続エラーなどがあった場合は `Err` を返す。
+async fn authenticate(
+    state: &AppState,
+    client: &db::ClientInfo,
+    username: &str,
+    password: &str,
+) -> Result<Option<db::User>, String> {
+    let mut last_error = None;
+    for backend in &state.auth.backends {
+        let result = match backend {
+            AuthBackend::Local => authenticate_local(state, client, username, password).await,
+            AuthBackend::Ldap => authenticate_ldap(state, client, username, password).await,
+        };
+        match result {
+            Ok(Some(user)) => return Ok(Some(user)),
+            Ok(None) => {}
+            // 障害時でも次のバックエンドでログインできるようにする
+            Err(error) => last_error = Some(error),
+        }
+    }
+
+    match last_error {
+        Some(error) => Err(error),
+        None => Ok(None),
+    }
+}
+
+/// `users.password_hash` の Argon2 ハッシュで検証する
+///
+/// 検証に成功し、保存済みのハッシュが現在のパラメータより弱い場合は作り直して保存する。
+async fn authenticate_local(
+    state: &AppState,
+    client: &db::ClientInfo,
+    username: &str,
+    password: &str,
+) -> Result<Option<db::User>, String> {
+    let user = db::get_user_by_username(&state.db, username)
+        .await
+        .map_err(|_| "データベースエラーが発生しました".to_string())?;
+
+    // 外部の認証基盤のユーザーは PHC形式のハッシュを持たないため常に失敗する
+    let Some(mut user) = user.filter(|user| verify_password(&user.password_hash, password)) else {
+        return Ok(None);
+    };
+
+    if let Some(params) = state.password_hashing.rehash_params(&user.password_hash) {
+        rehash_password(state, client, &mut user, password, params).await;
+    }
+    Ok(Some(user))
+}
+
+/// 現在のパラメータ以上でハッシュを作り直す（失敗してもログインは続ける）
+async fn rehash_password(
+    state: &AppState,
+    client: &db::ClientInfo,
+    user: &mut db::User,
+    password: &str,
+    params: argon2::Params,
+) {
+    let hash = match state.password_hashing.hash_with_params(password, params) {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("パスワードの再ハッシュに失敗: {} {:?}", user.username, e);
+            return;
+        }
+    };
+    // 検証からの間にパスワードが変更されていれば上書きしない
+    match db::replace_password_hash(&state.db, user.id, &user.password_hash, &hash).await {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            eprintln!("パスワードの再ハッシュの保存に失敗: {} {:?}", user.username, e);
+            return;
+        }
+    }
+
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        client,
+        Some(user.id),
+        &user.username,
+        "password_rehash",
+        None,
+        Some(&format!(
+            "Upgraded password hash from {} to {}",
+            PasswordHashing::describe(&user.password_hash),
+            PasswordHashing::describe(&hash)
+        )),
+    ).await;
+    user.password_hash = hash;
+}
+
+/// LDAP ディレクトリへの bind で検証し、ローカルのユーザーを作成・更新する
+async fn authenticate_ldap(
+    state: &AppState,
+    client: &db::ClientInfo,
+    username: &str,
+    password: &str,
+) -> Result<Option<db::User>, String> {
+    let Some(config) = &state.auth.ldap else {
+        return Ok(None);
+    };
+
+    let account = match ldap::authenticate(config, username, password).await {
+        Ok(Some(account)) => account,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            eprintln!("LDAP認証エラー: {} {:?}", username, e);
+            return Err("認証サーバーに接続できませんでした".into());
+        }
+    };
+
+    // DN は大文字小文字を区別しないため小文字にそろえて対応付ける
+    let subject = account.dn.to_lowercase();
+
+    // 同期しない場合は管理者が登録したユーザーのパスワード確認にのみ使う。
+    // ディレクトリのアカウントに対応付け済みのユーザーか、外部認証として（ローカルのパスワードなしで）
+    // 登録されたユーザーに限り、同名のローカルユーザー（初期の admin など）には成り代わらせない
+    if !config.sync_users {
+        const DB_ERROR: &str = "データベースエラーが発生しました";
+        if let Some(user) = db::get_user_by_identity(&state.db, &config.url, &subject).await.map_err(|_| DB_ERROR.to_string())? {
+            let _ = db::touch_user_identity(&state.db, &config.url, &subject).await;
+            return Ok(Some(user));
+        }
+        let user = db::get_user_by_username(&state.db, username)
+            .await
+            .map_err(|_| DB_ERROR.to_string())?;
+        return Ok(match user {
+            Some(user) if user.has_local_password() => {
+                eprintln!("ログイン拒否: {} (ローカルのパスワードを持つユーザーにはLDAPでログインできません)", username);
+                None
+            }
+            user => user,
+        });
+    }
 
-        Ok(Redirect::to("/"))
-    } else {
-        eprintln!("ログイン失敗: {} (パスワード不一致)", form.username);
-        // ログイン失敗も記録
+    let Some(role) = config.role_mapping.resolve(&account.groups) else {
+        eprintln!("ログイン拒否: {} (LDAPグループに対応するロールなし)", username);
         let _ = db::create_audit_log(
             &state.db,
+            &state.audit_events,
+            client,
             None,
-            &form.username,
+            username,
             "login_failed",
             None,
-            Some("Failed login attempt"),
-            None,
-            None,
+            Some(&format!("LDAP groups of '{}' are not mapped to any role", account.dn)),
         ).await;
+        return Err("このアカウントにはダッシュボードの利用が許可されていません".into());
+    };
+
+    sync_external_user(state, client, &config.url, &subject, username, role)
+        .await
+        .map(Some)
+}
+
+/// パスワードやシングルサインオンで本人確認できたユーザーのログインを進める
+///
+/// TOTP が有効なユーザーは確認コードの入力画面に進め、`two_factor::login_2fa` で
+/// コードを確認してから `complete_login` する。それ以外のユーザーはそのままログインを完了する。
+pub async fn continue_login(
+    state: &AppState,
+    client: &db::ClientInfo,
+    session: &Session,
+    user: &db::User,
+) -> Result<Redirect, String> {
+    let totp_enabled = match db::get_user_totp(&state.db, user.id).await {
+        Ok(totp) => totp.is_some_and(|totp| totp.enabled),
+        Err(_) => return Err("データベースエラーが発生しました".into()),
+    };
 
-        Err(LoginTemplate {
-            error: Some("ユーザー名またはパスワードが間違っています".into()),
-        })
+    if totp_enabled {
+        if let Err(e) = session.insert(SESSION_PENDING_2FA_KEY, user.id).await {
+            eprintln!("セッション保存エラー: {:?}", e);
+            return Err("セッションエラーが発生しました".into());
+        }
+        return Ok(Redirect::to("/login/2fa"));
     }
+
+    complete_login(state, client, session, user).await
 }
 
-pub async fn logout(State(state): State<AppState>, session: Session) -> Redirect {
-    // ログアウト前にユーザー名を取得
-    if let Some(username) = get_username(&session).await {
-        if let Ok(Some(user)) = db::get_user_by_username(&state.db, &username).await {
-            // 監査ログに記録
-            let _ = db::create_audit_log(
-                &state.db,
-                Some(user.id),
-                &username,
-                "logout",
-                None,
-                Some("User logged out"),
-                None,
-                None,
-            ).await;
+/// 認証済みユーザーをセッションに保存してログインを完了する
+pub async fn complete_login(
+    state: &AppState,
+    client: &db::ClientInfo,
+    session: &Session,
+    user: &db::User,
+) -> Result<Redirect, String> {
+    // 二要素認証の入力中に無効化された場合もここで拒否する
+    if user.is_disabled() {
+        return Err(DISABLED_ACCOUNT_ERROR.into());
+    }
+
+    // セッション固定化攻撃を防ぐためにログイン時にセッションIDを振り直す
+    let stored = async {
+        session.cycle_id().await?;
+        session.remove::<i32>(SESSION_PENDING_2FA_KEY).await?;
+        // ID を振り直してもデータは引き継がれるため、ログイン前のトークンも使えないようにする
+        csrf::renew(session).await?;
+        session.insert(SESSION_USER_KEY, &user.username).await?;
+        session.insert(session_store::USER_ID_KEY, user.id).await
+    }
+    .await;
+    if let Err(e) = stored {
+        eprintln!("セッション保存エラー: {:?}", e);
+        return Err("セッションエラーが発生しました".into());
+    }
+
+    eprintln!("ログイン成功: {}", user.username);
+
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        client,
+        Some(user.id),
+        &user.username,
+        "login",
+        None,
+        Some("User logged in successfully"),
+    ).await;
+
+    // 二要素認証が必須のロールで未登録の場合は登録画面に誘導する
+    if two_factor::setup_required(state, user).await {
+        if session.insert(SESSION_2FA_SETUP_KEY, true).await.is_err() {
+            return Err("セッションエラーが発生しました".into());
         }
+        return Ok(Redirect::to("/account/2fa"));
     }
 
+    Ok(Redirect::to("/"))
+}
+
+/// ログイン失敗を監査ログに記録し、上限に達していればロックする
+pub async fn record_failed_login(state: &AppState, client: &db::ClientInfo, username: &str, details: &str) {
+    eprintln!("ログイン失敗: {} ({})", username, details);
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        client,
+        None,
+        username,
+        "login_failed",
+        None,
+        Some(details),
+    ).await;
+
+    lockouts::record_login_failure(state, client, username).await;
+}
+
+pub async fn logout(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    ClientContext(client): ClientContext,
+    session: Session,
+) -> Redirect {
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(user.id),
+        &user.username,
+        "logout",
+        None,
+        Some("User logged out"),
+    ).await;
+
     session.delete().await.unwrap();
     Redirect::to("/login")
 }
@@ -137,7 +408,123 @@ pub async fn get_username(session: &Session) -> Option<String> {
         .unwrap_or(None)
 }
 
-pub async fn get_current_user(session: &Session, db: &sqlx::MySqlPool) -> Option<crate::db::User> {
-    let username = get_username(session).await?;
-    db::get_user_by_username(db, &username).await.ok().flatten()
+/// パスワードなどの確認が済み、確認コードの入力を待っているユーザーの ID
+pub async fn get_pending_2fa_user_id(session: &Session) -> Option<i32> {
+    session
+        .get::<i32>(SESSION_PENDING_2FA_KEY)
+        .await
+        .unwrap_or(None)
+}
+
+pub async fn is_2fa_setup_required(session: &Session) -> bool {
+    session
+        .get::<bool>(SESSION_2FA_SETUP_KEY)
+        .await
+        .unwrap_or(None)
+        .unwrap_or(false)
+}
+
+pub async fn clear_2fa_setup_required(session: &Session) {
+    let _ = session.remove::<bool>(SESSION_2FA_SETUP_KEY).await;
+}
+
+/// セッションのユーザーを読み込む（削除されたユーザーは `None`）
+///
+/// ユーザー名ではなく ID で引くため、削除後に同じユーザー名で作り直したユーザーが
+/// 以前のセッションを引き継ぐことはない（ID を持たない古いセッションも `None`）。
+pub async fn load_session_user(session: &Session, db: &sqlx::MySqlPool) -> Result<Option<db::User>, sqlx::Error> {
+    match session.get::<i32>(session_store::USER_ID_KEY).await.unwrap_or(None) {
+        Some(user_id) => db::get_user_by_id(db, user_id).await,
+        None => Ok(None),
+    }
+}
+
+/// ユーザーのロールに権限が付与されているか（確認できない場合は拒否）
+pub async fn has_permission(db: &sqlx::MySqlPool, user: &db::User, permission: db::Permission) -> bool {
+    match db::role_has_permission(db, user.role(), permission).await {
+        Ok(granted) => granted,
+        Err(e) => {
+            eprintln!("権限の確認に失敗: {} {}: {:?}", user.role(), permission.as_str(), e);
+            false
+        }
+    }
+}
+
+/// パスワードが Argon2 ハッシュと一致するか（PHC形式でないハッシュは常に不一致）
+pub fn verify_password(password_hash: &str, password: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+        .unwrap_or(false)
+}
+
+/// 外部の認証基盤で認証されたユーザーを読み込む
+///
+/// 初回ログイン時はユーザーを作成し（user_provision）、以降のログインでは
+/// 認証基盤から得たロールに合わせてローカルのロールを更新する（user_role_sync）。
+/// 同じユーザー名のローカルアカウントがある場合は乗っ取りを防ぐため紐付けずに拒否する。
+pub async fn sync_external_user(
+    state: &AppState,
+    client: &db::ClientInfo,
+    provider: &str,
+    subject: &str,
+    username: &str,
+    role: &str,
+) -> Result<db::User, String> {
+    const DB_ERROR: &str = "データベースエラーが発生しました";
+
+    match db::get_role(&state.db, role).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err(format!("ロール '{}' が存在しません。管理者に連絡してください", role)),
+        Err(_) => return Err(DB_ERROR.into()),
+    }
+
+    if let Some(user) = db::get_user_by_identity(&state.db, provider, subject).await.map_err(|_| DB_ERROR.to_string())? {
+        let _ = db::touch_user_identity(&state.db, provider, subject).await;
+        if user.role() == role {
+            return Ok(user);
+        }
+
+        db::update_user_role(&state.db, user.id, role).await.map_err(|_| DB_ERROR.to_string())?;
+        let _ = db::create_audit_log(
+            &state.db,
+            &state.audit_events,
+            client,
+            Some(user.id),
+            &user.username,
+            "user_role_sync",
+            None,
+            Some(&format!("Changed role of '{}' from {} to {} by {}", user.username, user.role(), role, provider)),
+        ).await;
+
+        sessions::revoke_all(state, client, None, &user, None, "role synchronized from identity provider").await;
+
+        return match db::get_user_by_id(&state.db, user.id).await {
+            Ok(Some(user)) => Ok(user),
+            _ => Err(DB_ERROR.into()),
+        };
+    }
+
+    match db::get_user_by_username(&state.db, username).await {
+        Ok(None) => {}
+        Ok(Some(_)) => return Err(format!("ユーザー名 '{}' は既に別のアカウントで使用されています", username)),
+        Err(_) => return Err(DB_ERROR.into()),
+    }
+
+    let user = db::create_external_user(&state.db, username, role, provider, subject)
+        .await
+        .map_err(|_| DB_ERROR.to_string())?;
+
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        client,
+        Some(user.id),
+        &user.username,
+        "user_provision",
+        None,
+        Some(&format!("Provisioned user '{}' (id: {}, role: {}) from {}", user.username, user.id, role, provider)),
+    ).await;
+
+    Ok(user)
 }
@@ -0,0 +1,80 @@
+//! ルート単位の認可
+//!
+//! `main.rs` でルートを登録するときに必要な権限を宣言する。
+//!
+//! ```ignore
+//! Router::new()
+//!     .route("/users", get(routes::users::list_users))
+//!     .require_permission(&app_state, db::Permission::UsersManage)
+//! ```
+//!
+//! 権限がない場合は access_denied を監査ログに記録して `/` にリダイレクトする。
+//! ユーザーは `auth_middleware` が読み込んだもの（`CurrentUser`）を使うため、
+//! 権限チェックのためにユーザーを読み直すことはない。
+
+use axum::{
+    Router,
+    extract::{Request, State},
+    middleware::{self, Next},
+    response::{Redirect, Response},
+};
+use crate::{AppState, db};
+use crate::routes::{auth, context};
+use crate::routes::context::CurrentUser;
+
+#[derive(Clone)]
+pub struct RequiredPermission {
+    state: AppState,
+    permission: db::Permission,
+}
+
+pub trait RequirePermissionExt {
+    /// このルーターに登録済みのすべてのルートに権限チェックを追加する
+    fn require_permission(self, state: &AppState, permission: db::Permission) -> Self;
+}
+
+impl RequirePermissionExt for Router<AppState> {
+    fn require_permission(self, state: &AppState, permission: db::Permission) -> Self {
+        let required = RequiredPermission {
+            state: state.clone(),
+            permission,
+        };
+        self.route_layer(middleware::from_fn_with_state(required, authorize))
+    }
+}
+
+async fn authorize(
+    State(required): State<RequiredPermission>,
+    CurrentUser(current_user): CurrentUser,
+    request: Request,
+    next: Next,
+) -> Result<Response, Redirect> {
+    let RequiredPermission { state, permission } = required;
+
+    if !auth::has_permission(&state.db, &current_user, permission).await {
+        let (parts, _) = request.into_parts();
+        let client = context::client_info(&parts, &state);
+        let resource = parts.uri.path();
+
+        // 権限エラーを監査ログに記録
+        let _ = db::create_audit_log(
+            &state.db,
+            &state.audit_events,
+            &client,
+            Some(current_user.id),
+            &current_user.username,
+            "access_denied",
+            Some(resource),
+            Some(&format!(
+                "Attempted {} {} without permission '{}'",
+                parts.method,
+                resource,
+                permission.as_str()
+            )),
+        ).await;
+
+        return Err(Redirect::to("/"));
+    }
+
+    Ok(next.run(request).await)
+}
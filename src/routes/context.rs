@@ -0,0 +1,167 @@
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{HeaderMap, header, request::Parts},
+    response::Redirect,
+};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use rust_dashboard::config::ProxyConfig;
+use crate::{AppState, db};
+
+// user_agent カラムに保存する最大文字数
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+/// リクエスト元のIPアドレスと User-Agent を取り出すエクストラクタ
+///
+/// 監査ログはすべてこの情報を付けて記録する。
+pub struct ClientContext(pub db::ClientInfo);
+
+impl FromRequestParts<AppState> for ClientContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        Ok(ClientContext(client_info(parts, state)))
+    }
+}
+
+/// ログイン中のユーザーを取り出すエクストラクタ
+///
+/// ユーザーは `auth_middleware` がリクエストごとに1回だけデータベースから読み込み、
+/// 削除・無効化されていないことを確認してからリクエストの Extension に入れる。
+/// ログインページなどミドルウェアが読み込まないルートでは `/login` にリダイレクトする。
+#[derive(Clone)]
+pub struct CurrentUser(pub db::User);
+
+impl<S: Send + Sync> FromRequestParts<S> for CurrentUser {
+    type Rejection = Redirect;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<CurrentUser>()
+            .cloned()
+            .ok_or_else(|| Redirect::to("/login"))
+    }
+}
+
+/// リクエストの Parts からリクエスト元の情報を取り出す（ミドルウェア用）
+pub fn client_info(parts: &Parts, state: &AppState) -> db::ClientInfo {
+    let peer_ip = parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+
+    let ip_address = peer_ip.map(|peer_ip| client_ip(&parts.headers, &state.proxy, peer_ip).to_string());
+
+    let user_agent = parts
+        .headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.chars().take(MAX_USER_AGENT_LENGTH).collect());
+
+    db::ClientInfo {
+        ip_address,
+        user_agent,
+    }
+}
+
+// 信頼できるプロキシ経由の場合は X-Forwarded-For を右から辿り、
+// 最初に現れた信頼できないアドレスをクライアントとみなす
+fn client_ip(headers: &HeaderMap, proxy: &ProxyConfig, peer_ip: IpAddr) -> IpAddr {
+    if !proxy.is_trusted(&peer_ip) {
+        return peer_ip;
+    }
+
+    let forwarded: Vec<IpAddr> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|value| value.trim().parse().ok())
+        .collect();
+
+    let mut client = peer_ip;
+    for ip in forwarded.into_iter().rev() {
+        client = ip;
+        if !proxy.is_trusted(&ip) {
+            break;
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn proxies(values: &[&str]) -> ProxyConfig {
+        ProxyConfig { trusted_proxies: values.iter().map(|value| ip(value)).collect() }
+    }
+
+    fn forwarded_for(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("x-forwarded-for", value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn ignores_forwarded_for_without_trusted_proxies() {
+        let headers = forwarded_for(&["203.0.113.7"]);
+        assert_eq!(client_ip(&headers, &proxies(&[]), ip("198.51.100.1")), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn ignores_forwarded_for_from_untrusted_peer() {
+        let headers = forwarded_for(&["203.0.113.7"]);
+        let proxy = proxies(&["127.0.0.1"]);
+        assert_eq!(client_ip(&headers, &proxy, ip("198.51.100.1")), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn uses_forwarded_for_from_trusted_proxy() {
+        let headers = forwarded_for(&["203.0.113.7"]);
+        let proxy = proxies(&["127.0.0.1"]);
+        assert_eq!(client_ip(&headers, &proxy, ip("127.0.0.1")), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn skips_spoofed_entries_left_of_the_first_untrusted_address() {
+        // クライアントが先頭に偽のアドレスを付けても、プロキシが追加した右側のアドレスを使う
+        let headers = forwarded_for(&["10.0.0.1, 203.0.113.7, 10.1.1.1"]);
+        let proxy = proxies(&["127.0.0.1", "10.1.1.1"]);
+        assert_eq!(client_ip(&headers, &proxy, ip("127.0.0.1")), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn reads_multiple_headers_and_ipv6() {
+        let headers = forwarded_for(&["2001:db8::1", "::1"]);
+        let proxy = proxies(&["::1"]);
+        assert_eq!(client_ip(&headers, &proxy, ip("::1")), ip("2001:db8::1"));
+    }
+
+    #[test]
+    fn ignores_unparsable_entries() {
+        let headers = forwarded_for(&["unknown, 203.0.113.7, garbage"]);
+        let proxy = proxies(&["127.0.0.1"]);
+        assert_eq!(client_ip(&headers, &proxy, ip("127.0.0.1")), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn falls_back_to_last_proxy_when_all_are_trusted() {
+        let headers = forwarded_for(&["10.1.1.1"]);
+        let proxy = proxies(&["127.0.0.1", "10.1.1.1"]);
+        assert_eq!(client_ip(&headers, &proxy, ip("127.0.0.1")), ip("10.1.1.1"));
+    }
+
+    #[test]
+    fn missing_header_from_trusted_proxy_uses_peer() {
+        let proxy = proxies(&["127.0.0.1"]);
+        assert_eq!(client_ip(&HeaderMap::new(), &proxy, ip("127.0.0.1")), ip("127.0.0.1"));
+    }
+}
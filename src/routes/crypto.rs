@@ -4,9 +4,8 @@ use axum::{
     response::{Html, IntoResponse, Redirect},
 };
 use serde::Deserialize;
-use tower_sessions::Session;
 use crate::{AppState, db};
-use super::auth;
+use super::context::{ClientContext, CurrentUser};
 
 #[derive(Template)]
 #[template(path = "crypto.html")]
@@ -31,33 +30,7 @@ pub struct DecryptForm {
     password: String,
 }
 
-pub async fn index(
-    State(state): State<AppState>,
-    session: Session,
-) -> Result<impl IntoResponse, Redirect> {
-    // 現在のユーザーを取得
-    let current_user = match auth::get_current_user(&session, &state.db).await {
-        Some(user) => user,
-        None => return Err(Redirect::to("/login")),
-    };
-
-    // User以上の権限をチェック
-    if !current_user.role().can_access_crypto() {
-        // 権限エラーを監査ログに記録
-        let _ = db::create_audit_log(
-            &state.db,
-            Some(current_user.id),
-            &current_user.username,
-            "access_denied",
-            Some("/crypto"),
-            Some("Attempted to access crypto page without permission"),
-            None,
-            None,
-        ).await;
-
-        return Err(Redirect::to("/"));
-    }
-
+pub async fn index() -> impl IntoResponse {
     let template = CryptoTemplate {
         encrypted_text: String::new(),
         decrypted_text: String::new(),
@@ -66,49 +39,42 @@ pub async fn index(
         show_decrypted: false,
         show_error: false,
     };
-    Ok(Html(template.render().unwrap()))
+    Html(template.render().unwrap())
+}
+
+/// エラーだけを表示したページ（ファイルの暗号化・復号化で使う）
+pub(crate) fn error_page(error: String) -> Html<String> {
+    let template = CryptoTemplate {
+        encrypted_text: String::new(),
+        decrypted_text: String::new(),
+        error,
+        show_encrypted: false,
+        show_decrypted: false,
+        show_error: true,
+    };
+    Html(template.render().unwrap())
 }
 
 pub async fn encrypt(
     State(state): State<AppState>,
-    session: Session,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
     Form(form): Form<EncryptForm>,
 ) -> Result<impl IntoResponse, Redirect> {
-    // 現在のユーザーを取得
-    let current_user = match auth::get_current_user(&session, &state.db).await {
-        Some(user) => user,
-        None => return Err(Redirect::to("/login")),
-    };
-
-    // User以上の権限をチェック
-    if !current_user.role().can_access_crypto() {
-        // 権限エラーを監査ログに記録
-        let _ = db::create_audit_log(
-            &state.db,
-            Some(current_user.id),
-            &current_user.username,
-            "access_denied",
-            Some("/crypto/encrypt"),
-            Some("Attempted to encrypt without permission"),
-            None,
-            None,
-        ).await;
-
-        return Err(Redirect::to("/"));
-    }
-
-    Ok(match encrypt_string(&form.plaintext, &form.password) {
+    let plaintext_len = form.plaintext.len();
+    let result = blocking(move || encrypt_string(&form.plaintext, &form.password)).await;
+    Ok(match result {
         Ok(encrypted) => {
             // 監査ログに記録
             let _ = db::create_audit_log(
                 &state.db,
+                &state.audit_events,
+                &client,
                 Some(current_user.id),
                 &current_user.username,
                 "encrypt",
                 Some("/crypto/encrypt"),
-                Some(&format!("Encrypted text (length: {})", form.plaintext.len())),
-                None,
-                None,
+                Some(&format!("Encrypted text (length: {})", plaintext_len)),
             ).await;
 
             let template = CryptoTemplate {
@@ -125,13 +91,13 @@ pub async fn encrypt(
             // エラーも記録
             let _ = db::create_audit_log(
                 &state.db,
+                &state.audit_events,
+                &client,
                 Some(current_user.id),
                 &current_user.username,
                 "encrypt_failed",
                 Some("/crypto/encrypt"),
                 Some(&format!("Encryption failed: {}", e)),
-                None,
-                None,
             ).await;
 
             let template = CryptoTemplate {
@@ -149,44 +115,23 @@ pub async fn encrypt(
 
 pub async fn decrypt(
     State(state): State<AppState>,
-    session: Session,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
     Form(form): Form<DecryptForm>,
 ) -> Result<impl IntoResponse, Redirect> {
-    // 現在のユーザーを取得
-    let current_user = match auth::get_current_user(&session, &state.db).await {
-        Some(user) => user,
-        None => return Err(Redirect::to("/login")),
-    };
-
-    // User以上の権限をチェック
-    if !current_user.role().can_access_crypto() {
-        // 権限エラーを監査ログに記録
-        let _ = db::create_audit_log(
-            &state.db,
-            Some(current_user.id),
-            &current_user.username,
-            "access_denied",
-            Some("/crypto/decrypt"),
-            Some("Attempted to decrypt without permission"),
-            None,
-            None,
-        ).await;
-
-        return Err(Redirect::to("/"));
-    }
-
-    Ok(match decrypt_string(&form.ciphertext, &form.password) {
+    let result = blocking(move || decrypt_string(&form.ciphertext, &form.password)).await;
+    Ok(match result {
         Ok(decrypted) => {
             // 監査ログに記録
             let _ = db::create_audit_log(
                 &state.db,
+                &state.audit_events,
+                &client,
                 Some(current_user.id),
                 &current_user.username,
                 "decrypt",
                 Some("/crypto/decrypt"),
                 Some(&format!("Decrypted text (length: {})", decrypted.len())),
-                None,
-                None,
             ).await;
 
             let template = CryptoTemplate {
@@ -203,13 +148,13 @@ pub async fn decrypt(
             // エラーも記録
             let _ = db::create_audit_log(
                 &state.db,
+                &state.audit_events,
+                &client,
                 Some(current_user.id),
                 &current_user.username,
                 "decrypt_failed",
                 Some("/crypto/decrypt"),
                 Some(&format!("Decryption failed: {}", e)),
-                None,
-                None,
             ).await;
 
             let template = CryptoTemplate {
@@ -225,89 +170,242 @@ pub async fn decrypt(
     })
 }
 
+// 暗号文の形式
+//
+// 現在の形式（Base64 エンコード前のバイト列）:
+//   "RDE" | バージョン(1) | KDF(1) | m_cost(4) | t_cost(4) | p_cost(4) | ソルト(16) | ナンス(12) | 暗号文
+// 数値はビッグエンディアン。暗号文の前までのヘッダーは AES-GCM の追加認証データとして認証する。
+//
+// 以前の形式（ナンス(12) | 暗号文）も復号化できる。
+const ENVELOPE_MAGIC: &[u8; 3] = b"RDE";
+const ENVELOPE_VERSION: u8 = 1;
+pub(crate) const KDF_ARGON2ID: u8 = 1;
+pub(crate) const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = ENVELOPE_MAGIC.len() + 2 + 12 + SALT_LEN + NONCE_LEN;
+
+/// 暗号化に使う鍵導出（Argon2id）のパラメータ
+pub(crate) const DEFAULT_KDF_PARAMS: KdfParams = KdfParams {
+    m_cost: 65536, // 64MB
+    t_cost: 3,     // 3回繰り返し
+    p_cost: 4,     // 4並列
+};
+
+/// 以前の形式の暗号文を作成したときのパラメータ（`DEFAULT_KDF_PARAMS` を変えても変えない）
+const LEGACY_KDF_PARAMS: KdfParams = KdfParams {
+    m_cost: 65536,
+    t_cost: 3,
+    p_cost: 4,
+};
+
+// 復号化時に受け入れるパラメータの上限（細工した暗号文で大量のメモリや時間を使わせないため）
+const MAX_KDF_M_COST: u32 = 262144; // 256MB
+const MAX_KDF_T_COST: u32 = 10;
+const MAX_KDF_P_COST: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct KdfParams {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl KdfParams {
+    pub(crate) fn to_bytes(self) -> [u8; 12] {
+        let mut bytes = [0u8; 12];
+        bytes[..4].copy_from_slice(&self.m_cost.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.t_cost.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.p_cost.to_be_bytes());
+        bytes
+    }
+
+    /// ヘッダーのパラメータを読み取る（上限を超える場合はエラー）
+    pub(crate) fn from_bytes(bytes: &[u8; 12]) -> anyhow::Result<Self> {
+        let read_u32 = |offset: usize| u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let kdf = Self {
+            m_cost: read_u32(0),
+            t_cost: read_u32(4),
+            p_cost: read_u32(8),
+        };
+        if kdf.m_cost > MAX_KDF_M_COST || kdf.t_cost > MAX_KDF_T_COST || kdf.p_cost > MAX_KDF_P_COST {
+            anyhow::bail!("鍵導出のパラメータが大きすぎます");
+        }
+        Ok(kdf)
+    }
+}
+
+/// 暗号文の先頭に付けるヘッダー
+struct EnvelopeHeader {
+    kdf: KdfParams,
+    salt: [u8; SALT_LEN],
+    nonce: [u8; NONCE_LEN],
+}
+
+impl EnvelopeHeader {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN);
+        bytes.extend_from_slice(ENVELOPE_MAGIC);
+        bytes.push(ENVELOPE_VERSION);
+        bytes.push(KDF_ARGON2ID);
+        bytes.extend_from_slice(&self.kdf.to_bytes());
+        bytes.extend_from_slice(&self.salt);
+        bytes.extend_from_slice(&self.nonce);
+        bytes
+    }
+
+    /// 現在の形式のヘッダーを読み取る（現在の形式でなければ `None`）
+    fn parse(data: &[u8]) -> Option<anyhow::Result<Self>> {
+        if data.len() < HEADER_LEN || !data.starts_with(ENVELOPE_MAGIC) {
+            return None;
+        }
+
+        let header = (|| {
+            let (version, kdf) = (data[3], data[4]);
+            if version != ENVELOPE_VERSION {
+                anyhow::bail!("対応していない形式のバージョンです: {}", version);
+            }
+            if kdf != KDF_ARGON2ID {
+                anyhow::bail!("対応していない鍵導出方式です: {}", kdf);
+            }
+
+            let kdf = KdfParams::from_bytes(data[5..17].try_into().unwrap())?;
+
+            let salt_start = 17;
+            let nonce_start = salt_start + SALT_LEN;
+            Ok(Self {
+                kdf,
+                salt: data[salt_start..nonce_start].try_into().unwrap(),
+                nonce: data[nonce_start..HEADER_LEN].try_into().unwrap(),
+            })
+        })();
+        Some(header)
+    }
+}
+
 // encript_toolの機能を使用した暗号化関数
-fn encrypt_string(text: &str, password: &str) -> anyhow::Result<String> {
+pub fn encrypt_string(text: &str, password: &str) -> anyhow::Result<String> {
+    encrypt_with_kdf(text, password, DEFAULT_KDF_PARAMS)
+}
+
+fn encrypt_with_kdf(text: &str, password: &str, kdf: KdfParams) -> anyhow::Result<String> {
     use aes_gcm::{
         Aes256Gcm, Nonce,
-        aead::{Aead, KeyInit},
+        aead::{Aead, KeyInit, Payload},
     };
     use rand::RngCore;
 
-    // キー生成（簡易版 - Argon2を使用）
-    let key = derive_key_from_password(password)?;
+    // メッセージごとにランダムなソルトとナンスを生成
+    let mut header = EnvelopeHeader {
+        kdf,
+        salt: [0u8; SALT_LEN],
+        nonce: [0u8; NONCE_LEN],
+    };
+    rand::rng().fill_bytes(&mut header.salt);
+    rand::rng().fill_bytes(&mut header.nonce);
 
-    // ランダムナンス生成
-    let mut nonce_bytes = [0u8; 12];
-    rand::rng().fill_bytes(&mut nonce_bytes);
-    let nonce = Nonce::from_slice(&nonce_bytes);
+    let key = derive_key(password, &header.salt, header.kdf)?;
 
-    // AES-GCM暗号化
+    // AES-GCM暗号化（ヘッダーも改ざんされていないことを確認できるようにする）
+    let mut result = header.encode();
     let cipher = Aes256Gcm::new(&key.into());
     let ciphertext = cipher
-        .encrypt(nonce, text.as_bytes())
+        .encrypt(Nonce::from_slice(&header.nonce), Payload { msg: text.as_bytes(), aad: &result })
         .map_err(|e| anyhow::anyhow!("暗号化に失敗: {}", e))?;
 
-    // ナンス + 暗号文を結合してBase64エンコード
-    let mut result = nonce_bytes.to_vec();
+    // ヘッダー + 暗号文を結合してBase64エンコード
     result.extend_from_slice(&ciphertext);
 
     Ok(base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &result))
 }
 
 // encript_toolの機能を使用した復号化関数
-fn decrypt_string(encrypted_text: &str, password: &str) -> anyhow::Result<String> {
+pub fn decrypt_string(encrypted_text: &str, password: &str) -> anyhow::Result<String> {
     use aes_gcm::{
         Aes256Gcm, Nonce,
-        aead::{Aead, KeyInit},
+        aead::{Aead, KeyInit, Payload},
     };
 
     // Base64デコード
-    let data = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, encrypted_text)
+    let data = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, encrypted_text.trim())
         .map_err(|e| anyhow::anyhow!("Base64デコードに失敗: {}", e))?;
 
-    if data.len() < 12 {
+    let plaintext = match EnvelopeHeader::parse(&data) {
+        // ヘッダーを読み取れた場合は以前の形式として試し直さない（パスワードの誤りで鍵導出を2回行わない）
+        Some(Ok(header)) => {
+            let (aad, ciphertext) = data.split_at(HEADER_LEN);
+            let key = derive_key(password, &header.salt, header.kdf)?;
+
+            let cipher = Aes256Gcm::new(&key.into());
+            cipher
+                .decrypt(Nonce::from_slice(&header.nonce), Payload { msg: ciphertext, aad })
+                .map_err(|e| anyhow::anyhow!("復号化に失敗: {}", e))?
+        }
+        // 以前の形式のナンスがたまたま "RDE" で始まっている場合に備える
+        Some(Err(e)) => decrypt_legacy(&data, password).map_err(|_| e)?,
+        None => decrypt_legacy(&data, password)?,
+    };
+
+    String::from_utf8(plaintext)
+        .map_err(|e| anyhow::anyhow!("UTF-8変換に失敗: {}", e))
+}
+
+// 以前の形式（ナンス + 暗号文、ソルトはパスワードから導出）を復号化する
+fn decrypt_legacy(data: &[u8], password: &str) -> anyhow::Result<Vec<u8>> {
+    use aes_gcm::{
+        Aes256Gcm, Nonce,
+        aead::{Aead, KeyInit},
+    };
+
+    if data.len() < NONCE_LEN {
         return Err(anyhow::anyhow!("データが短すぎます"));
     }
 
     // ナンスと暗号文を分離
-    let (nonce_bytes, ciphertext) = data.split_at(12);
+    let (nonce_bytes, ciphertext) = data.split_at(NONCE_LEN);
     let nonce = Nonce::from_slice(nonce_bytes);
 
     // キー再生成
-    let key = derive_key_from_password(password)?;
+    let key = derive_key(password, &legacy_salt(password), LEGACY_KDF_PARAMS)?;
 
     // AES-GCM復号化
     let cipher = Aes256Gcm::new(&key.into());
-    let plaintext = cipher
+    cipher
         .decrypt(nonce, ciphertext)
-        .map_err(|e| anyhow::anyhow!("復号化に失敗: {}", e))?;
-
-    String::from_utf8(plaintext)
-        .map_err(|e| anyhow::anyhow!("UTF-8変換に失敗: {}", e))
+        .map_err(|e| anyhow::anyhow!("復号化に失敗: {}", e))
 }
 
-// パスワードから鍵を生成（Argon2使用）
-fn derive_key_from_password(password: &str) -> anyhow::Result<[u8; 32]> {
-    use argon2::Argon2;
+// 以前の形式のソルト（パスワードから導出していたもの、復号化にのみ使う）
+//
+// DefaultHasher の出力は Rust のバージョンによって変わる可能性があるため、
+// 古い暗号文は作成時と異なるバージョンでビルドすると復号化できない場合がある。
+fn legacy_salt(password: &str) -> [u8; SALT_LEN] {
     use std::hash::{Hash, Hasher};
 
-    // ソルト生成（パスワードから導出）
-    let mut salt = [0u8; 16];
+    let mut salt = [0u8; SALT_LEN];
     let mut hasher = std::collections::hash_map::DefaultHasher::new();
     password.hash(&mut hasher);
     let hash_value = hasher.finish();
     let hash_bytes = hash_value.to_le_bytes();
     salt[..8].copy_from_slice(&hash_bytes);
     salt[8..16].copy_from_slice(&hash_bytes);
+    salt
+}
 
-    // Argon2でキー導出
-    let params = argon2::Params::new(
-        65536, // 64MB
-        3,     // 3回繰り返し
-        4,     // 4並列
-        Some(32),
-    )
-    .map_err(|e| anyhow::anyhow!("Argon2パラメータエラー: {}", e))?;
+/// 鍵導出（Argon2）は時間がかかるため、非同期のワーカースレッドを塞がないよう別スレッドで行う
+///
+/// `encrypt_string`・`decrypt_string` や鍵導出を伴う処理は、ハンドラからこれを通して呼ぶ。
+pub(crate) async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    tokio::task::spawn_blocking(f).await?
+}
+
+// パスワードとソルトから鍵を生成（Argon2id使用）
+pub(crate) fn derive_key(password: &str, salt: &[u8], kdf: KdfParams) -> anyhow::Result<[u8; 32]> {
+    use argon2::Argon2;
+
+    let params = argon2::Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(32))
+        .map_err(|e| anyhow::anyhow!("Argon2パラメータエラー: {}", e))?;
 
     let argon2 = Argon2::new(
         argon2::Algorithm::Argon2id,
@@ -317,8 +415,132 @@ fn derive_key_from_password(password: &str) -> anyhow::Result<[u8; 32]> {
 
     let mut key = [0u8; 32];
     argon2
-        .hash_password_into(password.as_bytes(), &salt, &mut key)
+        .hash_password_into(password.as_bytes(), salt, &mut key)
         .map_err(|e| anyhow::anyhow!("キー導出に失敗: {}", e))?;
 
     Ok(key)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+
+    // テストを速くするための小さいパラメータ
+    const TEST_KDF_PARAMS: KdfParams = KdfParams { m_cost: 64, t_cost: 1, p_cost: 1 };
+
+    fn decode(encrypted: &str) -> Vec<u8> {
+        base64::engine::general_purpose::STANDARD.decode(encrypted).unwrap()
+    }
+
+    fn encode(data: &[u8]) -> String {
+        base64::engine::general_purpose::STANDARD.encode(data)
+    }
+
+    /// 以前の形式（ナンス + 暗号文）で暗号化する
+    fn encrypt_legacy(text: &str, password: &str) -> String {
+        encrypt_legacy_with_nonce(text, password, [7u8; NONCE_LEN])
+    }
+
+    fn encrypt_legacy_with_nonce(text: &str, password: &str, nonce: [u8; NONCE_LEN]) -> String {
+        use aes_gcm::{Aes256Gcm, Nonce, aead::{Aead, KeyInit}};
+
+        let key = derive_key(password, &legacy_salt(password), LEGACY_KDF_PARAMS).unwrap();
+        let ciphertext = Aes256Gcm::new(&key.into())
+            .encrypt(Nonce::from_slice(&nonce), text.as_bytes())
+            .unwrap();
+        encode(&[nonce.as_slice(), &ciphertext].concat())
+    }
+
+    #[test]
+    fn envelope_round_trip() {
+        let encrypted = encrypt_with_kdf("秘密のメッセージ", "password", TEST_KDF_PARAMS).unwrap();
+        let data = decode(&encrypted);
+        assert!(data.starts_with(ENVELOPE_MAGIC));
+        assert_eq!(&data[5..17], &TEST_KDF_PARAMS.to_bytes());
+
+        assert_eq!(decrypt_string(&encrypted, "password").unwrap(), "秘密のメッセージ");
+        assert!(decrypt_string(&encrypted, "wrong password").is_err());
+    }
+
+    #[test]
+    fn same_text_encrypts_differently() {
+        let first = encrypt_with_kdf("text", "password", TEST_KDF_PARAMS).unwrap();
+        let second = encrypt_with_kdf("text", "password", TEST_KDF_PARAMS).unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn legacy_format_decrypts_with_legacy_params() {
+        let encrypted = encrypt_legacy("以前の形式", "password");
+        assert_eq!(decrypt_string(&encrypted, "password").unwrap(), "以前の形式");
+        assert!(decrypt_string(&encrypted, "wrong password").is_err());
+    }
+
+    #[test]
+    fn legacy_nonce_starting_with_magic_decrypts() {
+        // ナンスが "RDE" で始まり、現在の形式のヘッダーとしては読み取れない場合
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[..3].copy_from_slice(ENVELOPE_MAGIC);
+        nonce[3] = ENVELOPE_VERSION + 1;
+        let text = "以前の形式で暗号化した長めのメッセージ";
+        let encrypted = encrypt_legacy_with_nonce(text, "password", nonce);
+        assert!(decode(&encrypted).len() >= HEADER_LEN);
+        assert_eq!(decrypt_string(&encrypted, "password").unwrap(), text);
+    }
+
+    #[test]
+    fn legacy_params_are_the_original_params() {
+        assert_eq!(LEGACY_KDF_PARAMS, KdfParams { m_cost: 65536, t_cost: 3, p_cost: 4 });
+    }
+
+    #[test]
+    fn tampered_header_fails_authentication() {
+        let encrypted = encrypt_with_kdf("text", "password", TEST_KDF_PARAMS).unwrap();
+        // ソルトとナンスのバイトを書き換える
+        for index in [17, HEADER_LEN - 1] {
+            let mut data = decode(&encrypted);
+            data[index] ^= 0x01;
+            let error = decrypt_string(&encode(&data), "password").unwrap_err();
+            assert!(error.to_string().contains("復号化に失敗"), "{}: {}", index, error);
+        }
+    }
+
+    #[test]
+    fn tampered_ciphertext_fails_authentication() {
+        let mut data = decode(&encrypt_with_kdf("text", "password", TEST_KDF_PARAMS).unwrap());
+        data[HEADER_LEN] ^= 0x01;
+        assert!(decrypt_string(&encode(&data), "password").is_err());
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let mut data = decode(&encrypt_with_kdf("text", "password", TEST_KDF_PARAMS).unwrap());
+        data[3] = ENVELOPE_VERSION + 1;
+        let error = decrypt_string(&encode(&data), "password").unwrap_err();
+        assert!(error.to_string().contains("バージョン"), "{}", error);
+    }
+
+    #[test]
+    fn unknown_kdf_is_rejected() {
+        let mut data = decode(&encrypt_with_kdf("text", "password", TEST_KDF_PARAMS).unwrap());
+        data[4] = KDF_ARGON2ID + 1;
+        let error = decrypt_string(&encode(&data), "password").unwrap_err();
+        assert!(error.to_string().contains("鍵導出方式"), "{}", error);
+    }
+
+    #[test]
+    fn oversized_kdf_params_are_rejected() {
+        let mut data = decode(&encrypt_with_kdf("text", "password", TEST_KDF_PARAMS).unwrap());
+        data[5..9].copy_from_slice(&(MAX_KDF_M_COST + 1).to_be_bytes());
+        let error = decrypt_string(&encode(&data), "password").unwrap_err();
+        assert!(error.to_string().contains("大きすぎます"), "{}", error);
+    }
+
+    #[test]
+    fn truncated_envelope_is_rejected() {
+        let data = decode(&encrypt_with_kdf("text", "password", TEST_KDF_PARAMS).unwrap());
+        assert!(decrypt_string(&encode(&data[..HEADER_LEN - 1]), "password").is_err());
+        assert!(decrypt_string(&encode(&data[..NONCE_LEN - 1]), "password").is_err());
+    }
+}
@@ -0,0 +1,537 @@
+//! ファイルの暗号化/復号化
+//!
+//! アップロードされたファイルを一定の大きさのチャンクに分け、AES-256-GCM の STREAM 構成
+//! （ナンス = 接頭辞(7) | チャンク番号(4) | 最後のチャンクか(1)）で暗号化する。
+//! アップロードを読みながら暗号化・復号化した結果を一時ファイルに書き出し、アップロードを
+//! 読み終えてから（復号化ではすべてのチャンクの認証を確認してから）ダウンロードとして返す。
+//! ファイル全体をメモリに載せることはない。
+//!
+//! ファイルの形式:
+//!   "RDF" | バージョン(1) | KDF(1) | m_cost(4) | t_cost(4) | p_cost(4) | ソルト(16)
+//!   | ナンスの接頭辞(7) | チャンクの大きさ(4) | 暗号化したチャンク...
+//! 各チャンクはヘッダー全体を追加認証データとして認証する。途中で切り詰められた場合は
+//! 最後のチャンクの認証に失敗するため検出できる。
+
+use aes_gcm::{
+    Aes256Gcm, KeyInit,
+    aead::{Payload, stream::{DecryptorBE32, EncryptorBE32}},
+};
+use axum::{
+    body::Body,
+    extract::{Multipart, State},
+    http::header,
+    response::{IntoResponse, Redirect, Response},
+};
+use rand::RngCore;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+use crate::{AppState, db};
+use crate::routes::crypto::{self, DEFAULT_KDF_PARAMS, KDF_ARGON2ID, KdfParams, SALT_LEN, blocking};
+use crate::routes::context::{ClientContext, CurrentUser};
+
+/// アップロードできるファイルの最大サイズ（main.rs でルートに設定する）
+pub const MAX_UPLOAD_BYTES: usize = 1024 * 1024 * 1024;
+
+const FILE_MAGIC: &[u8; 3] = b"RDF";
+const FILE_VERSION: u8 = 1;
+const NONCE_PREFIX_LEN: usize = 7;
+const TAG_LEN: usize = 16;
+const FILE_HEADER_LEN: usize = FILE_MAGIC.len() + 2 + 12 + SALT_LEN + NONCE_PREFIX_LEN + 4;
+const CHUNK_SIZE: u32 = 64 * 1024;
+// 復号化時に受け入れるチャンクの大きさの上限（細工したファイルで大量のメモリを使わせないため）
+const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+// 暗号化したファイルに付ける拡張子
+const ENCRYPTED_EXTENSION: &str = ".rdf";
+
+/// アップロードを少しずつ受け取って暗号化する
+struct FileEncryptor {
+    encryptor: EncryptorBE32<Aes256Gcm>,
+    header: Vec<u8>,
+    buffer: Vec<u8>,
+}
+
+impl FileEncryptor {
+    /// 暗号化を始め、ファイルの先頭に書くヘッダーを返す
+    fn new(password: &str) -> anyhow::Result<(Self, Vec<u8>)> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        rand::rng().fill_bytes(&mut salt);
+        rand::rng().fill_bytes(&mut nonce_prefix);
+
+        let mut header = Vec::with_capacity(FILE_HEADER_LEN);
+        header.extend_from_slice(FILE_MAGIC);
+        header.push(FILE_VERSION);
+        header.push(KDF_ARGON2ID);
+        header.extend_from_slice(&DEFAULT_KDF_PARAMS.to_bytes());
+        header.extend_from_slice(&salt);
+        header.extend_from_slice(&nonce_prefix);
+        header.extend_from_slice(&CHUNK_SIZE.to_be_bytes());
+
+        let key = crypto::derive_key(password, &salt, DEFAULT_KDF_PARAMS)?;
+        let cipher = Aes256Gcm::new(&key.into());
+        let encryptor = EncryptorBE32::from_aead(cipher, nonce_prefix.as_slice().into());
+
+        let output = header.clone();
+        Ok((Self { encryptor, header, buffer: Vec::new() }, output))
+    }
+
+    fn update(&mut self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+
+        // 最後のチャンクは finish で暗号化するため、チャンクより多く溜まった分だけ暗号化する
+        let chunk_size = CHUNK_SIZE as usize;
+        let mut output = Vec::new();
+        while self.buffer.len() > chunk_size {
+            let chunk: Vec<u8> = self.buffer.drain(..chunk_size).collect();
+            let encrypted = self
+                .encryptor
+                .encrypt_next(Payload { msg: &chunk, aad: &self.header })
+                .map_err(|e| anyhow::anyhow!("暗号化に失敗: {}", e))?;
+            output.extend_from_slice(&encrypted);
+        }
+        Ok(output)
+    }
+
+    fn finish(self) -> anyhow::Result<Vec<u8>> {
+        self.encryptor
+            .encrypt_last(Payload { msg: &self.buffer, aad: &self.header })
+            .map_err(|e| anyhow::anyhow!("暗号化に失敗: {}", e))
+    }
+}
+
+/// アップロードを少しずつ受け取って復号化する
+struct FileDecryptor {
+    decryptor: DecryptorBE32<Aes256Gcm>,
+    header: Vec<u8>,
+    encrypted_chunk_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl FileDecryptor {
+    /// ファイルの先頭のヘッダー（`FILE_HEADER_LEN` バイト）を読んで復号化を始める
+    fn new(password: &str, header: &[u8]) -> anyhow::Result<Self> {
+        if header.len() < FILE_HEADER_LEN || !header.starts_with(FILE_MAGIC) {
+            anyhow::bail!("暗号化したファイルではありません");
+        }
+        let header = header[..FILE_HEADER_LEN].to_vec();
+        if header[3] != FILE_VERSION {
+            anyhow::bail!("対応していない形式のバージョンです: {}", header[3]);
+        }
+        if header[4] != KDF_ARGON2ID {
+            anyhow::bail!("対応していない鍵導出方式です: {}", header[4]);
+        }
+
+        let kdf = KdfParams::from_bytes(header[5..17].try_into().unwrap())?;
+        let salt_end = 17 + SALT_LEN;
+        let nonce_end = salt_end + NONCE_PREFIX_LEN;
+        let chunk_size = u32::from_be_bytes(header[nonce_end..FILE_HEADER_LEN].try_into().unwrap());
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            anyhow::bail!("チャンクの大きさが不正です: {}", chunk_size);
+        }
+
+        let key = crypto::derive_key(password, &header[17..salt_end], kdf)?;
+        let cipher = Aes256Gcm::new(&key.into());
+        let decryptor = DecryptorBE32::from_aead(cipher, header[salt_end..nonce_end].into());
+        Ok(Self {
+            decryptor,
+            header,
+            encrypted_chunk_size: chunk_size as usize + TAG_LEN,
+            buffer: Vec::new(),
+        })
+    }
+
+    fn update(&mut self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+
+        // 最後のチャンクは finish で復号化するため、チャンクより多く溜まった分だけ復号化する
+        let mut output = Vec::new();
+        while self.buffer.len() > self.encrypted_chunk_size {
+            let chunk: Vec<u8> = self.buffer.drain(..self.encrypted_chunk_size).collect();
+            let decrypted = self
+                .decryptor
+                .decrypt_next(Payload { msg: &chunk, aad: &self.header })
+                .map_err(|_| anyhow::anyhow!("復号化に失敗: パスワードが違うか、ファイルが破損しています"))?;
+            output.extend_from_slice(&decrypted);
+        }
+        Ok(output)
+    }
+
+    fn finish(self) -> anyhow::Result<Vec<u8>> {
+        self.decryptor
+            .decrypt_last(Payload { msg: &self.buffer, aad: &self.header })
+            .map_err(|_| anyhow::anyhow!("復号化に失敗: パスワードが違うか、ファイルが破損しています"))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Encrypt,
+    Decrypt,
+}
+
+impl Mode {
+    fn action(self) -> &'static str {
+        match self {
+            Mode::Encrypt => "encrypt_file",
+            Mode::Decrypt => "decrypt_file",
+        }
+    }
+
+    fn resource(self) -> &'static str {
+        match self {
+            Mode::Encrypt => "/crypto/encrypt-file",
+            Mode::Decrypt => "/crypto/decrypt-file",
+        }
+    }
+
+    fn output_name(self, file_name: &str) -> String {
+        match self {
+            Mode::Encrypt => format!("{}{}", file_name, ENCRYPTED_EXTENSION),
+            Mode::Decrypt => match file_name.strip_suffix(ENCRYPTED_EXTENSION) {
+                Some(name) if !name.is_empty() => name.to_string(),
+                _ => format!("{}.decrypted", file_name),
+            },
+        }
+    }
+}
+
+enum Processor {
+    Encrypt(FileEncryptor),
+    Decrypt(FileDecryptor),
+}
+
+impl Processor {
+    fn update(&mut self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Processor::Encrypt(encryptor) => encryptor.update(data),
+            Processor::Decrypt(decryptor) => decryptor.update(data),
+        }
+    }
+
+    fn finish(self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Processor::Encrypt(encryptor) => encryptor.finish(),
+            Processor::Decrypt(decryptor) => decryptor.finish(),
+        }
+    }
+}
+
+/// ダウンロードのファイル名（ASCII 以外は filename* で渡す）
+fn content_disposition(file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '_' })
+        .collect();
+    let encoded: String = file_name
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect();
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
+// ブラウザによってはパスを含めて送ってくるため、最後の要素だけを使う
+fn base_name(file_name: &str) -> String {
+    let name = file_name.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    if name.is_empty() { "file".to_string() } else { name.to_string() }
+}
+
+/// 暗号化・復号化した結果を書き出した一時ファイル（閉じると削除される）
+struct Output {
+    file: tokio::fs::File,
+    name: String,
+    input_size: u64,
+    size: u64,
+}
+
+impl Output {
+    fn new(name: String) -> anyhow::Result<Self> {
+        let file = tokio::fs::File::from_std(tempfile::tempfile()?);
+        Ok(Self { file, name, input_size: 0, size: 0 })
+    }
+
+    async fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.file.write_all(data).await?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    /// 書き出した内容をダウンロードとして返す
+    async fn into_response(mut self) -> anyhow::Result<Response> {
+        self.file.flush().await?;
+        self.file.rewind().await?;
+        Ok((
+            [
+                (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+                (header::CONTENT_DISPOSITION, content_disposition(&self.name)),
+                (header::CONTENT_LENGTH, self.size.to_string()),
+            ],
+            Body::from_stream(ReaderStream::new(self.file)),
+        )
+            .into_response())
+    }
+}
+
+struct Job {
+    state: AppState,
+    client: db::ClientInfo,
+    user: db::User,
+    mode: Mode,
+}
+
+impl Job {
+    async fn audit(&self, action: &str, details: &str) {
+        let _ = db::create_audit_log(
+            &self.state.db,
+            &self.state.audit_events,
+            &self.client,
+            Some(self.user.id),
+            &self.user.username,
+            action,
+            Some(self.mode.resource()),
+            Some(details),
+        ).await;
+    }
+
+    /// フォームをすべて読みながら暗号化・復号化し、結果をダウンロードとして返す
+    async fn run(self, mut multipart: Multipart) -> Response {
+        let mut file_name = None;
+        let result = match self.process(&mut multipart, &mut file_name).await {
+            Ok(output) => {
+                let (input_size, output_size) = (output.input_size, output.size);
+                output.into_response().await.map(|response| (response, input_size, output_size))
+            }
+            Err(e) => Err(e),
+        };
+
+        let file_name = file_name.unwrap_or_else(|| "-".to_string());
+        match result {
+            Ok((response, input_size, output_size)) => {
+                let verb = if self.mode == Mode::Encrypt { "Encrypted" } else { "Decrypted" };
+                let details = format!("{} file '{}' (size: {} bytes, output: {} bytes)", verb, file_name, input_size, output_size);
+                self.audit(self.mode.action(), &details).await;
+                response
+            }
+            Err(e) => {
+                let verb = if self.mode == Mode::Encrypt { "Encryption" } else { "Decryption" };
+                let details = format!("{} of file '{}' failed: {}", verb, file_name, e);
+                self.audit(&format!("{}_failed", self.mode.action()), &details).await;
+
+                let prefix = if self.mode == Mode::Encrypt { "暗号化エラー" } else { "復号化エラー" };
+                crypto::error_page(format!("{}: {}", prefix, e)).into_response()
+            }
+        }
+    }
+
+    async fn process(&self, multipart: &mut Multipart, file_name: &mut Option<String>) -> anyhow::Result<Output> {
+        // フォームは csrf_token、password、file の順に送られる
+        let mut password = None;
+        let mut field = loop {
+            let Some(field) = multipart.next_field().await? else {
+                anyhow::bail!("ファイルを選択してください");
+            };
+            match field.name() {
+                Some("password") => password = Some(field.text().await?),
+                Some("file") => break field,
+                _ => {}
+            }
+        };
+        let password = password.filter(|password| !password.is_empty());
+        let Some(password) = password else {
+            anyhow::bail!("パスワードを入力してください");
+        };
+        let name = base_name(field.file_name().unwrap_or_default());
+        let mut output = Output::new(self.mode.output_name(&name))?;
+        *file_name = Some(name);
+
+        let mut processor = match self.mode {
+            Mode::Encrypt => {
+                let (encryptor, header) = blocking(move || FileEncryptor::new(&password)).await?;
+                output.write(&header).await?;
+                Processor::Encrypt(encryptor)
+            }
+            Mode::Decrypt => {
+                // ヘッダーを読むまでは鍵を作れない
+                let mut header = Vec::new();
+                while header.len() < FILE_HEADER_LEN {
+                    let Some(chunk) = field.chunk().await? else {
+                        anyhow::bail!("データが短すぎます");
+                    };
+                    output.input_size += chunk.len() as u64;
+                    header.extend_from_slice(&chunk);
+                }
+                let rest = header.split_off(FILE_HEADER_LEN);
+                let mut decryptor = blocking(move || FileDecryptor::new(&password, &header)).await?;
+                output.write(&decryptor.update(&rest)?).await?;
+                Processor::Decrypt(decryptor)
+            }
+        };
+
+        while let Some(chunk) = field.chunk().await? {
+            output.input_size += chunk.len() as u64;
+            let data = processor.update(&chunk)?;
+            output.write(&data).await?;
+        }
+        output.write(&processor.finish()?).await?;
+        Ok(output)
+    }
+}
+
+async fn handle(
+    state: AppState,
+    current_user: db::User,
+    client: db::ClientInfo,
+    multipart: Multipart,
+    mode: Mode,
+) -> Response {
+    let job = Job { state, client, user: current_user, mode };
+    job.run(multipart).await
+}
+
+pub async fn encrypt_file(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    multipart: Multipart,
+) -> Result<impl IntoResponse, Redirect> {
+    Ok(handle(state, current_user, client, multipart, Mode::Encrypt).await)
+}
+
+pub async fn decrypt_file(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    multipart: Multipart,
+) -> Result<impl IntoResponse, Redirect> {
+    Ok(handle(state, current_user, client, multipart, Mode::Decrypt).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD: &str = "password";
+    const CHUNK: usize = CHUNK_SIZE as usize;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// アップロードと同じように `piece` バイトずつ渡して暗号化する
+    fn encrypt(data: &[u8], piece: usize) -> Vec<u8> {
+        let (mut encryptor, mut output) = FileEncryptor::new(PASSWORD).unwrap();
+        for piece in data.chunks(piece) {
+            output.extend_from_slice(&encryptor.update(piece).unwrap());
+        }
+        output.extend_from_slice(&encryptor.finish().unwrap());
+        output
+    }
+
+    fn decrypt(data: &[u8], password: &str) -> anyhow::Result<Vec<u8>> {
+        let mut decryptor = FileDecryptor::new(password, data)?;
+        let mut output = Vec::new();
+        for piece in data[FILE_HEADER_LEN.min(data.len())..].chunks(10_000) {
+            output.extend_from_slice(&decryptor.update(piece)?);
+        }
+        output.extend_from_slice(&decryptor.finish()?);
+        Ok(output)
+    }
+
+    fn encrypted_len(len: usize) -> usize {
+        let chunks = len.div_ceil(CHUNK).max(1);
+        FILE_HEADER_LEN + len + chunks * TAG_LEN
+    }
+
+    #[test]
+    fn round_trip_at_chunk_boundaries() {
+        for len in [0, 1, CHUNK - 1, CHUNK, CHUNK + 1, 2 * CHUNK, 2 * CHUNK + 1] {
+            let data = sample(len);
+            let encrypted = encrypt(&data, 10_000);
+            assert_eq!(encrypted.len(), encrypted_len(len), "{}", len);
+            assert_eq!(decrypt(&encrypted, PASSWORD).unwrap(), data, "{}", len);
+        }
+    }
+
+    #[test]
+    fn output_does_not_depend_on_upload_pieces() {
+        let data = sample(CHUNK + 1);
+        for piece in [1, CHUNK, CHUNK + 1] {
+            let encrypted = encrypt(&data, piece);
+            assert_eq!(encrypted.len(), encrypted_len(data.len()));
+            assert_eq!(decrypt(&encrypted, PASSWORD).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn wrong_password_fails() {
+        let encrypted = encrypt(&sample(100), CHUNK);
+        assert!(decrypt(&encrypted, "wrong password").is_err());
+    }
+
+    #[test]
+    fn missing_final_chunk_is_detected() {
+        let encrypted = encrypt(&sample(2 * CHUNK + 10), CHUNK);
+        let truncated = &encrypted[..FILE_HEADER_LEN + 2 * (CHUNK + TAG_LEN)];
+        assert!(decrypt(truncated, PASSWORD).is_err());
+    }
+
+    #[test]
+    fn truncated_final_chunk_is_detected() {
+        let encrypted = encrypt(&sample(CHUNK + 10), CHUNK);
+        assert!(decrypt(&encrypted[..encrypted.len() - 1], PASSWORD).is_err());
+    }
+
+    #[test]
+    fn flipped_ciphertext_byte_is_detected() {
+        let encrypted = encrypt(&sample(2 * CHUNK + 10), CHUNK);
+        for index in [FILE_HEADER_LEN, FILE_HEADER_LEN + CHUNK + TAG_LEN, encrypted.len() - 1] {
+            let mut tampered = encrypted.clone();
+            tampered[index] ^= 0x01;
+            assert!(decrypt(&tampered, PASSWORD).is_err(), "{}", index);
+        }
+    }
+
+    #[test]
+    fn flipped_header_byte_is_detected() {
+        let encrypted = encrypt(&sample(100), CHUNK);
+        // ソルト・ナンスの接頭辞は鍵やナンスを、チャンクの大きさは追加認証データを変える
+        for index in [17, 17 + SALT_LEN, FILE_HEADER_LEN - 1] {
+            let mut tampered = encrypted.clone();
+            tampered[index] ^= 0x01;
+            assert!(decrypt(&tampered, PASSWORD).is_err(), "{}", index);
+        }
+    }
+
+    #[test]
+    fn reordered_chunks_are_detected() {
+        let encrypted = encrypt(&sample(3 * CHUNK), CHUNK);
+        let chunk = CHUNK + TAG_LEN;
+        let mut reordered = encrypted[..FILE_HEADER_LEN].to_vec();
+        reordered.extend_from_slice(&encrypted[FILE_HEADER_LEN + chunk..FILE_HEADER_LEN + 2 * chunk]);
+        reordered.extend_from_slice(&encrypted[FILE_HEADER_LEN..FILE_HEADER_LEN + chunk]);
+        reordered.extend_from_slice(&encrypted[FILE_HEADER_LEN + 2 * chunk..]);
+        assert!(decrypt(&reordered, PASSWORD).is_err());
+    }
+
+    #[test]
+    fn short_or_foreign_data_is_rejected() {
+        assert!(decrypt(b"", PASSWORD).is_err());
+        let encrypted = encrypt(&sample(10), CHUNK);
+        assert!(decrypt(&encrypted[..FILE_HEADER_LEN - 1], PASSWORD).is_err());
+        assert!(decrypt(&sample(FILE_HEADER_LEN + 100), PASSWORD).is_err());
+    }
+
+    #[test]
+    fn output_name_round_trips() {
+        let encrypted = Mode::Encrypt.output_name("report.pdf");
+        assert_eq!(encrypted, "report.pdf.rdf");
+        assert_eq!(Mode::Decrypt.output_name(&encrypted), "report.pdf");
+        assert_eq!(Mode::Decrypt.output_name("report.pdf"), "report.pdf.decrypted");
+        assert_eq!(base_name("C:\\Users\\me\\report.pdf"), "report.pdf");
+    }
+}
@@ -0,0 +1,368 @@
+//! CSRF 対策（シンクロナイザートークン）
+//!
+//! トークンはセッションごとに1つ発行してセッションに保存する。セッションを持たないクライアントには
+//! ログイン画面を表示するときだけ発行し、それ以外のリクエストでセッションを作らない。
+//! ログインに成功したときはトークンを発行し直す（`renew`）。GET・HEAD・OPTIONS 以外の
+//! リクエストは、フォームの `csrf_token` フィールドまたは `X-CSRF-Token` ヘッダーの値が
+//! セッションのトークンと一致しなければ 403 を返す。ファイルをアップロードするフォーム
+//! （multipart/form-data）では `csrf_token` を最初のフィールドにする。
+//!
+//! テンプレートでは `{{ crate::routes::csrf::token() }}` でトークンを埋め込む。
+//! トークンは `verify` がリクエストの処理中だけタスクローカルに設定する。
+//!
+//! REST API（`/api/`）は Cookie ではなく APIトークンで認証するため対象外。
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use futures::StreamExt;
+use rand::RngCore;
+use tower_sessions::Session;
+use crate::{AppState, db};
+use crate::routes::{auth, context};
+
+const SESSION_CSRF_KEY: &str = "csrf_token";
+pub const FORM_FIELD: &str = "csrf_token";
+pub const HEADER_NAME: &str = "x-csrf-token";
+// トークンを探すために読み込むフォームの最大サイズ（axum の Form の既定の上限と同じ）
+const MAX_FORM_BODY_BYTES: usize = 2 * 1024 * 1024;
+// multipart の最初のフィールドを探すために先読みする最大サイズ
+const MAX_MULTIPART_PEEK_BYTES: usize = 8 * 1024;
+// セッションがなくてもトークンを発行する（ログインしていなくても表示する）フォームの画面
+const ANONYMOUS_FORM_PAGES: [&str; 1] = ["/login"];
+
+tokio::task_local! {
+    static CSRF_TOKEN: String;
+}
+
+/// 処理中のリクエストのトークン（テンプレートから呼ぶ）
+pub fn token() -> String {
+    CSRF_TOKEN.try_with(Clone::clone).unwrap_or_default()
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+// 一致するまでの時間からトークンを推測されないよう、すべてのバイトを比較する
+fn tokens_match(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// リクエストのトークンがセッションのトークンと一致するか（送られていなければ拒否）
+fn is_valid_token(expected: &str, actual: Option<&str>) -> bool {
+    actual.is_some_and(|actual| tokens_match(expected, actual))
+}
+
+/// トークンがまだない場合に発行してよいか（画面を表示するリクエストだけ）
+///
+/// 発行するとセッションが保存されるため、セッションのないクライアントにはフォームの画面を
+/// 表示するときだけ発行する（ログイン画面に転送されるだけのリクエストでセッションを作らない）。
+fn may_issue_token(method: &Method, path: &str, has_session: bool) -> bool {
+    is_safe_method(method) && (has_session || ANONYMOUS_FORM_PAGES.contains(&path))
+}
+
+/// セッションのトークンを返す（発行してよい場合で、まだなければ発行して保存する）
+async fn session_token(session: &Session, method: &Method, path: &str) -> Result<Option<String>, ()> {
+    match session.get::<String>(SESSION_CSRF_KEY).await {
+        Ok(Some(token)) => return Ok(Some(token)),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("CSRFトークンの読み込みに失敗: {:?}", e);
+            return Err(());
+        }
+    }
+    // 読み込み後も ID があれば、保存済みのセッションがある
+    if !may_issue_token(method, path, session.id().is_some()) {
+        return Ok(None);
+    }
+
+    let token = generate_token();
+    match session.insert(SESSION_CSRF_KEY, &token).await {
+        Ok(()) => Ok(Some(token)),
+        Err(e) => {
+            eprintln!("CSRFトークンの保存に失敗: {:?}", e);
+            Err(())
+        }
+    }
+}
+
+/// トークンを発行し直す（ログイン時に、ログイン前のトークンを使えないようにする）
+pub async fn renew(session: &Session) -> Result<(), tower_sessions::session::Error> {
+    session.insert(SESSION_CSRF_KEY, generate_token()).await
+}
+
+fn is_safe_method(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+fn is_form(request: &Request) -> bool {
+    request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"))
+}
+
+fn multipart_boundary(request: &Request) -> Option<String> {
+    let content_type = request.headers().get(header::CONTENT_TYPE)?.to_str().ok()?;
+    if !content_type.starts_with("multipart/form-data") {
+        return None;
+    }
+    content_type
+        .split(';')
+        .filter_map(|param| param.trim().strip_prefix("boundary="))
+        .map(|boundary| boundary.trim_matches('"').to_string())
+        .next()
+}
+
+/// multipart の最初のフィールドが `csrf_token` であればその値を返す
+fn multipart_token(data: &[u8], boundary: &str) -> Option<String> {
+    let data = data.strip_prefix(format!("--{}\r\n", boundary).as_bytes())?;
+    let headers_end = data.windows(4).position(|window| window == b"\r\n\r\n")?;
+    let headers = std::str::from_utf8(&data[..headers_end]).ok()?;
+    if !headers.contains(&format!("name=\"{}\"", FORM_FIELD)) {
+        return None;
+    }
+
+    let value = &data[headers_end + 4..];
+    let delimiter = format!("\r\n--{}", boundary);
+    let value_end = value.windows(delimiter.len()).position(|window| window == delimiter.as_bytes())?;
+    String::from_utf8(value[..value_end].to_vec()).ok()
+}
+
+/// multipart のボディの先頭を読んでトークンを取り出し、読んだ部分を戻したボディを返す
+///
+/// ファイルの本体は読み込まずにハンドラへそのまま流す。
+async fn peek_multipart_token(body: Body, boundary: &str) -> (Body, Option<String>) {
+    let mut stream = body.into_data_stream();
+    let mut prefix = Vec::new();
+    let mut token = None;
+    while prefix.len() < MAX_MULTIPART_PEEK_BYTES {
+        match stream.next().await {
+            Some(Ok(chunk)) => prefix.extend_from_slice(&chunk),
+            _ => break,
+        }
+        token = multipart_token(&prefix, boundary);
+        if token.is_some() {
+            break;
+        }
+    }
+
+    let prefix = futures::stream::once(async move { Ok::<_, axum::Error>(Bytes::from(prefix)) });
+    (Body::from_stream(prefix.chain(stream)), token)
+}
+
+fn form_token(body: &Bytes) -> Option<String> {
+    serde_urlencoded::from_bytes::<Vec<(String, String)>>(body)
+        .ok()?
+        .into_iter()
+        .find(|(name, _)| name == FORM_FIELD)
+        .map(|(_, value)| value)
+}
+
+/// トークンを発行・検証するミドルウェア
+pub async fn verify(
+    State(state): State<AppState>,
+    session: Session,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if path.starts_with("/static/") || path.starts_with("/api/") {
+        return next.run(request).await;
+    }
+
+    let Ok(expected) = session_token(&session, request.method(), path).await else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "セッションエラーが発生しました").into_response();
+    };
+    if is_safe_method(request.method()) {
+        return match expected {
+            Some(expected) => CSRF_TOKEN.scope(expected, next.run(request)).await,
+            None => next.run(request).await,
+        };
+    }
+    // トークンを発行していないセッションからの送信は常に拒否する
+    let Some(expected) = expected else {
+        return reject(&state, &session, request).await;
+    };
+
+    let header_token = request
+        .headers()
+        .get(HEADER_NAME)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    // フォームのトークンを読むためにボディを読み込み、ハンドラには同じ内容を渡し直す
+    let boundary = multipart_boundary(&request);
+    let (request, actual) = match (header_token, boundary) {
+        (Some(token), _) => (request, Some(token)),
+        (None, Some(boundary)) => {
+            let (parts, body) = request.into_parts();
+            let (body, token) = peek_multipart_token(body, &boundary).await;
+            (Request::from_parts(parts, body), token)
+        }
+        (None, None) if is_form(&request) => {
+            let (parts, body) = request.into_parts();
+            let Ok(bytes) = axum::body::to_bytes(body, MAX_FORM_BODY_BYTES).await else {
+                return (StatusCode::PAYLOAD_TOO_LARGE, "リクエストが大きすぎます").into_response();
+            };
+            let token = form_token(&bytes);
+            (Request::from_parts(parts, Body::from(bytes)), token)
+        }
+        (None, None) => (request, None),
+    };
+
+    if !is_valid_token(&expected, actual.as_deref()) {
+        return reject(&state, &session, request).await;
+    }
+    CSRF_TOKEN.scope(expected, next.run(request)).await
+}
+
+async fn reject(state: &AppState, session: &Session, request: Request) -> Response {
+    let (parts, _) = request.into_parts();
+    let client = context::client_info(&parts, state);
+    let username = auth::get_username(session).await.unwrap_or_else(|| "anonymous".to_string());
+
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        None,
+        &username,
+        "csrf_rejected",
+        Some(parts.uri.path()),
+        Some(&format!("Rejected {} {} with missing or invalid CSRF token", parts.method, parts.uri.path())),
+    ).await;
+
+    (
+        StatusCode::FORBIDDEN,
+        "CSRFトークンが無効です。ページを再読み込みしてからもう一度お試しください",
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDARY: &str = "----boundary1234";
+
+    fn multipart(fields: &[(&str, &str)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, value) in fields {
+            body.extend_from_slice(format!("--{}\r\n", BOUNDARY).as_bytes());
+            body.extend_from_slice(format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", name).as_bytes());
+            body.extend_from_slice(value.as_bytes());
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    #[test]
+    fn token_check() {
+        let expected = generate_token();
+        assert!(is_valid_token(&expected, Some(&expected)));
+        assert!(!is_valid_token(&expected, None));
+        assert!(!is_valid_token(&expected, Some("")));
+        assert!(!is_valid_token(&expected, Some(&generate_token())));
+        assert!(!is_valid_token(&expected, Some(&expected[..expected.len() - 1])));
+    }
+
+    #[test]
+    fn token_is_issued_without_a_session_only_for_the_login_form() {
+        assert!(may_issue_token(&Method::GET, "/login", false));
+        assert!(!may_issue_token(&Method::GET, "/", false));
+        assert!(!may_issue_token(&Method::GET, "/users", false));
+        assert!(!may_issue_token(&Method::HEAD, "/sysinfo", false));
+        assert!(!may_issue_token(&Method::POST, "/login", false));
+    }
+
+    #[test]
+    fn token_is_issued_for_pages_of_an_existing_session() {
+        assert!(may_issue_token(&Method::GET, "/", true));
+        // 送信時には発行しない（トークンのないセッションからの送信は拒否される）
+        assert!(!may_issue_token(&Method::POST, "/users/create", true));
+    }
+
+    #[test]
+    fn form_token_extraction() {
+        let body = Bytes::from("name=alice&csrf_token=abc%2Bdef&role=user");
+        assert_eq!(form_token(&body).as_deref(), Some("abc+def"));
+        assert_eq!(form_token(&Bytes::from("name=alice")), None);
+    }
+
+    #[test]
+    fn multipart_boundary_from_content_type() {
+        let request = Request::builder()
+            .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary=\"{}\"", BOUNDARY))
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(multipart_boundary(&request).as_deref(), Some(BOUNDARY));
+
+        let request = Request::builder()
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(multipart_boundary(&request), None);
+    }
+
+    #[test]
+    fn multipart_token_from_first_field() {
+        let body = multipart(&[("csrf_token", "secret-token"), ("file", "data")]);
+        assert_eq!(multipart_token(&body, BOUNDARY).as_deref(), Some("secret-token"));
+    }
+
+    #[test]
+    fn multipart_token_must_be_first_field() {
+        let body = multipart(&[("file", "data"), ("csrf_token", "secret-token")]);
+        assert_eq!(multipart_token(&body, BOUNDARY), None);
+    }
+
+    #[test]
+    fn multipart_token_incomplete_value() {
+        let body = multipart(&[("csrf_token", "secret-token")]);
+        let truncated = &body[..body.len() - format!("\r\n--{}--\r\n", BOUNDARY).len()];
+        assert_eq!(multipart_token(truncated, BOUNDARY), None);
+    }
+
+    #[tokio::test]
+    async fn peek_keeps_the_whole_body() {
+        let large = "x".repeat(MAX_MULTIPART_PEEK_BYTES * 2);
+        let body = multipart(&[("csrf_token", "secret-token"), ("file", &large)]);
+
+        // 小さなチャンクに分けて届いても、トークンを読んだ後のボディは元と同じ
+        let chunks: Vec<Result<Bytes, std::io::Error>> = body
+            .chunks(7)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let (rest, token) = peek_multipart_token(Body::from_stream(futures::stream::iter(chunks)), BOUNDARY).await;
+
+        assert_eq!(token.as_deref(), Some("secret-token"));
+        let bytes = axum::body::to_bytes(rest, usize::MAX).await.unwrap();
+        assert_eq!(bytes.as_ref(), body.as_slice());
+    }
+
+    #[tokio::test]
+    async fn peek_without_token() {
+        let body = multipart(&[("file", "data")]);
+        let (rest, token) = peek_multipart_token(Body::from(body.clone()), BOUNDARY).await;
+        assert_eq!(token, None);
+        let bytes = axum::body::to_bytes(rest, usize::MAX).await.unwrap();
+        assert_eq!(bytes.as_ref(), body.as_slice());
+    }
+}
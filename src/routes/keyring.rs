@@ -0,0 +1,109 @@
+//! 公開鍵暗号（X25519）と署名（Ed25519）で共通の鍵の登録・置き換え・削除
+//!
+//! 鍵はユーザーと鍵の種類（`db::KEY_TYPE_*`）ごとに1つだけ `keyring` テーブルに登録する。
+//! 置き換えと削除では、確認のチェックとページに表示していた公開鍵を求め、
+//! その後に別の操作で鍵が変わっていれば何もしない。
+
+use serde::Deserialize;
+use sqlx::MySqlPool;
+use crate::db;
+
+#[derive(Deserialize)]
+pub struct DeleteKeyForm {
+    /// ページに表示していた現在の公開鍵
+    current_public_key: String,
+    /// 削除の確認（チェックボックス）
+    confirm: Option<String>,
+}
+
+const KEY_CHANGED: &str = "鍵が変更されています。ページを読み込み直してください";
+const DATABASE_ERROR: &str = "データベースエラーが発生しました";
+
+/// エラーメッセージに使う鍵の呼び方
+fn key_name(key_type: &str) -> &'static str {
+    if key_type == db::KEY_TYPE_ED25519 { "署名用の鍵" } else { "鍵" }
+}
+
+/// 鍵がまだ登録されていない場合だけ登録する（登録済みの鍵は確認してから置き換える）
+pub async fn register(
+    pool: &MySqlPool,
+    user_id: i32,
+    key_type: &str,
+    public_key: &str,
+    encrypted_private_key: Option<&str>,
+) -> Result<(), String> {
+    match db::create_keyring_entry(pool, user_id, key_type, public_key, encrypted_private_key).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(format!(
+            "{}が登録済みです。新しい鍵にする場合は「鍵を置き換える」から置き換えてください",
+            key_name(key_type)
+        )),
+        Err(_) => Err(DATABASE_ERROR.into()),
+    }
+}
+
+/// 確認のチェックとページに表示していた公開鍵を確かめ、現在の鍵を返す
+///
+/// `operation` は確認を求めるメッセージに使う操作の名前（「置き換える」など）。
+pub async fn confirmed_entry(
+    pool: &MySqlPool,
+    user_id: i32,
+    key_type: &str,
+    confirmed: bool,
+    current_public_key: &str,
+    operation: &str,
+) -> Result<db::KeyringEntry, String> {
+    if !confirmed {
+        return Err(format!("鍵を{}ことを確認してください", operation));
+    }
+    let Ok(Some(entry)) = db::get_keyring_entry(pool, user_id, key_type).await else {
+        return Err(format!("{}が登録されていません", key_name(key_type)));
+    };
+    if entry.public_key != current_public_key {
+        return Err(KEY_CHANGED.into());
+    }
+    Ok(entry)
+}
+
+/// `confirmed_entry` で確かめた鍵を新しい鍵で置き換える
+pub async fn replace(
+    pool: &MySqlPool,
+    entry: &db::KeyringEntry,
+    public_key: &str,
+    encrypted_private_key: Option<&str>,
+) -> Result<(), String> {
+    if public_key == entry.public_key {
+        return Err("現在の鍵と同じ公開鍵です".into());
+    }
+
+    match db::replace_keyring_entry(
+        pool,
+        entry.user_id,
+        &entry.key_type,
+        &entry.public_key,
+        public_key,
+        encrypted_private_key,
+    )
+    .await
+    {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(KEY_CHANGED.into()),
+        Err(_) => Err(DATABASE_ERROR.into()),
+    }
+}
+
+/// 確認のチェックとページに表示していた公開鍵を確かめて鍵を削除し、削除した鍵を返す
+pub async fn delete(
+    pool: &MySqlPool,
+    user_id: i32,
+    key_type: &str,
+    form: &DeleteKeyForm,
+) -> Result<db::KeyringEntry, String> {
+    let entry = confirmed_entry(pool, user_id, key_type, form.confirm.is_some(), &form.current_public_key, "削除する").await?;
+
+    match db::delete_keyring_entry(pool, user_id, key_type, &entry.public_key).await {
+        Ok(true) => Ok(entry),
+        Ok(false) => Err(KEY_CHANGED.into()),
+        Err(_) => Err(DATABASE_ERROR.into()),
+    }
+}
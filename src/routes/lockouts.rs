@@ -0,0 +1,185 @@
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    response::{Html, IntoResponse, Redirect},
+};
+use chrono::{DateTime, Local, Utc};
+use crate::{AppState, db};
+use crate::routes::context::{ClientContext, CurrentUser};
+
+#[derive(Template)]
+#[template(path = "lockouts.html")]
+pub struct LockoutsTemplate {
+    pub lockouts: Vec<LockoutDisplay>,
+    pub max_failures_per_account: i64,
+    pub max_failures_per_ip: i64,
+    pub window_minutes: i64,
+    pub lockout_minutes: i64,
+    pub message: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct LockoutDisplay {
+    pub id: i32,
+    pub scope: String,
+    pub subject: String,
+    pub failure_count: i32,
+    pub locked_until: String,
+    pub created_at: String,
+}
+
+fn format_local(time: DateTime<Utc>) -> String {
+    let local_time: DateTime<Local> = DateTime::from(time);
+    local_time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+impl From<db::LoginLockout> for LockoutDisplay {
+    fn from(lockout: db::LoginLockout) -> Self {
+        Self {
+            id: lockout.id,
+            scope: lockout.scope,
+            subject: lockout.subject,
+            failure_count: lockout.failure_count,
+            locked_until: format_local(lockout.locked_until),
+            created_at: format_local(lockout.created_at),
+        }
+    }
+}
+
+/// アカウントまたはIPに有効なロックがあれば返す
+pub async fn find_active_lockout(
+    state: &AppState,
+    client: &db::ClientInfo,
+    username: &str,
+) -> Result<Option<db::LoginLockout>, sqlx::Error> {
+    if let Some(lockout) = db::get_active_lockout(&state.db, db::LOCKOUT_SCOPE_ACCOUNT, username).await? {
+        return Ok(Some(lockout));
+    }
+    match &client.ip_address {
+        Some(ip_address) => db::get_active_lockout(&state.db, db::LOCKOUT_SCOPE_IP, ip_address).await,
+        None => Ok(None),
+    }
+}
+
+/// ログイン失敗後に呼び出し、失敗回数が上限に達したアカウント・IPをロックする
+pub async fn record_login_failure(state: &AppState, client: &db::ClientInfo, username: &str) {
+    let config = &state.lockout;
+
+    let mut targets = vec![(db::LOCKOUT_SCOPE_ACCOUNT, username, config.max_failures_per_account)];
+    if let Some(ip_address) = &client.ip_address {
+        targets.push((db::LOCKOUT_SCOPE_IP, ip_address, config.max_failures_per_ip));
+    }
+
+    for (scope, subject, max_failures) in targets {
+        if max_failures <= 0 {
+            continue;
+        }
+        if let Err(e) = lock_if_exceeded(state, client, username, scope, subject, max_failures).await {
+            eprintln!("ロックアウト判定エラー: {} {}: {:?}", scope, subject, e);
+        }
+    }
+}
+
+async fn lock_if_exceeded(
+    state: &AppState,
+    client: &db::ClientInfo,
+    username: &str,
+    scope: &str,
+    subject: &str,
+    max_failures: i64,
+) -> Result<(), sqlx::Error> {
+    // 集計期間の開始時刻（直前のロック以降の失敗のみを数える）
+    let mut since = Utc::now() - state.lockout.window;
+    if let Some(reset_at) = db::last_lockout_reset(&state.db, scope, subject).await? {
+        since = since.max(reset_at);
+    }
+
+    let failures = if scope == db::LOCKOUT_SCOPE_ACCOUNT {
+        db::count_failed_logins_by_username(&state.db, subject, since).await?
+    } else {
+        db::count_failed_logins_by_ip(&state.db, subject, since).await?
+    };
+
+    if failures < max_failures {
+        return Ok(());
+    }
+
+    let locked_until = Utc::now() + state.lockout.lockout_duration;
+    db::create_lockout(&state.db, scope, subject, failures, locked_until).await?;
+
+    eprintln!("ロック: {} {} ({}回失敗)", scope, subject, failures);
+
+    // 監査ログに記録
+    let action = if scope == db::LOCKOUT_SCOPE_ACCOUNT { "account_locked" } else { "ip_locked" };
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        client,
+        None,
+        username,
+        action,
+        Some("/login"),
+        Some(&format!("Locked {} '{}' until {} after {} failed logins", scope, subject, locked_until.to_rfc3339(), failures)),
+    ).await;
+
+    Ok(())
+}
+
+async fn render_lockouts_page(state: &AppState, message: Option<String>) -> Result<Html<String>, Redirect> {
+    let lockouts = db::list_active_lockouts(&state.db)
+        .await
+        .map_err(|_| Redirect::to("/"))?;
+
+    let template = LockoutsTemplate {
+        lockouts: lockouts.into_iter().map(|lockout| lockout.into()).collect(),
+        max_failures_per_account: state.lockout.max_failures_per_account,
+        max_failures_per_ip: state.lockout.max_failures_per_ip,
+        window_minutes: state.lockout.window.num_minutes(),
+        lockout_minutes: state.lockout.lockout_duration.num_minutes(),
+        message,
+    };
+
+    match template.render() {
+        Ok(html) => Ok(Html(html)),
+        Err(_) => Err(Redirect::to("/")),
+    }
+}
+
+pub async fn list_lockouts(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Redirect> {
+    render_lockouts_page(&state, None).await
+}
+
+pub async fn clear_lockout(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, Redirect> {
+    let resource = format!("/lockouts/{}/clear", id);
+
+    let lockout = match db::get_lockout(&state.db, id).await {
+        Ok(Some(lockout)) if lockout.cleared_at.is_none() => lockout,
+        _ => return render_lockouts_page(&state, Some("ロックが見つかりません".into())).await,
+    };
+
+    if db::clear_lockout(&state.db, lockout.id, &current_user.username).await.is_err() {
+        return Err(Redirect::to("/"));
+    }
+
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "lockout_cleared",
+        Some(&resource),
+        Some(&format!("Cleared lockout of {} '{}'", lockout.scope, lockout.subject)),
+    ).await;
+
+    let message = format!("{} '{}' のロックを解除しました", lockout.scope, lockout.subject);
+    render_lockouts_page(&state, Some(message)).await
+}
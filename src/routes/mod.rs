@@ -1,7 +1,23 @@
+pub mod api;
+pub mod api_tokens;
 pub mod audit;
 pub mod auth;
+pub mod authz;
+pub mod context;
 pub mod crypto;
+pub mod crypto_file;
+pub mod csrf;
 pub mod home;
+pub mod keyring;
+pub mod lockouts;
+pub mod oidc;
+pub mod password;
+pub mod public_key;
+pub mod roles;
+pub mod sessions;
+pub mod signing;
 pub mod sysinfo;
 pub mod time;
+pub mod two_factor;
 pub mod users;
+pub mod vault;
@@ -0,0 +1,162 @@
+//! OpenID Connect によるシングルサインオン
+//!
+//! 認可コードフロー（`rust_dashboard::oidc`）の `state`・`nonce`・`code_verifier` は
+//! セッションに保存し、コールバックで照合してから破棄する。
+//! ユーザーは初回ログイン時に作成し、ロールは IDトークンのクレームから毎回決め直す。
+//! 二要素認証を有効にしているユーザーは、パスワードでのログインと同じく確認コードの入力に進む。
+
+use axum::{
+    extract::{Query, State},
+    response::Redirect,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use tower_sessions::Session;
+use rust_dashboard::oidc::{self, OidcFlow};
+use crate::{AppState, db};
+use crate::routes::auth::{self, LoginTemplate};
+use crate::routes::context::ClientContext;
+use crate::routes::lockouts;
+
+// 認可リクエストからコールバックまでの間に保存しておく値
+const SESSION_OIDC_FLOW_KEY: &str = "oidc_flow";
+
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// ログインを拒否して監査ログに記録する（パスワードでのログインと同じく失敗回数に数える）
+async fn reject(state: &AppState, client: &db::ClientInfo, username: &str, error: &str, details: &str) -> LoginTemplate {
+    eprintln!("OIDCログイン失敗: {} ({})", username, details);
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        client,
+        None,
+        username,
+        "login_failed",
+        Some("/login/oidc/callback"),
+        Some(&format!("OIDC login failed: {}", details)),
+    ).await;
+
+    lockouts::record_login_failure(state, client, username).await;
+
+    LoginTemplate::error(state, error)
+}
+
+/// 発行者の認可エンドポイントにリダイレクトする
+pub async fn start(
+    State(state): State<AppState>,
+    session: Session,
+) -> Result<Redirect, LoginTemplate> {
+    let Some(config) = &state.oidc else {
+        return Ok(Redirect::to("/login"));
+    };
+
+    let (url, flow) = match oidc::begin(config).await {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("OIDCディスカバリーエラー: {:?}", e);
+            return Err(LoginTemplate::error(&state, "認証サーバーに接続できませんでした"));
+        }
+    };
+    if let Err(e) = session.insert(SESSION_OIDC_FLOW_KEY, &flow).await {
+        eprintln!("セッション保存エラー: {:?}", e);
+        return Err(LoginTemplate::error(&state, "セッションエラーが発生しました"));
+    }
+
+    Ok(Redirect::to(&url))
+}
+
+/// 発行者からのリダイレクトを受け取り、IDトークンを検証してログインを完了する
+pub async fn callback(
+    State(state): State<AppState>,
+    ClientContext(client): ClientContext,
+    session: Session,
+    Query(query): Query<CallbackQuery>,
+) -> Result<Redirect, LoginTemplate> {
+    let Some(config) = &state.oidc else {
+        return Ok(Redirect::to("/login"));
+    };
+    const FAILED: &str = "シングルサインオンに失敗しました";
+
+    // 保存した値は1回だけ使う
+    let flow = session.remove::<OidcFlow>(SESSION_OIDC_FLOW_KEY).await.ok().flatten();
+    let Some(flow) = flow else {
+        return Err(reject(&state, &client, "anonymous", FAILED, "no authorization request in session").await);
+    };
+
+    if let Some(error) = &query.error {
+        let details = format!("provider returned {} {}", error, query.error_description.as_deref().unwrap_or(""));
+        return Err(reject(&state, &client, "anonymous", FAILED, details.trim_end()).await);
+    }
+    let (Some(code), Some(returned_state)) = (&query.code, &query.state) else {
+        return Err(reject(&state, &client, "anonymous", FAILED, "missing code or state").await);
+    };
+    if *returned_state != flow.state {
+        return Err(reject(&state, &client, "anonymous", FAILED, "state mismatch").await);
+    }
+
+    let oidc::VerifiedIdToken { issuer, claims } = match oidc::finish(config, &flow, code).await {
+        Ok(verified) => verified,
+        Err(e) => return Err(reject(&state, &client, "anonymous", FAILED, &e.to_string()).await),
+    };
+
+    let subject = claims.get("sub").and_then(Value::as_str).unwrap_or_default();
+    let Some(username) = oidc::claim_values(&claims, &config.username_claim).into_iter().next() else {
+        let details = format!("claim '{}' is missing (sub: {})", config.username_claim, subject);
+        return Err(reject(&state, &client, "anonymous", FAILED, &details).await);
+    };
+
+    // アカウントまたは送信元IPがロック中であればユーザーを作成・更新しない
+    match lockouts::find_active_lockout(&state, &client, &username).await {
+        Ok(None) => {}
+        Ok(Some(lockout)) => {
+            let _ = db::create_audit_log(
+                &state.db,
+                &state.audit_events,
+                &client,
+                None,
+                &username,
+                "login_blocked",
+                Some("/login/oidc/callback"),
+                Some(&format!("OIDC login rejected while {} '{}' is locked", lockout.scope, lockout.subject)),
+            ).await;
+            return Err(LoginTemplate::error(&state, "ログイン失敗が続いたため一時的にロックされています。しばらくしてから再度お試しください"));
+        }
+        Err(_) => return Err(LoginTemplate::error(&state, "データベースエラーが発生しました")),
+    }
+
+    let Some(role) = config.role_mapping.resolve(&oidc::claim_values(&claims, &config.role_claim)) else {
+        let details = format!("no role is mapped from claim '{}'", config.role_claim);
+        return Err(reject(&state, &client, &username, "このアカウントにはダッシュボードの利用が許可されていません", &details).await);
+    };
+
+    let user = match auth::sync_external_user(&state, &client, &issuer, subject, &username, role).await {
+        Ok(user) => user,
+        Err(error) => return Err(reject(&state, &client, &username, &error, "could not provision local user").await),
+    };
+
+    if user.is_disabled() {
+        let _ = db::create_audit_log(
+            &state.db,
+            &state.audit_events,
+            &client,
+            Some(user.id),
+            &user.username,
+            "login_blocked",
+            None,
+            Some("OIDC login rejected for disabled account"),
+        ).await;
+        return Err(LoginTemplate::error(&state, auth::DISABLED_ACCOUNT_ERROR));
+    }
+
+    // 二要素認証が有効なユーザーは確認コードを入力してからログインを完了する
+    auth::continue_login(&state, &client, &session, &user)
+        .await
+        .map_err(|error| LoginTemplate::error(&state, error))
+}
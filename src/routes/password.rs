@@ -0,0 +1,143 @@
+use askama::Template;
+use axum::{
+    extract::{Form, State},
+    response::{Html, IntoResponse, Redirect},
+};
+use serde::Deserialize;
+use tower_sessions::Session;
+use crate::{AppState, db};
+use crate::routes::{api_tokens, auth, lockouts, sessions};
+use crate::routes::context::{ClientContext, CurrentUser};
+
+#[derive(Template)]
+#[template(path = "password.html")]
+pub struct PasswordTemplate {
+    /// 管理者にパスワードの変更を求められている
+    pub required: bool,
+    /// 外部の認証基盤のユーザーでパスワードを変更できない
+    pub external: bool,
+    pub min_length: usize,
+    pub require_letters_and_digits: bool,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordForm {
+    current_password: String,
+    new_password: String,
+    confirm_password: String,
+}
+
+fn render_password_page(
+    state: &AppState,
+    user: &db::User,
+    message: Option<String>,
+    error: Option<String>,
+) -> Result<Html<String>, Redirect> {
+    let template = PasswordTemplate {
+        required: user.must_change_password,
+        external: !user.has_local_password(),
+        min_length: state.password_policy.min_length,
+        require_letters_and_digits: state.password_policy.require_letters_and_digits,
+        message,
+        error,
+    };
+
+    match template.render() {
+        Ok(html) => Ok(Html(html)),
+        Err(_) => Err(Redirect::to("/")),
+    }
+}
+
+pub async fn page(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+) -> Result<impl IntoResponse, Redirect> {
+    render_password_page(&state, &current_user, None, None)
+}
+
+pub async fn change(
+    State(state): State<AppState>,
+    CurrentUser(mut current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    session: Session,
+    Form(form): Form<ChangePasswordForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    if !current_user.has_local_password() {
+        return render_password_page(&state, &current_user, None, None);
+    }
+
+    // 現在のパスワードの総当たりを防ぐため、ログインと同じロックを適用する
+    match lockouts::find_active_lockout(&state, &client, &current_user.username).await {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            let error = "失敗が続いたため一時的にロックされています。しばらくしてから再度お試しください";
+            return render_password_page(&state, &current_user, None, Some(error.into()));
+        }
+        Err(_) => {
+            return render_password_page(&state, &current_user, None, Some("データベースエラーが発生しました".into()));
+        }
+    }
+
+    // セッションを乗っ取られた場合に備え、現在のパスワードを確認する
+    if !auth::verify_password(&current_user.password_hash, &form.current_password) {
+        let _ = db::create_audit_log(
+            &state.db,
+            &state.audit_events,
+            &client,
+            Some(current_user.id),
+            &current_user.username,
+            "password_change_failed",
+            Some("/account/password"),
+            Some("Current password did not match"),
+        ).await;
+        lockouts::record_login_failure(&state, &client, &current_user.username).await;
+        return render_password_page(&state, &current_user, None, Some("現在のパスワードが間違っています".into()));
+    }
+    if form.new_password != form.confirm_password {
+        return render_password_page(&state, &current_user, None, Some("新しいパスワードが確認用と一致しません".into()));
+    }
+    if form.new_password == form.current_password {
+        return render_password_page(&state, &current_user, None, Some("現在と同じパスワードは使用できません".into()));
+    }
+    if let Err(error) = state.password_policy.validate(&form.new_password, &current_user.username) {
+        return render_password_page(&state, &current_user, None, Some(error));
+    }
+
+    let Ok(hash) = state.password_hashing.hash(&form.new_password) else {
+        return render_password_page(&state, &current_user, None, Some("パスワードのハッシュ化に失敗しました".into()));
+    };
+    if db::update_user_password(&state.db, current_user.id, &hash, false).await.is_err() {
+        return render_password_page(&state, &current_user, None, Some("データベースエラーが発生しました".into()));
+    }
+
+    // 変更前のセッションIDを使えないようにする
+    if let Err(e) = session.cycle_id().await {
+        eprintln!("セッションID更新エラー: {:?}", e);
+    }
+
+    // 監査ログに記録（パスワード自体は記録しない）
+    let details = if current_user.must_change_password {
+        "Changed own password as required by administrator"
+    } else {
+        "Changed own password"
+    };
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "password_change",
+        Some("/account/password"),
+        Some(details),
+    ).await;
+
+    // 他の端末のセッションは失効させる（このセッションは振り直したIDで保存される）
+    sessions::revoke_all(&state, &client, None, &current_user, Some(&session), "password changed").await;
+    api_tokens::revoke_all(&state, &client, &current_user, &current_user, "password changed").await;
+
+    current_user.must_change_password = false;
+    render_password_page(&state, &current_user, Some("パスワードを変更しました".into()), None)
+}
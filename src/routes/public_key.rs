@@ -0,0 +1,603 @@
+//! 公開鍵暗号（X25519）による暗号化/復号化
+//!
+//! 暗号文は age（https://age-encryption.org/v1）の ASCII armor 形式で、`age -d` でも復号化できる。
+//! 受信者の公開鍵はユーザーごとに `keyring` テーブルに登録する。ダッシュボードで生成した
+//! 秘密鍵はユーザーが決めたパスフレーズで暗号化して保存し、復号化のたびにパスフレーズで開く。
+//! 公開鍵だけを登録した場合、秘密鍵は手元の age で管理する。
+
+use askama::Template;
+use age::armor::{ArmoredReader, ArmoredWriter, Format};
+use age::secrecy::ExposeSecret;
+use axum::{
+    extract::{Form, State},
+    response::{Html, IntoResponse, Redirect},
+};
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+use std::io::{Read, Write};
+use std::str::FromStr;
+use crate::{AppState, db};
+use crate::routes::{crypto, keyring};
+use crate::routes::context::{ClientContext, CurrentUser};
+
+// 1回に指定できる受信者の上限
+const MAX_RECIPIENTS: usize = 50;
+
+#[derive(Template)]
+#[template(path = "crypto_public.html")]
+pub struct PublicKeyTemplate {
+    pub own_key: Option<OwnKeyDisplay>,
+    pub recipients: Vec<RecipientOption>,
+    pub min_passphrase_length: usize,
+    /// 生成した秘密鍵（生成した直後に1回だけ表示する）
+    pub generated_identity: Option<String>,
+    pub encrypted_text: Option<String>,
+    pub decrypted_text: Option<String>,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+pub struct OwnKeyDisplay {
+    pub public_key: String,
+    pub has_private_key: bool,
+    pub created_at: String,
+}
+
+pub struct RecipientOption {
+    pub username: String,
+    pub public_key: String,
+    pub current: bool,
+}
+
+#[derive(Deserialize)]
+pub struct GenerateKeyForm {
+    passphrase: String,
+    confirm_passphrase: String,
+}
+
+#[derive(Deserialize)]
+pub struct ImportKeyForm {
+    public_key: String,
+}
+
+#[derive(Deserialize)]
+pub struct ReplaceKeyForm {
+    /// ページに表示していた現在の公開鍵
+    current_public_key: String,
+    /// 置き換えの確認（チェックボックス）
+    confirm: Option<String>,
+    /// `generate`（鍵ペアを生成）または `import`（公開鍵を登録）
+    method: String,
+    #[serde(default)]
+    passphrase: String,
+    #[serde(default)]
+    confirm_passphrase: String,
+    #[serde(default)]
+    public_key: String,
+}
+
+#[derive(Deserialize)]
+pub struct DecryptForm {
+    ciphertext: String,
+    passphrase: String,
+}
+
+/// ページに表示する処理結果
+#[derive(Default)]
+struct PageOutput {
+    generated_identity: Option<String>,
+    encrypted_text: Option<String>,
+    decrypted_text: Option<String>,
+    message: Option<String>,
+    error: Option<String>,
+}
+
+impl PageOutput {
+    fn message(message: impl Into<String>) -> Self {
+        Self { message: Some(message.into()), ..Default::default() }
+    }
+
+    fn error(error: impl Into<String>) -> Self {
+        Self { error: Some(error.into()), ..Default::default() }
+    }
+}
+
+async fn render_page(state: &AppState, user: &db::User, output: PageOutput) -> Result<Html<String>, Redirect> {
+    let own_key = db::get_keyring_entry(&state.db, user.id, db::KEY_TYPE_X25519)
+        .await
+        .map_err(|_| Redirect::to("/crypto"))?;
+    let entries = db::list_keyring(&state.db, db::KEY_TYPE_X25519)
+        .await
+        .map_err(|_| Redirect::to("/crypto"))?;
+
+    let template = PublicKeyTemplate {
+        own_key: own_key.map(|entry| OwnKeyDisplay {
+            has_private_key: entry.has_private_key(),
+            created_at: DateTime::<Local>::from(entry.created_at).format("%Y-%m-%d %H:%M:%S").to_string(),
+            public_key: entry.public_key,
+        }),
+        recipients: entries
+            .into_iter()
+            .map(|entry| RecipientOption {
+                current: entry.user_id == user.id,
+                username: entry.username,
+                public_key: entry.public_key,
+            })
+            .collect(),
+        min_passphrase_length: state.password_policy.min_length,
+        generated_identity: output.generated_identity,
+        encrypted_text: output.encrypted_text,
+        decrypted_text: output.decrypted_text,
+        message: output.message,
+        error: output.error,
+    };
+
+    match template.render() {
+        Ok(html) => Ok(Html(html)),
+        Err(_) => Err(Redirect::to("/crypto")),
+    }
+}
+
+async fn audit(state: &AppState, client: &db::ClientInfo, user: &db::User, action: &str, resource: &str, details: &str) {
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        client,
+        Some(user.id),
+        &user.username,
+        action,
+        Some(resource),
+        Some(details),
+    ).await;
+}
+
+/// age の形式で受信者ごとに暗号化する
+fn encrypt_to(recipients: &[age::x25519::Recipient], plaintext: &str) -> anyhow::Result<String> {
+    let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|recipient| recipient as &dyn age::Recipient))?;
+
+    let mut output = Vec::new();
+    let armor = ArmoredWriter::wrap_output(&mut output, Format::AsciiArmor)?;
+    let mut writer = encryptor.wrap_output(armor)?;
+    writer.write_all(plaintext.as_bytes())?;
+    writer.finish()?.finish()?;
+
+    Ok(String::from_utf8(output)?)
+}
+
+fn decrypt_with(identity: &age::x25519::Identity, ciphertext: &str) -> anyhow::Result<String> {
+    let decryptor = age::Decryptor::new(ArmoredReader::new(ciphertext.trim().as_bytes()))?;
+    if decryptor.is_scrypt() {
+        anyhow::bail!("パスフレーズで暗号化された age のデータには対応していません");
+    }
+
+    let mut reader = decryptor.decrypt(std::iter::once(identity as &dyn age::Identity))?;
+    let mut plaintext = Vec::new();
+    reader.read_to_end(&mut plaintext)?;
+
+    String::from_utf8(plaintext).map_err(|e| anyhow::anyhow!("UTF-8変換に失敗: {}", e))
+}
+
+pub async fn index(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+) -> Result<impl IntoResponse, Redirect> {
+    render_page(&state, &current_user, PageOutput::default()).await
+}
+
+/// 登録する鍵
+struct NewKey {
+    public_key: String,
+    encrypted_private_key: Option<String>,
+    /// 生成した秘密鍵（生成した直後に1回だけ表示する）
+    identity: Option<String>,
+}
+
+impl NewKey {
+    /// 鍵ペアを生成し、秘密鍵をパスフレーズで暗号化する
+    async fn generate(state: &AppState, user: &db::User, passphrase: &str, confirm_passphrase: &str) -> Result<Self, String> {
+        if passphrase != confirm_passphrase {
+            return Err("パスフレーズが確認用と一致しません".into());
+        }
+        state.password_policy.validate(passphrase, &user.username)?;
+
+        let identity = age::x25519::Identity::generate();
+        let secret_key = identity.to_string();
+        let (secret, passphrase) = (secret_key.expose_secret().to_string(), passphrase.to_string());
+        let encrypted_private_key = crypto::blocking(move || crypto::encrypt_string(&secret, &passphrase))
+            .await
+            .map_err(|e| format!("鍵の暗号化に失敗しました: {}", e))?;
+
+        Ok(Self {
+            public_key: identity.to_public().to_string(),
+            encrypted_private_key: Some(encrypted_private_key),
+            identity: Some(secret_key.expose_secret().to_string()),
+        })
+    }
+
+    /// 手元の age で作成した公開鍵を読み取る
+    fn import(public_key: &str) -> Result<Self, String> {
+        let recipient = age::x25519::Recipient::from_str(public_key.trim())
+            .map_err(|_| "公開鍵の形式が正しくありません（age1... の形式で入力してください）".to_string())?;
+
+        Ok(Self {
+            public_key: recipient.to_string(),
+            encrypted_private_key: None,
+            identity: None,
+        })
+    }
+
+    /// 監査ログに残す鍵の説明（秘密鍵とパスフレーズは含めない）
+    fn describe(&self) -> String {
+        if self.identity.is_some() {
+            format!("X25519 key pair (public key: {})", self.public_key)
+        } else {
+            format!("X25519 public key {}", self.public_key)
+        }
+    }
+}
+
+/// 鍵がまだ登録されていない場合だけ登録する（登録済みの鍵は replace_key で確認してから置き換える）
+async fn register_key(
+    state: &AppState,
+    client: &db::ClientInfo,
+    user: &db::User,
+    key: Result<NewKey, String>,
+    action: &str,
+    resource: &str,
+    message: &str,
+) -> Result<Html<String>, Redirect> {
+    let key = match key {
+        Ok(key) => key,
+        Err(error) => return render_page(state, user, PageOutput::error(error)).await,
+    };
+
+    let registered = keyring::register(
+        &state.db,
+        user.id,
+        db::KEY_TYPE_X25519,
+        &key.public_key,
+        key.encrypted_private_key.as_deref(),
+    )
+    .await;
+    if let Err(error) = registered {
+        return render_page(state, user, PageOutput::error(error)).await;
+    }
+
+    // 監査ログに記録
+    let verb = if key.identity.is_some() { "Generated" } else { "Registered" };
+    audit(state, client, user, action, resource, &format!("{} {}", verb, key.describe())).await;
+
+    let output = PageOutput { generated_identity: key.identity, ..PageOutput::message(message) };
+    render_page(state, user, output).await
+}
+
+/// 鍵ペアを生成し、秘密鍵をパスフレーズで暗号化して保存する
+pub async fn generate_key(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Form(form): Form<GenerateKeyForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let key = NewKey::generate(&state, &current_user, &form.passphrase, &form.confirm_passphrase).await;
+    register_key(&state, &client, &current_user, key, "keyring_generate", "/crypto/public/keys/generate", "鍵ペアを生成しました").await
+}
+
+/// 手元の age で作成した公開鍵を登録する
+pub async fn import_key(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Form(form): Form<ImportKeyForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let key = NewKey::import(&form.public_key);
+    register_key(&state, &client, &current_user, key, "keyring_import", "/crypto/public/keys/import", "公開鍵を登録しました").await
+}
+
+/// 登録済みの鍵を新しい鍵ペアまたは公開鍵で置き換える
+///
+/// 以前の鍵宛ての暗号文はダッシュボードで復号化できなくなるため、確認のチェックと、
+/// ページに表示していた公開鍵（その後に別の操作で鍵が変わっていれば置き換えない）を求める。
+pub async fn replace_key(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Form(form): Form<ReplaceKeyForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let entry = keyring::confirmed_entry(
+        &state.db,
+        current_user.id,
+        db::KEY_TYPE_X25519,
+        form.confirm.is_some(),
+        &form.current_public_key,
+        "置き換える",
+    )
+    .await;
+    let entry = match entry {
+        Ok(entry) => entry,
+        Err(error) => return render_page(&state, &current_user, PageOutput::error(error)).await,
+    };
+
+    let key = match form.method.as_str() {
+        "generate" => NewKey::generate(&state, &current_user, &form.passphrase, &form.confirm_passphrase).await,
+        "import" => NewKey::import(&form.public_key),
+        _ => Err("置き換える方法が正しくありません".to_string()),
+    };
+    let key = match key {
+        Ok(key) => key,
+        Err(error) => return render_page(&state, &current_user, PageOutput::error(error)).await,
+    };
+    if let Err(error) = keyring::replace(&state.db, &entry, &key.public_key, key.encrypted_private_key.as_deref()).await {
+        return render_page(&state, &current_user, PageOutput::error(error)).await;
+    }
+
+    // 監査ログに記録（置き換える前の鍵も残す）
+    let details = format!("Replaced X25519 key {} with {}", entry.public_key, key.describe());
+    audit(&state, &client, &current_user, "keyring_replace", "/crypto/public/keys/replace", &details).await;
+
+    let output = PageOutput { generated_identity: key.identity, ..PageOutput::message("鍵を置き換えました") };
+    render_page(&state, &current_user, output).await
+}
+
+/// 登録済みの鍵を削除する
+///
+/// 置き換えと同じく、確認のチェックとページに表示していた公開鍵を求める。
+pub async fn delete_key(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Form(form): Form<keyring::DeleteKeyForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let entry = match keyring::delete(&state.db, current_user.id, db::KEY_TYPE_X25519, &form).await {
+        Ok(entry) => entry,
+        Err(error) => return render_page(&state, &current_user, PageOutput::error(error)).await,
+    };
+
+    let details = format!("Deleted X25519 key {}", entry.public_key);
+    audit(&state, &client, &current_user, "keyring_delete", "/crypto/public/keys/delete", &details).await;
+
+    render_page(&state, &current_user, PageOutput::message("鍵を削除しました")).await
+}
+
+/// 選択したユーザーの鍵と入力した公開鍵から受信者を集め、監査ログに残す受信者の説明とともに返す
+fn collect_recipients(
+    entries: &[db::KeyringEntry],
+    selected: &[&str],
+    extra_recipients: &str,
+) -> Result<(Vec<age::x25519::Recipient>, String), String> {
+    let mut recipients = Vec::new();
+    let mut names = Vec::new();
+    for entry in entries.iter().filter(|entry| selected.contains(&entry.username.as_str())) {
+        match age::x25519::Recipient::from_str(&entry.public_key) {
+            Ok(recipient) => {
+                recipients.push(recipient);
+                names.push(entry.username.clone());
+            }
+            Err(_) => eprintln!("登録済みの公開鍵を読み込めません: {}", entry.username),
+        }
+    }
+
+    // キーリングにない相手の公開鍵も1行に1つ指定できる
+    let mut external = 0;
+    for line in extra_recipients.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let recipient = age::x25519::Recipient::from_str(line)
+            .map_err(|_| format!("公開鍵の形式が正しくありません: {}", line))?;
+        recipients.push(recipient);
+        external += 1;
+    }
+
+    if recipients.is_empty() {
+        return Err("受信者を1人以上選択してください".into());
+    }
+    if recipients.len() > MAX_RECIPIENTS {
+        return Err(format!("受信者は{}人までです", MAX_RECIPIENTS));
+    }
+
+    let mut detail = names.join(", ");
+    if external > 0 {
+        if !detail.is_empty() {
+            detail.push_str(", ");
+        }
+        detail.push_str(&format!("{} external key(s)", external));
+    }
+
+    Ok((recipients, detail))
+}
+
+/// 選択したユーザー（と入力した公開鍵）を受信者として暗号化する
+///
+/// 受信者はチェックボックスで複数選択するため、フォームはキーと値の組の一覧で受け取る。
+pub async fn encrypt(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Form(form): Form<Vec<(String, String)>>,
+) -> Result<impl IntoResponse, Redirect> {
+    let field = |name: &str| {
+        form.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_default()
+    };
+    let selected: Vec<&str> = form
+        .iter()
+        .filter(|(key, _)| key == "recipient")
+        .map(|(_, value)| value.as_str())
+        .collect();
+    let plaintext = field("plaintext");
+
+    let Ok(entries) = db::list_keyring(&state.db, db::KEY_TYPE_X25519).await else {
+        return render_page(&state, &current_user, PageOutput::error("データベースエラーが発生しました")).await;
+    };
+    let (recipients, recipients_detail) = match collect_recipients(&entries, &selected, field("extra_recipients")) {
+        Ok(collected) => collected,
+        Err(error) => return render_page(&state, &current_user, PageOutput::error(error)).await,
+    };
+
+    match encrypt_to(&recipients, plaintext) {
+        Ok(encrypted) => {
+            // 監査ログに記録
+            let details = format!("Encrypted text (length: {}) for {}", plaintext.len(), recipients_detail);
+            audit(&state, &client, &current_user, "encrypt_public", "/crypto/public/encrypt", &details).await;
+
+            let output = PageOutput { encrypted_text: Some(encrypted), ..Default::default() };
+            render_page(&state, &current_user, output).await
+        }
+        Err(e) => {
+            let details = format!("Public-key encryption for {} failed: {}", recipients_detail, e);
+            audit(&state, &client, &current_user, "encrypt_public_failed", "/crypto/public/encrypt", &details).await;
+
+            render_page(&state, &current_user, PageOutput::error(format!("暗号化エラー: {}", e))).await
+        }
+    }
+}
+
+/// 自分の秘密鍵をパスフレーズで開いて復号化する
+pub async fn decrypt(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Form(form): Form<DecryptForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let entry = db::get_keyring_entry(&state.db, current_user.id, db::KEY_TYPE_X25519).await;
+    let Some(encrypted_private_key) = entry.ok().flatten().and_then(|entry| entry.encrypted_private_key) else {
+        let error = "ダッシュボードに秘密鍵が保存されていません（公開鍵だけを登録した場合は手元の age で復号化してください）";
+        return render_page(&state, &current_user, PageOutput::error(error)).await;
+    };
+
+    let passphrase = form.passphrase.clone();
+    let decrypted = crypto::blocking(move || crypto::decrypt_string(&encrypted_private_key, &passphrase))
+        .await
+        .map_err(|_| anyhow::anyhow!("パスフレーズが間違っています"))
+        .and_then(|secret_key| {
+            age::x25519::Identity::from_str(&secret_key).map_err(|e| anyhow::anyhow!("秘密鍵を読み込めません: {}", e))
+        })
+        .and_then(|identity| decrypt_with(&identity, &form.ciphertext));
+
+    match decrypted {
+        Ok(decrypted) => {
+            // 監査ログに記録
+            let details = format!("Decrypted text (length: {})", decrypted.len());
+            audit(&state, &client, &current_user, "decrypt_public", "/crypto/public/decrypt", &details).await;
+
+            let output = PageOutput { decrypted_text: Some(decrypted), ..Default::default() };
+            render_page(&state, &current_user, output).await
+        }
+        Err(e) => {
+            // エラーも記録
+            let details = format!("Public-key decryption failed: {}", e);
+            audit(&state, &client, &current_user, "decrypt_public_failed", "/crypto/public/decrypt", &details).await;
+
+            render_page(&state, &current_user, PageOutput::error(format!("復号化エラー: {}", e))).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    /// age の参照実装のテストベクター（testkit の armor）。秘密鍵 `FIXTURE_IDENTITY` 宛てに "age" を暗号化したもの
+    const FIXTURE_CIPHERTEXT: &str = "-----BEGIN AGE ENCRYPTED FILE-----
+YWdlLWVuY3J5cHRpb24ub3JnL3YxCi0+IFgyNTUxOSBURWlGMHlwcXIrYnB2Y3FY
+TnlDVkpwTDdPdXdQZFZ3UEw3S1FFYkZET0NjCmhqYWJHWHdTTFE5YzNTNkx3Mmkr
+UzJUdTJmaXdRSEhzbGJCTjZCNDFGTEUKLS0tIFd5SnA5Ri85Rk9aaDdnSmRoZXEy
+V0lKY3dIZ1ljOE5JVmgzZGR3aHJjTmcK7s9ix86RtDMnTmjU8vkTTLdMW/73vqpS
+yPC8DpksHoMx+2Y=
+-----END AGE ENCRYPTED FILE-----
+";
+    const FIXTURE_IDENTITY: &str = "AGE-SECRET-KEY-1EGTZVFFV20835NWYV6270LXYVK2VKNX2MMDKWYKLMGR48UAWX40Q2P2LM0";
+
+    fn entry(username: &str, public_key: &str) -> db::KeyringEntry {
+        db::KeyringEntry {
+            user_id: 1,
+            username: username.to_string(),
+            key_type: db::KEY_TYPE_X25519.to_string(),
+            public_key: public_key.to_string(),
+            encrypted_private_key: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn encrypted_text_is_armored_age_for_every_recipient() {
+        let alice = age::x25519::Identity::generate();
+        let bob = age::x25519::Identity::generate();
+        let encrypted = encrypt_to(&[alice.to_public(), bob.to_public()], "秘密のメッセージ").unwrap();
+        assert!(encrypted.starts_with("-----BEGIN AGE ENCRYPTED FILE-----"));
+
+        // ダッシュボードの復号化を通さず、age の Decryptor で直接読めること
+        for identity in [&alice, &bob] {
+            let decryptor = age::Decryptor::new(ArmoredReader::new(encrypted.as_bytes())).unwrap();
+            let mut reader = decryptor.decrypt(std::iter::once(identity as &dyn age::Identity)).unwrap();
+            let mut plaintext = String::new();
+            reader.read_to_string(&mut plaintext).unwrap();
+            assert_eq!(plaintext, "秘密のメッセージ");
+        }
+
+        let other = age::x25519::Identity::generate();
+        assert!(decrypt_with(&other, &encrypted).is_err());
+    }
+
+    #[test]
+    fn decrypts_ciphertext_from_the_reference_implementation() {
+        let identity = age::x25519::Identity::from_str(FIXTURE_IDENTITY).unwrap();
+        assert_eq!(decrypt_with(&identity, FIXTURE_CIPHERTEXT).unwrap(), "age");
+    }
+
+    #[test]
+    fn rejects_passphrase_encrypted_data() {
+        let mut recipient = age::scrypt::Recipient::new("passphrase".to_string().into());
+        // テストを速くするための小さい作業係数
+        recipient.set_work_factor(2);
+        let encryptor = age::Encryptor::with_recipients(std::iter::once(&recipient as &dyn age::Recipient)).unwrap();
+
+        let mut output = Vec::new();
+        let armor = ArmoredWriter::wrap_output(&mut output, Format::AsciiArmor).unwrap();
+        let mut writer = encryptor.wrap_output(armor).unwrap();
+        writer.write_all(b"secret").unwrap();
+        writer.finish().unwrap().finish().unwrap();
+
+        let identity = age::x25519::Identity::generate();
+        let error = decrypt_with(&identity, &String::from_utf8(output).unwrap()).unwrap_err();
+        assert!(error.to_string().contains("パスフレーズで暗号化された"));
+    }
+
+    #[test]
+    fn collects_selected_and_extra_recipients() {
+        let alice = age::x25519::Identity::generate().to_public().to_string();
+        let bob = age::x25519::Identity::generate().to_public().to_string();
+        let external = age::x25519::Identity::generate().to_public().to_string();
+        let entries = [entry("alice", &alice), entry("bob", &bob)];
+
+        let (recipients, detail) = collect_recipients(&entries, &["alice"], &format!("\n  {}  \n", external)).unwrap();
+        let recipients: Vec<String> = recipients.iter().map(ToString::to_string).collect();
+        assert_eq!(recipients, [alice, external]);
+        assert_eq!(detail, "alice, 1 external key(s)");
+
+        assert!(collect_recipients(&entries, &[], "").is_err());
+        assert!(collect_recipients(&entries, &[], "age1invalid").is_err());
+    }
+
+    #[test]
+    fn limits_the_number_of_recipients() {
+        let keys: Vec<String> = (0..=MAX_RECIPIENTS)
+            .map(|_| age::x25519::Identity::generate().to_public().to_string())
+            .collect();
+
+        assert!(collect_recipients(&[], &[], &keys[..MAX_RECIPIENTS].join("\n")).is_ok());
+        let error = collect_recipients(&[], &[], &keys.join("\n")).unwrap_err();
+        assert_eq!(error, format!("受信者は{}人までです", MAX_RECIPIENTS));
+    }
+
+    #[test]
+    fn imports_only_valid_public_keys() {
+        let public_key = age::x25519::Identity::generate().to_public().to_string();
+        let key = NewKey::import(&format!(" {} ", public_key)).unwrap();
+        assert_eq!(key.public_key, public_key);
+        assert!(key.encrypted_private_key.is_none());
+
+        assert!(NewKey::import("age1invalid").is_err());
+        assert!(NewKey::import(FIXTURE_IDENTITY).is_err());
+    }
+}
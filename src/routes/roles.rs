@@ -0,0 +1,233 @@
+use askama::Template;
+use axum::{
+    extract::{Form, Path, State},
+    response::{Html, IntoResponse, Redirect},
+};
+use serde::Deserialize;
+use crate::{AppState, db};
+use crate::routes::context::{ClientContext, CurrentUser};
+
+#[derive(Template)]
+#[template(path = "roles.html")]
+pub struct RolesTemplate {
+    pub roles: Vec<RoleDisplay>,
+    pub permissions: Vec<PermissionOption>,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+pub struct RoleDisplay {
+    pub name: String,
+    pub description: String,
+    pub is_builtin: bool,
+    pub user_count: i64,
+    pub permissions: Vec<PermissionOption>,
+}
+
+#[derive(Clone)]
+pub struct PermissionOption {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub granted: bool,
+}
+
+#[derive(Deserialize)]
+pub struct CreateRoleForm {
+    name: String,
+    description: String,
+}
+
+async fn render_roles_page(
+    state: &AppState,
+    message: Option<String>,
+    error: Option<String>,
+) -> Result<Html<String>, Redirect> {
+    let roles = db::list_roles(&state.db)
+        .await
+        .map_err(|_| Redirect::to("/"))?;
+
+    let mut roles_display = Vec::with_capacity(roles.len());
+    for role in roles {
+        let granted = db::list_role_permissions(&state.db, &role.name)
+            .await
+            .map_err(|_| Redirect::to("/"))?;
+        let user_count = db::count_users_with_role(&state.db, &role.name)
+            .await
+            .map_err(|_| Redirect::to("/"))?;
+
+        roles_display.push(RoleDisplay {
+            permissions: db::Permission::all()
+                .into_iter()
+                .map(|permission| PermissionOption {
+                    name: permission.as_str(),
+                    description: permission.description(),
+                    granted: granted.contains(&permission),
+                })
+                .collect(),
+            name: role.name,
+            description: role.description,
+            is_builtin: role.is_builtin,
+            user_count,
+        });
+    }
+
+    let template = RolesTemplate {
+        roles: roles_display,
+        permissions: db::Permission::all()
+            .into_iter()
+            .map(|permission| PermissionOption {
+                name: permission.as_str(),
+                description: permission.description(),
+                granted: false,
+            })
+            .collect(),
+        message,
+        error,
+    };
+
+    match template.render() {
+        Ok(html) => Ok(Html(html)),
+        Err(_) => Err(Redirect::to("/")),
+    }
+}
+
+pub async fn list_roles(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Redirect> {
+    render_roles_page(&state, None, None).await
+}
+
+pub async fn create_role(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Form(form): Form<CreateRoleForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let name = form.name.trim().to_lowercase();
+    if !db::is_valid_role_name(&name) {
+        let error = "ロール名は英小文字・数字・_・- の50文字以内で入力してください".to_string();
+        return render_roles_page(&state, None, Some(error)).await;
+    }
+
+    if let Err(e) = db::create_role(&state.db, &name, form.description.trim()).await {
+        let error = match e.as_database_error() {
+            Some(db_err) if db_err.is_unique_violation() => format!("ロール '{}' は既に存在します", name),
+            _ => "データベースエラーが発生しました".to_string(),
+        };
+        return render_roles_page(&state, None, Some(error)).await;
+    }
+
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "role_create",
+        Some("/roles/create"),
+        Some(&format!("Created role '{}'", name)),
+    ).await;
+
+    let message = format!("ロール '{}' を作成しました。権限を設定してください", name);
+    render_roles_page(&state, Some(message), None).await
+}
+
+pub async fn update_permissions(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Path(name): Path<String>,
+    // チェックボックスは同じ名前で複数送信されるため、名前と値の組の一覧で受け取る
+    Form(form): Form<Vec<(String, String)>>,
+) -> Result<impl IntoResponse, Redirect> {
+    let resource = format!("/roles/{}/permissions", name);
+
+    let role = match db::get_role(&state.db, &name).await {
+        Ok(Some(role)) => role,
+        _ => return render_roles_page(&state, None, Some("ロールが見つかりません".into())).await,
+    };
+
+    let permissions: Vec<db::Permission> = form
+        .iter()
+        .filter(|(key, _)| key == "permission")
+        .filter_map(|(_, value)| db::Permission::parse(value))
+        .collect();
+
+    // 自分自身のロールから管理権限を外すと誰もロールを管理できなくなる可能性がある
+    if role.name == current_user.role() && !permissions.contains(&db::Permission::UsersManage) {
+        let error = format!("自分のロールから {} 権限は外せません", db::Permission::UsersManage.as_str());
+        return render_roles_page(&state, None, Some(error)).await;
+    }
+
+    let previous = db::list_role_permissions(&state.db, &role.name)
+        .await
+        .unwrap_or_default();
+    if db::set_role_permissions(&state.db, &role.name, &permissions).await.is_err() {
+        return render_roles_page(&state, None, Some("データベースエラーが発生しました".into())).await;
+    }
+
+    // 監査ログに記録
+    let join = |permissions: &[db::Permission]| {
+        permissions.iter().map(|permission| permission.as_str()).collect::<Vec<_>>().join(",")
+    };
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "role_update_permissions",
+        Some(&resource),
+        Some(&format!(
+            "Changed permissions of role '{}' from [{}] to [{}]",
+            role.name,
+            join(&previous),
+            join(&permissions),
+        )),
+    ).await;
+
+    let message = format!("ロール '{}' の権限を更新しました", role.name);
+    render_roles_page(&state, Some(message), None).await
+}
+
+pub async fn delete_role(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Path(name): Path<String>,
+) -> Result<impl IntoResponse, Redirect> {
+    let resource = format!("/roles/{}/delete", name);
+
+    let role = match db::get_role(&state.db, &name).await {
+        Ok(Some(role)) => role,
+        _ => return render_roles_page(&state, None, Some("ロールが見つかりません".into())).await,
+    };
+    if role.is_builtin {
+        return render_roles_page(&state, None, Some("組み込みロールは削除できません".into())).await;
+    }
+    if !matches!(db::count_users_with_role(&state.db, &role.name).await, Ok(0)) {
+        let error = format!("ロール '{}' が割り当てられているユーザーがいるため削除できません", role.name);
+        return render_roles_page(&state, None, Some(error)).await;
+    }
+
+    match db::delete_role(&state.db, &role.name).await {
+        Ok(true) => {}
+        _ => return render_roles_page(&state, None, Some("データベースエラーが発生しました".into())).await,
+    }
+
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "role_delete",
+        Some(&resource),
+        Some(&format!("Deleted role '{}'", role.name)),
+    ).await;
+
+    let message = format!("ロール '{}' を削除しました", role.name);
+    render_roles_page(&state, Some(message), None).await
+}
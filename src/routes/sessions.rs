@@ -0,0 +1,256 @@
+//! ログイン中のセッションの一覧と失効
+//!
+//! セッションは `sessions` テーブルの行をそのまま使い、失効は行の削除で行う。
+//! 削除されたセッションは次のリクエストで未ログインとして扱われる。
+
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    response::{Html, IntoResponse, Redirect},
+};
+use chrono::{DateTime, Local, Utc};
+use tower_sessions::Session;
+use crate::{AppState, db};
+use crate::routes::context::{ClientContext, CurrentUser};
+
+// 最終アクセス日時を更新する間隔（リクエストごとに書き込まないようにする）
+const TOUCH_INTERVAL_SECS: i64 = 60;
+
+#[derive(Template)]
+#[template(path = "sessions.html")]
+pub struct SessionsTemplate {
+    /// 管理者が他のユーザーのセッションを表示している場合はそのユーザー名
+    pub target_username: Option<String>,
+    /// 失効のフォームの送信先（`/account/sessions` または `/users/{id}/sessions`）
+    pub base_path: String,
+    pub sessions: Vec<SessionDisplay>,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+pub struct SessionDisplay {
+    pub handle: String,
+    pub current: bool,
+    pub created_at: String,
+    pub last_activity_at: String,
+    pub ip_address: String,
+    pub user_agent: String,
+    pub expires_at: String,
+}
+
+fn format_local(time: DateTime<Utc>) -> String {
+    let local_time: DateTime<Local> = DateTime::from(time);
+    local_time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn current_handle(session: &Session) -> Option<String> {
+    session.id().map(|id| db::session_handle(&id.to_string()))
+}
+
+async fn render_sessions_page(
+    state: &AppState,
+    session: &Session,
+    target: &db::User,
+    admin_view: bool,
+    message: Option<String>,
+    error: Option<String>,
+) -> Result<Html<String>, Redirect> {
+    let sessions = db::list_user_sessions(&state.db, target.id)
+        .await
+        .map_err(|_| Redirect::to("/"))?;
+
+    let current = current_handle(session);
+    let template = SessionsTemplate {
+        target_username: admin_view.then(|| target.username.clone()),
+        base_path: if admin_view {
+            format!("/users/{}/sessions", target.id)
+        } else {
+            "/account/sessions".to_string()
+        },
+        sessions: sessions
+            .into_iter()
+            .map(|row| SessionDisplay {
+                current: current.as_deref() == Some(row.handle.as_str()),
+                created_at: format_local(row.created_at),
+                last_activity_at: row.last_activity_at.map(format_local).unwrap_or_else(|| "-".to_string()),
+                ip_address: row.ip_address.unwrap_or_else(|| "-".to_string()),
+                user_agent: row.user_agent.unwrap_or_else(|| "-".to_string()),
+                expires_at: format_local(row.expiry_date),
+                handle: row.handle,
+            })
+            .collect(),
+        message,
+        error,
+    };
+
+    match template.render() {
+        Ok(html) => Ok(Html(html)),
+        Err(_) => Err(Redirect::to("/")),
+    }
+}
+
+/// ログイン中のリクエストごとにセッションの最終アクセスと接続元を記録する（auth_middleware から呼ぶ）
+pub async fn track(state: &AppState, session: &Session, client: &db::ClientInfo) {
+    if let Some(id) = session.id() {
+        let interval = chrono::Duration::seconds(TOUCH_INTERVAL_SECS);
+        if let Err(e) = db::touch_session(&state.db, &id.to_string(), client, interval).await {
+            eprintln!("セッションの最終アクセス更新に失敗: {:?}", e);
+        }
+    }
+}
+
+/// ユーザーのセッションをすべて失効させて監査ログに記録する（現在のセッションは残す）
+///
+/// パスワードやロールの変更時に、変更前の権限で使われているセッションを残さないために呼ぶ。
+pub async fn revoke_all(
+    state: &AppState,
+    client: &db::ClientInfo,
+    actor: Option<&db::User>,
+    target: &db::User,
+    keep: Option<&Session>,
+    reason: &str,
+) {
+    let keep_id = keep.and_then(|session| session.id()).map(|id| id.to_string());
+    let revoked = match db::delete_user_sessions(&state.db, target.id, keep_id.as_deref()).await {
+        Ok(revoked) => revoked,
+        Err(e) => {
+            eprintln!("セッションの失効に失敗: {} {:?}", target.username, e);
+            return;
+        }
+    };
+    if revoked == 0 {
+        return;
+    }
+
+    let actor = actor.unwrap_or(target);
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        client,
+        Some(actor.id),
+        &actor.username,
+        "session_revoke_all",
+        None,
+        Some(&format!("Revoked {} session(s) of '{}' ({})", revoked, target.username, reason)),
+    ).await;
+}
+
+async fn revoke_one(
+    state: &AppState,
+    client: &db::ClientInfo,
+    current_user: &db::User,
+    target: &db::User,
+    handle: &str,
+    resource: &str,
+) -> Result<(), String> {
+    match db::delete_user_session(&state.db, target.id, handle).await {
+        Ok(true) => {}
+        Ok(false) => return Err("セッションが見つかりません".into()),
+        Err(_) => return Err("データベースエラーが発生しました".into()),
+    }
+
+    // 監査ログに記録（セッションIDは記録しない）
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        client,
+        Some(current_user.id),
+        &current_user.username,
+        "session_revoke",
+        Some(resource),
+        Some(&format!("Revoked a session of '{}' (handle: {})", target.username, &handle[..handle.len().min(12)])),
+    ).await;
+
+    Ok(())
+}
+
+pub async fn account_page(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    session: Session,
+) -> Result<impl IntoResponse, Redirect> {
+    render_sessions_page(&state, &session, &current_user, false, None, None).await
+}
+
+pub async fn account_revoke(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    session: Session,
+    Path(handle): Path<String>,
+) -> Result<impl IntoResponse, Redirect> {
+    // 現在のセッションはログアウトで終了する
+    if current_handle(&session).as_deref() == Some(handle.as_str()) {
+        let error = "このセッションはログアウトで終了してください".to_string();
+        return render_sessions_page(&state, &session, &current_user, false, None, Some(error)).await;
+    }
+
+    let resource = "/account/sessions/revoke";
+    let result = revoke_one(&state, &client, &current_user, &current_user, &handle, resource).await;
+    let (message, error) = match result {
+        Ok(()) => (Some("セッションを失効させました".to_string()), None),
+        Err(error) => (None, Some(error)),
+    };
+    render_sessions_page(&state, &session, &current_user, false, message, error).await
+}
+
+/// 現在のセッション以外をすべて失効させる
+pub async fn account_revoke_others(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    session: Session,
+) -> Result<impl IntoResponse, Redirect> {
+    revoke_all(&state, &client, None, &current_user, Some(&session), "requested by user").await;
+
+    let message = "このセッション以外のセッションをすべて失効させました".to_string();
+    render_sessions_page(&state, &session, &current_user, false, Some(message), None).await
+}
+
+async fn load_target(state: &AppState, id: i32) -> Result<db::User, Redirect> {
+    match db::get_user_by_id(&state.db, id).await {
+        Ok(Some(user)) => Ok(user),
+        _ => Err(Redirect::to("/users")),
+    }
+}
+
+pub async fn user_page(
+    State(state): State<AppState>,
+    session: Session,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, Redirect> {
+    let target = load_target(&state, id).await?;
+    render_sessions_page(&state, &session, &target, true, None, None).await
+}
+
+pub async fn user_revoke(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    session: Session,
+    Path((id, handle)): Path<(i32, String)>,
+) -> Result<impl IntoResponse, Redirect> {
+    let target = load_target(&state, id).await?;
+    let resource = format!("/users/{}/sessions/revoke", id);
+
+    let result = revoke_one(&state, &client, &current_user, &target, &handle, &resource).await;
+    let (message, error) = match result {
+        Ok(()) => (Some(format!("'{}' のセッションを失効させました", target.username)), None),
+        Err(error) => (None, Some(error)),
+    };
+    render_sessions_page(&state, &session, &target, true, message, error).await
+}
+
+pub async fn user_revoke_all(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    session: Session,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, Redirect> {
+    let target = load_target(&state, id).await?;
+    revoke_all(&state, &client, Some(&current_user), &target, Some(&session), "requested by administrator").await;
+
+    let message = format!("'{}' のセッションをすべて失効させました", target.username);
+    render_sessions_page(&state, &session, &target, true, Some(message), None).await
+}
@@ -0,0 +1,470 @@
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::{
+    extract::{Form, State},
+    response::{Html, IntoResponse, Redirect},
+};
+use rand::Rng;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use totp_rs::{Algorithm, Secret, TOTP};
+use tower_sessions::Session;
+use crate::{AppState, db};
+use crate::routes::{auth, lockouts};
+use crate::routes::context::{ClientContext, CurrentUser};
+
+const TOTP_ISSUER: &str = "Rust Dashboard";
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+#[derive(Template, WebTemplate)]
+#[template(path = "login_2fa.html")]
+pub struct Login2faTemplate {
+    pub error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "two_factor.html")]
+pub struct TwoFactorTemplate {
+    pub enabled: bool,
+    pub required: bool,
+    pub qr_svg: String,
+    pub otpauth_url: String,
+    pub secret: String,
+    pub recovery_codes: Vec<String>,
+    pub unused_recovery_codes: i64,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CodeForm {
+    code: String,
+}
+
+fn build_totp(secret: &str, username: &str) -> Option<TOTP> {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+    // ラベルに ':' は使えないため置き換える
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret,
+        Some(TOTP_ISSUER.to_string()),
+        username.replace(':', "_"),
+    )
+    .ok()
+}
+
+/// 確認コードに一致するタイムステップを返す（前後 `skew` ステップの時刻のずれを許容）
+///
+/// `last_used_step` 以前のステップは使用済みとして一致させない。
+fn matching_step(totp: &TOTP, code: &str, time: u64, last_used_step: Option<u64>) -> Option<u64> {
+    let current = time / totp.step;
+    let skew = u64::from(totp.skew);
+    let first = match last_used_step {
+        Some(last) => current.saturating_sub(skew).max(last.saturating_add(1)),
+        None => current.saturating_sub(skew),
+    };
+    let exact = TOTP { skew: 0, ..totp.clone() };
+    (first..=current + skew).find(|step| exact.check(code, step * totp.step))
+}
+
+fn verify_totp(totp: &db::UserTotp, username: &str, code: &str) -> Option<u64> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    let last_used_step = totp.last_used_step.and_then(|step| u64::try_from(step).ok());
+    let totp = build_totp(&totp.secret, username)?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    matching_step(&totp, &code, now, last_used_step)
+}
+
+/// 確認コードを検証し、そのタイムステップを使用済みにする
+///
+/// 一度受け付けたコード（とそれ以前のコード）は有効期間内でも再び受け付けない。
+async fn accept_totp(state: &AppState, totp: &db::UserTotp, username: &str, code: &str) -> bool {
+    let Some(step) = verify_totp(totp, username, code) else {
+        return false;
+    };
+    match db::record_totp_step(&state.db, totp.user_id, step).await {
+        Ok(accepted) => accepted,
+        Err(e) => {
+            eprintln!("確認コードの記録に失敗: {} {:?}", username, e);
+            false
+        }
+    }
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    format!("{:x}", Sha256::digest(normalized.as_bytes()))
+}
+
+fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let chars: String = (0..10)
+                .map(|_| RECOVERY_CODE_ALPHABET[rng.random_range(0..RECOVERY_CODE_ALPHABET.len())] as char)
+                .collect();
+            format!("{}-{}", &chars[..5], &chars[5..])
+        })
+        .collect()
+}
+
+async fn issue_recovery_codes(state: &AppState, user_id: i32) -> Result<Vec<String>, sqlx::Error> {
+    let codes = generate_recovery_codes();
+    let hashes: Vec<String> = codes.iter().map(|code| hash_recovery_code(code)).collect();
+    db::replace_recovery_codes(&state.db, user_id, &hashes).await?;
+    Ok(codes)
+}
+
+/// ユーザーのロールに二要素認証が必須かどうか
+pub async fn is_required(state: &AppState, user: &db::User) -> bool {
+    if user.role() != db::ROLE_ADMIN {
+        return false;
+    }
+    matches!(
+        db::get_setting(&state.db, db::SETTING_REQUIRE_2FA_ADMIN).await,
+        Ok(Some(value)) if value == "true"
+    )
+}
+
+/// 二要素認証が必須なのに未登録であれば true
+pub async fn setup_required(state: &AppState, user: &db::User) -> bool {
+    if !is_required(state, user).await {
+        return false;
+    }
+    !matches!(db::get_user_totp(&state.db, user.id).await, Ok(Some(totp)) if totp.enabled)
+}
+
+pub async fn login_2fa_page(session: Session) -> Result<Login2faTemplate, Redirect> {
+    if auth::get_pending_2fa_user_id(&session).await.is_none() {
+        return Err(Redirect::to("/login"));
+    }
+    Ok(Login2faTemplate { error: None })
+}
+
+pub async fn login_2fa(
+    State(state): State<AppState>,
+    ClientContext(client): ClientContext,
+    session: Session,
+    Form(form): Form<CodeForm>,
+) -> Result<Redirect, impl IntoResponse> {
+    let Some(user_id) = auth::get_pending_2fa_user_id(&session).await else {
+        return Ok(Redirect::to("/login"));
+    };
+
+    let (user, totp) = match db::get_user_by_id(&state.db, user_id).await {
+        Ok(Some(user)) => match db::get_user_totp(&state.db, user.id).await {
+            Ok(Some(totp)) if totp.enabled => (user, totp),
+            _ => return Ok(Redirect::to("/login")),
+        },
+        _ => return Ok(Redirect::to("/login")),
+    };
+
+    match lockouts::find_active_lockout(&state, &client, &user.username).await {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            return Err(Login2faTemplate {
+                error: Some("ログイン失敗が続いたため一時的にロックされています。しばらくしてから再度お試しください".into()),
+            });
+        }
+        Err(_) => {
+            return Err(Login2faTemplate {
+                error: Some("データベースエラーが発生しました".into()),
+            });
+        }
+    }
+
+    let code = form.code.trim();
+    let is_numeric_code = code.chars().all(|c| c.is_ascii_digit() || c.is_whitespace());
+
+    let verified = if is_numeric_code {
+        accept_totp(&state, &totp, &user.username, code).await
+    } else {
+        // 数字以外を含む場合はリカバリーコードとして扱う
+        let used = db::consume_recovery_code(&state.db, user.id, &hash_recovery_code(code))
+            .await
+            .unwrap_or(false);
+        if used {
+            let _ = db::create_audit_log(
+                &state.db,
+                &state.audit_events,
+                &client,
+                Some(user.id),
+                &user.username,
+                "2fa_recovery_code_used",
+                Some("/login/2fa"),
+                Some("Logged in with a recovery code"),
+            ).await;
+        }
+        used
+    };
+
+    if !verified {
+        auth::record_failed_login(&state, &client, &user.username, "Invalid two-factor code").await;
+        return Err(Login2faTemplate {
+            error: Some("確認コードが正しくありません".into()),
+        });
+    }
+
+    auth::complete_login(&state, &client, &session, &user)
+        .await
+        .map_err(|error| Login2faTemplate { error: Some(error) })
+}
+
+async fn render_account_page(
+    state: &AppState,
+    user: &db::User,
+    recovery_codes: Vec<String>,
+    message: Option<String>,
+    error: Option<String>,
+) -> Result<Html<String>, Redirect> {
+    let required = is_required(state, user).await;
+    let totp = db::get_user_totp(&state.db, user.id)
+        .await
+        .map_err(|_| Redirect::to("/"))?;
+
+    let mut template = TwoFactorTemplate {
+        enabled: false,
+        required,
+        qr_svg: String::new(),
+        otpauth_url: String::new(),
+        secret: String::new(),
+        recovery_codes,
+        unused_recovery_codes: 0,
+        message,
+        error,
+    };
+
+    match totp {
+        Some(totp) if totp.enabled => {
+            template.enabled = true;
+            template.unused_recovery_codes = db::count_unused_recovery_codes(&state.db, user.id)
+                .await
+                .unwrap_or(0);
+        }
+        // 登録を開始するまではシークレットを発行しない（表示のたびに作り直さない）
+        None => {}
+        Some(pending) => {
+            let secret = pending.secret;
+            let totp = build_totp(&secret, &user.username).ok_or_else(|| Redirect::to("/"))?;
+            let otpauth_url = totp.get_url();
+            template.qr_svg = qrcode::QrCode::new(otpauth_url.as_bytes())
+                .map(|code| {
+                    code.render::<qrcode::render::svg::Color>()
+                        .min_dimensions(200, 200)
+                        .build()
+                })
+                .unwrap_or_default();
+            template.otpauth_url = otpauth_url;
+            template.secret = secret;
+        }
+    }
+
+    match template.render() {
+        Ok(html) => Ok(Html(html)),
+        Err(_) => Err(Redirect::to("/")),
+    }
+}
+
+pub async fn account_page(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+) -> Result<impl IntoResponse, Redirect> {
+    render_account_page(&state, &current_user, Vec::new(), None, None).await
+}
+
+/// 登録を開始してシークレットを発行する（登録途中のシークレットがあればそれを使い続ける）
+pub async fn start_setup(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+) -> Result<impl IntoResponse, Redirect> {
+    let Secret::Encoded(secret) = Secret::generate_secret().to_encoded() else {
+        return Err(Redirect::to("/"));
+    };
+    db::save_pending_totp(&state.db, current_user.id, &secret)
+        .await
+        .map_err(|_| Redirect::to("/"))?;
+
+    render_account_page(&state, &current_user, Vec::new(), None, None).await
+}
+
+pub async fn enable(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    session: Session,
+    Form(form): Form<CodeForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let totp = match db::get_user_totp(&state.db, current_user.id).await {
+        Ok(Some(totp)) if !totp.enabled => totp,
+        _ => return render_account_page(&state, &current_user, Vec::new(), None, None).await,
+    };
+
+    if !accept_totp(&state, &totp, &current_user.username, &form.code).await {
+        let error = Some("確認コードが正しくありません。認証アプリの時刻設定を確認してください".into());
+        return render_account_page(&state, &current_user, Vec::new(), None, error).await;
+    }
+
+    if db::enable_totp(&state.db, current_user.id).await.is_err() {
+        return Err(Redirect::to("/"));
+    }
+    let codes = issue_recovery_codes(&state, current_user.id)
+        .await
+        .map_err(|_| Redirect::to("/"))?;
+    auth::clear_2fa_setup_required(&session).await;
+
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "2fa_enabled",
+        Some("/account/2fa/enable"),
+        Some("Enabled TOTP two-factor authentication"),
+    ).await;
+
+    let message = Some("二要素認証を有効にしました。リカバリーコードを安全な場所に保管してください".into());
+    render_account_page(&state, &current_user, codes, message, None).await
+}
+
+pub async fn disable(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Form(form): Form<CodeForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    if is_required(&state, &current_user).await {
+        let error = Some("このロールでは二要素認証を無効にできません".into());
+        return render_account_page(&state, &current_user, Vec::new(), None, error).await;
+    }
+
+    let totp = match db::get_user_totp(&state.db, current_user.id).await {
+        Ok(Some(totp)) if totp.enabled => totp,
+        _ => return render_account_page(&state, &current_user, Vec::new(), None, None).await,
+    };
+
+    if !accept_totp(&state, &totp, &current_user.username, &form.code).await {
+        let error = Some("確認コードが正しくありません".into());
+        return render_account_page(&state, &current_user, Vec::new(), None, error).await;
+    }
+
+    if db::delete_totp(&state.db, current_user.id).await.is_err() {
+        return Err(Redirect::to("/"));
+    }
+
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "2fa_disabled",
+        Some("/account/2fa/disable"),
+        Some("Disabled TOTP two-factor authentication"),
+    ).await;
+
+    let message = Some("二要素認証を無効にしました".into());
+    render_account_page(&state, &current_user, Vec::new(), message, None).await
+}
+
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Form(form): Form<CodeForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let totp = match db::get_user_totp(&state.db, current_user.id).await {
+        Ok(Some(totp)) if totp.enabled => totp,
+        _ => return render_account_page(&state, &current_user, Vec::new(), None, None).await,
+    };
+
+    if !accept_totp(&state, &totp, &current_user.username, &form.code).await {
+        let error = Some("確認コードが正しくありません".into());
+        return render_account_page(&state, &current_user, Vec::new(), None, error).await;
+    }
+
+    let codes = issue_recovery_codes(&state, current_user.id)
+        .await
+        .map_err(|_| Redirect::to("/"))?;
+
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "2fa_recovery_codes_regenerated",
+        Some("/account/2fa/recovery-codes"),
+        Some("Regenerated two-factor recovery codes"),
+    ).await;
+
+    let message = Some("新しいリカバリーコードを発行しました。以前のコードは使用できません".into());
+    render_account_page(&state, &current_user, codes, message, None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP";
+    const NOW: u64 = 1_700_000_010;
+
+    fn totp() -> TOTP {
+        build_totp(SECRET, "alice").unwrap()
+    }
+
+    fn code_at_step(step: u64) -> String {
+        totp().generate(step * 30)
+    }
+
+    #[test]
+    fn accepts_codes_within_the_skew_window() {
+        let current = NOW / 30;
+        for step in [current - 1, current, current + 1] {
+            assert_eq!(matching_step(&totp(), &code_at_step(step), NOW, None), Some(step));
+        }
+    }
+
+    #[test]
+    fn rejects_codes_outside_the_skew_window() {
+        let current = NOW / 30;
+        for step in [current - 2, current + 2] {
+            assert_eq!(matching_step(&totp(), &code_at_step(step), NOW, None), None);
+        }
+    }
+
+    #[test]
+    fn rejects_used_and_earlier_steps() {
+        let current = NOW / 30;
+        assert_eq!(matching_step(&totp(), &code_at_step(current), NOW, Some(current)), None);
+        assert_eq!(matching_step(&totp(), &code_at_step(current - 1), NOW, Some(current)), None);
+        assert_eq!(matching_step(&totp(), &code_at_step(current - 1), NOW, Some(current - 1)), None);
+    }
+
+    #[test]
+    fn accepts_steps_after_the_last_used_step() {
+        let current = NOW / 30;
+        assert_eq!(matching_step(&totp(), &code_at_step(current), NOW, Some(current - 1)), Some(current));
+        assert_eq!(matching_step(&totp(), &code_at_step(current + 1), NOW, Some(current)), Some(current + 1));
+    }
+
+    #[test]
+    fn rejects_a_wrong_code() {
+        let wrong = format!("{:06}", (code_at_step(NOW / 30).parse::<u32>().unwrap() + 1) % 1_000_000);
+        assert_eq!(matching_step(&totp(), &wrong, NOW, None), None);
+    }
+}
@@ -1,13 +1,27 @@
 use askama::Template;
-use axum::{extract::State, response::{Html, Redirect, IntoResponse}};
-use tower_sessions::Session;
+use axum::{
+    extract::{Form, Path, State},
+    response::{Html, IntoResponse, Redirect},
+};
+use serde::Deserialize;
 use crate::{AppState, db};
-use crate::routes::auth;
+use tower_sessions::Session;
+use crate::routes::context::{ClientContext, CurrentUser};
+use crate::routes::{api_tokens, sessions};
+
+/// ユーザー名の最大文字数
+const MAX_USERNAME_LENGTH: usize = 64;
 
 #[derive(Template)]
 #[template(path = "users.html")]
 pub struct UsersTemplate {
     pub users: Vec<UserDisplay>,
+    pub roles: Vec<String>,
+    pub current_user_id: i32,
+    pub require_admin_2fa: bool,
+    pub min_password_length: usize,
+    pub message: Option<String>,
+    pub error: Option<String>,
 }
 
 #[derive(Clone)]
@@ -15,52 +29,87 @@ pub struct UserDisplay {
     pub id: i32,
     pub username: String,
     pub role: String,
+    pub two_factor_enabled: bool,
+    pub disabled: bool,
+    /// 次回ログイン時にパスワードの変更が必要
+    pub must_change_password: bool,
+    /// 外部の認証基盤のユーザー（ローカルのパスワードを持たない）
+    pub external: bool,
     pub created_at: String,
 }
 
-pub async fn list_users(
-    State(state): State<AppState>,
-    session: Session,
-) -> Result<impl IntoResponse, impl IntoResponse> {
-    // 現在のユーザーを取得
-    let current_user = match auth::get_current_user(&session, &state.db).await {
-        Some(user) => user,
-        None => return Err(Redirect::to("/login")),
-    };
+#[derive(Deserialize)]
+pub struct CreateUserForm {
+    username: String,
+    #[serde(default)]
+    password: String,
+    role: String,
+    // 外部の認証基盤（LDAP）でログインするユーザー（チェックボックスは未チェックの場合送信されない）
+    external: Option<String>,
+}
 
-    // Admin権限をチェック
-    if !current_user.role().can_access_users() {
-        // 権限エラーを監査ログに記録
-        let _ = db::create_audit_log(
-            &state.db,
-            Some(current_user.id),
-            &current_user.username,
-            "access_denied",
-            Some("/users"),
-            Some("Attempted to access users page without permission"),
-            None,
-            None,
-        ).await;
+#[derive(Deserialize)]
+pub struct UpdateRoleForm {
+    role: String,
+}
 
-        return Err(Redirect::to("/"));
-    }
+#[derive(Deserialize)]
+pub struct ResetPasswordForm {
+    password: String,
+    // 次回ログイン時に変更させる（チェックボックスは未チェックの場合送信されない）
+    require_change: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorSettingsForm {
+    // チェックボックスは未チェックの場合送信されない
+    require_admin_2fa: Option<String>,
+}
 
+async fn render_users_page(
+    state: &AppState,
+    current_user: &db::User,
+    message: Option<String>,
+    error: Option<String>,
+) -> Result<Html<String>, Redirect> {
     let users = db::list_users(&state.db)
         .await
         .map_err(|_| Redirect::to("/"))?;
 
+    let roles = db::list_roles(&state.db)
+        .await
+        .map_err(|_| Redirect::to("/"))?;
+
+    let totp_user_ids = db::list_totp_enabled_user_ids(&state.db)
+        .await
+        .map_err(|_| Redirect::to("/"))?;
+    let require_admin_2fa = matches!(
+        db::get_setting(&state.db, db::SETTING_REQUIRE_2FA_ADMIN).await,
+        Ok(Some(value)) if value == "true"
+    );
+
     let users_display: Vec<UserDisplay> = users
         .into_iter()
         .map(|user| UserDisplay {
             id: user.id,
             username: user.username.clone(),
-            role: user.role().as_str().to_string(),
+            role: user.role().to_string(),
+            two_factor_enabled: totp_user_ids.contains(&user.id),
+            disabled: user.is_disabled(),
+            must_change_password: user.must_change_password,
+            external: !user.has_local_password(),
             created_at: "N/A".to_string(),
         })
         .collect();
 
     let template = UsersTemplate {
         users: users_display,
+        roles: roles.into_iter().map(|role| role.name).collect(),
+        current_user_id: current_user.id,
+        require_admin_2fa,
+        min_password_length: state.password_policy.min_length,
+        message,
+        error,
     };
 
     match template.render() {
@@ -68,3 +117,427 @@ pub async fn list_users(
         Err(_) => Err(Redirect::to("/")),
     }
 }
+
+pub async fn list_users(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+) -> Result<impl IntoResponse, impl IntoResponse> {
+    render_users_page(&state, &current_user, None, None).await
+}
+
+pub async fn create_user(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Form(form): Form<CreateUserForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let username = form.username.trim();
+    if let Err(error) = validate_username(username) {
+        return render_users_page(&state, &current_user, None, Some(error)).await;
+    }
+    let external = form.external.is_some();
+    if !external
+        && let Err(error) = state.password_policy.validate(&form.password, username)
+    {
+        return render_users_page(&state, &current_user, None, Some(error)).await;
+    }
+    let role = match db::get_role(&state.db, &form.role).await {
+        Ok(Some(role)) => role.name,
+        _ => return render_users_page(&state, &current_user, None, Some("不正なロールです".into())).await,
+    };
+
+    let hash = if external {
+        db::EXTERNAL_PASSWORD_HASH.to_string()
+    } else {
+        match state.password_hashing.hash(&form.password) {
+            Ok(hash) => hash,
+            Err(_) => {
+                return render_users_page(&state, &current_user, None, Some("パスワードのハッシュ化に失敗しました".into())).await;
+            }
+        }
+    };
+
+    match db::create_user(&state.db, username, &hash, &role).await {
+        Ok(user) => {
+            // 監査ログに記録
+            let _ = db::create_audit_log(
+                &state.db,
+                &state.audit_events,
+                &client,
+                Some(current_user.id),
+                &current_user.username,
+                "user_create",
+                Some("/users/create"),
+                Some(&format!(
+                    "Created {} user '{}' (id: {}, role: {})",
+                    if external { "external" } else { "local" },
+                    user.username,
+                    user.id,
+                    role
+                )),
+            ).await;
+
+            let message = format!("ユーザー '{}' を作成しました", user.username);
+            render_users_page(&state, &current_user, Some(message), None).await
+        }
+        Err(e) => {
+            let error = match e.as_database_error() {
+                Some(db_err) if db_err.is_unique_violation() => {
+                    format!("ユーザー名 '{}' は既に使用されています", username)
+                }
+                _ => "データベースエラーが発生しました".to_string(),
+            };
+            render_users_page(&state, &current_user, None, Some(error)).await
+        }
+    }
+}
+
+/// 作成するユーザー名を確認する（英数字と `.` `_` `-` `@` のみ）
+fn validate_username(username: &str) -> Result<(), String> {
+    if username.is_empty() {
+        return Err("ユーザー名を入力してください".into());
+    }
+    if username.chars().count() > MAX_USERNAME_LENGTH {
+        return Err(format!("ユーザー名は{}文字以下にしてください", MAX_USERNAME_LENGTH));
+    }
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '@')) {
+        return Err("ユーザー名に使用できるのは英数字と . _ - @ のみです".into());
+    }
+    Ok(())
+}
+
+pub async fn update_role(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    session: Session,
+    Path(id): Path<i32>,
+    Form(form): Form<UpdateRoleForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let resource = format!("/users/{}/role", id);
+
+    let role = match db::get_role(&state.db, &form.role).await {
+        Ok(Some(role)) => role.name,
+        _ => return render_users_page(&state, &current_user, None, Some("不正なロールです".into())).await,
+    };
+    let target = match db::get_user_by_id(&state.db, id).await {
+        Ok(Some(user)) => user,
+        _ => return render_users_page(&state, &current_user, None, Some("ユーザーが見つかりません".into())).await,
+    };
+
+    // 自分自身の管理権限を外すと誰もユーザー管理できなくなる可能性がある
+    let keeps_admin = matches!(
+        db::role_has_permission(&state.db, &role, db::Permission::UsersManage).await,
+        Ok(true)
+    );
+    if target.id == current_user.id && !keeps_admin {
+        return render_users_page(&state, &current_user, None, Some("自分自身のロールは変更できません".into())).await;
+    }
+
+    let previous_role = target.role().to_string();
+    if db::update_user_role(&state.db, target.id, &role).await.is_err() {
+        return render_users_page(&state, &current_user, None, Some("データベースエラーが発生しました".into())).await;
+    }
+
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "user_update_role",
+        Some(&resource),
+        Some(&format!("Changed role of '{}' from {} to {}", target.username, previous_role, role)),
+    ).await;
+
+    // 変更前のロールで使われているセッションを残さない（操作した管理者自身のセッションは残す）
+    sessions::revoke_all(&state, &client, Some(&current_user), &target, Some(&session), "role changed").await;
+
+    let message = format!("'{}' のロールを {} に変更しました", target.username, role);
+    render_users_page(&state, &current_user, Some(message), None).await
+}
+
+pub async fn reset_password(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    session: Session,
+    Path(id): Path<i32>,
+    Form(form): Form<ResetPasswordForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let resource = format!("/users/{}/password", id);
+
+    let target = match db::get_user_by_id(&state.db, id).await {
+        Ok(Some(user)) => user,
+        _ => return render_users_page(&state, &current_user, None, Some("ユーザーが見つかりません".into())).await,
+    };
+    if !target.has_local_password() {
+        return render_users_page(&state, &current_user, None, Some("外部認証のユーザーのパスワードは変更できません".into())).await;
+    }
+    if let Err(error) = state.password_policy.validate(&form.password, &target.username) {
+        return render_users_page(&state, &current_user, None, Some(error)).await;
+    }
+
+    let require_change = form.require_change.is_some();
+    let Ok(hash) = state.password_hashing.hash(&form.password) else {
+        return render_users_page(&state, &current_user, None, Some("パスワードのハッシュ化に失敗しました".into())).await;
+    };
+    if db::update_user_password(&state.db, target.id, &hash, require_change).await.is_err() {
+        return render_users_page(&state, &current_user, None, Some("データベースエラーが発生しました".into())).await;
+    }
+
+    // 監査ログに記録（パスワード自体は記録しない）
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "user_reset_password",
+        Some(&resource),
+        Some(&format!("Reset password of '{}' (must change at next login: {})", target.username, require_change)),
+    ).await;
+
+    sessions::revoke_all(&state, &client, Some(&current_user), &target, Some(&session), "password reset").await;
+    api_tokens::revoke_all(&state, &client, &current_user, &target, "password reset").await;
+
+    let message = format!("'{}' のパスワードをリセットしました", target.username);
+    render_users_page(&state, &current_user, Some(message), None).await
+}
+
+/// 次回ログイン時にパスワードの変更を求める
+pub async fn require_password_change(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, Redirect> {
+    let resource = format!("/users/{}/require-password-change", id);
+
+    let target = match db::get_user_by_id(&state.db, id).await {
+        Ok(Some(user)) => user,
+        _ => return render_users_page(&state, &current_user, None, Some("ユーザーが見つかりません".into())).await,
+    };
+    if !target.has_local_password() {
+        return render_users_page(&state, &current_user, None, Some("外部認証のユーザーのパスワードは変更できません".into())).await;
+    }
+
+    if db::set_must_change_password(&state.db, target.id, true).await.is_err() {
+        return render_users_page(&state, &current_user, None, Some("データベースエラーが発生しました".into())).await;
+    }
+
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "user_require_password_change",
+        Some(&resource),
+        Some(&format!("Required '{}' to change password at next login", target.username)),
+    ).await;
+
+    let message = format!("'{}' に次回ログイン時のパスワード変更を求めました", target.username);
+    render_users_page(&state, &current_user, Some(message), None).await
+}
+
+pub async fn delete_user(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, Redirect> {
+    let resource = format!("/users/{}/delete", id);
+
+    if id == current_user.id {
+        return render_users_page(&state, &current_user, None, Some("自分自身は削除できません".into())).await;
+    }
+    let target = match db::get_user_by_id(&state.db, id).await {
+        Ok(Some(user)) => user,
+        _ => return render_users_page(&state, &current_user, None, Some("ユーザーが見つかりません".into())).await,
+    };
+
+    // ユーザーの行を消すと対応付けがたどれなくなるため、先にセッションを失効させる
+    // （APIトークンは外部キーの ON DELETE CASCADE でユーザーと一緒に削除される）
+    sessions::revoke_all(&state, &client, Some(&current_user), &target, None, "user deleted").await;
+
+    if db::delete_user(&state.db, target.id).await.is_err() {
+        return render_users_page(&state, &current_user, None, Some("データベースエラーが発生しました".into())).await;
+    }
+
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "user_delete",
+        Some(&resource),
+        Some(&format!("Deleted user '{}' (id: {}, role: {})", target.username, target.id, target.role())),
+    ).await;
+
+    let message = format!("ユーザー '{}' を削除しました", target.username);
+    render_users_page(&state, &current_user, Some(message), None).await
+}
+
+pub async fn disable_user(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, Redirect> {
+    set_disabled(&state, &current_user, &client, id, true).await
+}
+
+pub async fn enable_user(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, Redirect> {
+    set_disabled(&state, &current_user, &client, id, false).await
+}
+
+/// ユーザーを無効化・再有効化する（無効化されたユーザーのセッションは次のリクエストで破棄され、APIトークンは失効させる）
+async fn set_disabled(
+    state: &AppState,
+    current_user: &db::User,
+    client: &db::ClientInfo,
+    id: i32,
+    disabled: bool,
+) -> Result<Html<String>, Redirect> {
+    let (resource, action, verb) = if disabled {
+        (format!("/users/{}/disable", id), "user_disable", "無効化")
+    } else {
+        (format!("/users/{}/enable", id), "user_enable", "有効化")
+    };
+
+    if disabled && id == current_user.id {
+        return render_users_page(state, current_user, None, Some("自分自身は無効化できません".into())).await;
+    }
+    let target = match db::get_user_by_id(&state.db, id).await {
+        Ok(Some(user)) => user,
+        _ => return render_users_page(state, current_user, None, Some("ユーザーが見つかりません".into())).await,
+    };
+
+    if db::set_user_disabled(&state.db, target.id, disabled).await.is_err() {
+        return render_users_page(state, current_user, None, Some("データベースエラーが発生しました".into())).await;
+    }
+
+    // 監査ログに記録
+    let details = if disabled {
+        format!("Disabled user '{}'", target.username)
+    } else {
+        format!("Enabled user '{}'", target.username)
+    };
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        client,
+        Some(current_user.id),
+        &current_user.username,
+        action,
+        Some(&resource),
+        Some(&details),
+    ).await;
+
+    if disabled {
+        api_tokens::revoke_all(state, client, current_user, &target, "user disabled").await;
+    }
+
+    let message = format!("ユーザー '{}' を{}しました", target.username, verb);
+    render_users_page(state, current_user, Some(message), None).await
+}
+
+pub async fn reset_two_factor(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, Redirect> {
+    let resource = format!("/users/{}/2fa/reset", id);
+
+    let target = match db::get_user_by_id(&state.db, id).await {
+        Ok(Some(user)) => user,
+        _ => return render_users_page(&state, &current_user, None, Some("ユーザーが見つかりません".into())).await,
+    };
+
+    if db::delete_totp(&state.db, target.id).await.is_err() {
+        return render_users_page(&state, &current_user, None, Some("データベースエラーが発生しました".into())).await;
+    }
+
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "2fa_reset",
+        Some(&resource),
+        Some(&format!("Reset two-factor authentication of '{}'", target.username)),
+    ).await;
+
+    let message = format!("'{}' の二要素認証をリセットしました", target.username);
+    render_users_page(&state, &current_user, Some(message), None).await
+}
+
+pub async fn update_two_factor_settings(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Form(form): Form<TwoFactorSettingsForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let require_admin_2fa = form.require_admin_2fa.is_some();
+    let value = if require_admin_2fa { "true" } else { "false" };
+    if db::set_setting(&state.db, db::SETTING_REQUIRE_2FA_ADMIN, value, &current_user.username).await.is_err() {
+        return render_users_page(&state, &current_user, None, Some("データベースエラーが発生しました".into())).await;
+    }
+
+    // 監査ログに記録
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        &client,
+        Some(current_user.id),
+        &current_user.username,
+        "settings_update",
+        Some("/users/settings/2fa"),
+        Some(&format!("Set {} to {}", db::SETTING_REQUIRE_2FA_ADMIN, value)),
+    ).await;
+
+    let message = if require_admin_2fa {
+        "Adminロールの二要素認証を必須にしました"
+    } else {
+        "Adminロールの二要素認証を任意にしました"
+    };
+    render_users_page(&state, &current_user, Some(message.to_string()), None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_usernames_with_allowed_characters() {
+        assert!(validate_username("alice").is_ok());
+        assert!(validate_username("bob.smith_2-dev@example.com").is_ok());
+        assert!(validate_username(&"a".repeat(MAX_USERNAME_LENGTH)).is_ok());
+    }
+
+    #[test]
+    fn rejects_empty_and_long_usernames() {
+        assert!(validate_username("").is_err());
+        assert!(validate_username(&"a".repeat(MAX_USERNAME_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn rejects_disallowed_characters() {
+        for username in ["alice bob", "alice/..", "<script>", "ユーザー", "alice\tbob", "alice\u{0}"] {
+            assert!(validate_username(username).is_err(), "{:?}", username);
+        }
+    }
+}
@@ -0,0 +1,672 @@
+//! シークレット保管庫
+//!
+//! 名前を付けたシークレットの値をサーバーのマスターキー（`VAULT_MASTER_KEY`）で
+//! AES-256-GCM により暗号化して保存する。値は更新のたびに新しいバージョンとして追加し、
+//! 表示は監査ログに記録される「表示」操作でのみ行う（一覧や詳細のページには値を出さない）。
+//!
+//! 値の表示と更新ができるのは、作成したユーザー（所有者）と共有されたユーザー・ロールのみ。
+//! 共有先の変更と削除は所有者と `users.manage` 権限を持つユーザーができるが、
+//! 後者も共有されていなければ値は表示できない。
+
+use aes_gcm::{
+    Aes256Gcm, KeyInit, Nonce,
+    aead::{Aead, Payload},
+};
+use askama::Template;
+use axum::{
+    extract::{Form, Path, State},
+    response::{Html, IntoResponse, Redirect},
+};
+use chrono::{DateTime, Local, Utc};
+use rand::RngCore;
+use serde::Deserialize;
+use rust_dashboard::config::VaultConfig;
+use crate::{AppState, db};
+use crate::routes::context::{ClientContext, CurrentUser};
+
+const MAX_NAME_LENGTH: usize = 100;
+const MAX_DESCRIPTION_LENGTH: usize = 255;
+const MAX_VALUE_BYTES: usize = 64 * 1024;
+const NONCE_LEN: usize = 12;
+const DISABLED_ERROR: &str = "VAULT_MASTER_KEY が設定されていないため、シークレットの作成・表示・更新はできません";
+
+#[derive(Template)]
+#[template(path = "vault.html")]
+pub struct VaultTemplate {
+    pub enabled: bool,
+    pub secrets: Vec<SecretDisplay>,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "vault_secret.html")]
+pub struct VaultSecretTemplate {
+    pub enabled: bool,
+    pub secret: SecretDisplay,
+    pub can_manage: bool,
+    pub versions: Vec<VersionDisplay>,
+    pub grants: Vec<GrantDisplay>,
+    pub grant_options: Vec<GrantOption>,
+    pub revealed: Option<RevealedDisplay>,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+pub struct SecretDisplay {
+    pub id: i32,
+    pub name: String,
+    pub description: String,
+    pub owner: String,
+    pub current_version: i32,
+    pub updated_at: String,
+    /// 値を表示・更新できる（所有者か共有先）
+    pub accessible: bool,
+}
+
+pub struct VersionDisplay {
+    pub version: i32,
+    pub created_by: String,
+    pub created_at: String,
+}
+
+pub struct GrantDisplay {
+    /// `user:<id>` または `role:<name>`（共有をやめるフォームで送り返す）
+    pub principal: String,
+    pub label: String,
+}
+
+pub struct GrantOption {
+    /// `user:<id>` または `role:<name>`
+    pub value: String,
+    pub label: String,
+}
+
+pub struct RevealedDisplay {
+    pub version: i32,
+    pub value: String,
+}
+
+#[derive(Deserialize)]
+pub struct CreateSecretForm {
+    name: String,
+    #[serde(default)]
+    description: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateSecretForm {
+    /// 編集を始めたときのバージョン（その後に他のユーザーが更新した場合は保存しない）
+    base_version: i32,
+    value: String,
+}
+
+#[derive(Deserialize)]
+pub struct RevealForm {
+    version: i32,
+}
+
+#[derive(Deserialize)]
+pub struct GrantForm {
+    principal: String,
+}
+
+fn format_local(time: DateTime<Utc>) -> String {
+    let local_time: DateTime<Local> = DateTime::from(time);
+    local_time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn secret_display(secret: db::VaultSecret, accessible: bool) -> SecretDisplay {
+    SecretDisplay {
+        id: secret.id,
+        name: secret.name,
+        description: secret.description,
+        owner: secret.owner_username.unwrap_or_else(|| "-".to_string()),
+        current_version: secret.current_version,
+        updated_at: format_local(secret.updated_at),
+        accessible,
+    }
+}
+
+// 暗号文をシークレットの名前とバージョンに結び付け、別の行へのコピーを検出できるようにする
+fn aad(name: &str, version: i32) -> Vec<u8> {
+    format!("vault:{}:{}", name, version).into_bytes()
+}
+
+/// 値をマスターキーで暗号化し、ナンスと暗号文を返す
+fn seal(config: &VaultConfig, name: &str, version: i32, value: &str) -> anyhow::Result<([u8; NONCE_LEN], Vec<u8>)> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::rng().fill_bytes(&mut nonce);
+
+    let cipher = Aes256Gcm::new(&config.master_key.into());
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: value.as_bytes(), aad: &aad(name, version) })
+        .map_err(|e| anyhow::anyhow!("暗号化に失敗: {}", e))?;
+    Ok((nonce, ciphertext))
+}
+
+fn open(config: &VaultConfig, name: &str, row: &db::VaultSecretVersion) -> anyhow::Result<String> {
+    if row.key_id != config.key_id {
+        anyhow::bail!("別のマスターキー（{}）で暗号化されています", row.key_id);
+    }
+    if row.nonce.len() != NONCE_LEN {
+        anyhow::bail!("保存されたナンスが不正です");
+    }
+
+    let cipher = Aes256Gcm::new(&config.master_key.into());
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&row.nonce), Payload { msg: &row.ciphertext, aad: &aad(name, row.version) })
+        .map_err(|_| anyhow::anyhow!("復号化に失敗しました（データが改ざんされている可能性があります）"))?;
+    String::from_utf8(plaintext).map_err(|e| anyhow::anyhow!("UTF-8変換に失敗: {}", e))
+}
+
+/// フォームで共有先を表す値（`user:<id>` または `role:<name>`）
+fn principal(grant: &db::VaultGrant) -> String {
+    match grant {
+        db::VaultGrant::User { user_id, .. } => format!("user:{}", user_id),
+        db::VaultGrant::Role(role) => format!("role:{}", role),
+    }
+}
+
+/// 監査ログに残す共有先の説明
+fn describe_grant(grant: &db::VaultGrant) -> String {
+    match grant {
+        db::VaultGrant::User { username, .. } => format!("user '{}'", username),
+        db::VaultGrant::Role(role) => format!("role '{}'", role),
+    }
+}
+
+fn validate_value(value: &str) -> Result<(), String> {
+    if value.is_empty() {
+        return Err("値を入力してください".into());
+    }
+    if value.len() > MAX_VALUE_BYTES {
+        return Err(format!("値は{}KB以下にしてください", MAX_VALUE_BYTES / 1024));
+    }
+    Ok(())
+}
+
+/// 共有先の変更と削除ができるか（所有者か、ユーザー管理の権限を持つユーザー）
+async fn can_manage(state: &AppState, user: &db::User, secret: &db::VaultSecret) -> bool {
+    secret.owner_id == Some(user.id)
+        || db::role_has_permission(&state.db, user.role(), db::Permission::UsersManage)
+            .await
+            .unwrap_or(false)
+}
+
+async fn can_access(state: &AppState, user: &db::User, secret_id: i32) -> bool {
+    db::can_access_vault_secret(&state.db, secret_id, user.id, user.role())
+        .await
+        .unwrap_or(false)
+}
+
+async fn audit(
+    state: &AppState,
+    client: &db::ClientInfo,
+    user: &db::User,
+    action: &str,
+    secret_id: i32,
+    details: &str,
+) {
+    let _ = db::create_audit_log(
+        &state.db,
+        &state.audit_events,
+        client,
+        Some(user.id),
+        &user.username,
+        action,
+        Some(&format!("/vault/{}", secret_id)),
+        Some(details),
+    ).await;
+}
+
+async fn render_list(
+    state: &AppState,
+    user: &db::User,
+    message: Option<String>,
+    error: Option<String>,
+) -> Result<Html<String>, Redirect> {
+    let accessible = db::list_accessible_vault_secrets(&state.db, user.id, user.role())
+        .await
+        .map_err(|_| Redirect::to("/"))?;
+
+    // ユーザー管理の権限を持つユーザーには、共有先を管理できるようにすべてのシークレットを表示する
+    let is_manager = db::role_has_permission(&state.db, user.role(), db::Permission::UsersManage)
+        .await
+        .unwrap_or(false);
+    let secrets = if is_manager {
+        let accessible_ids: Vec<i32> = accessible.iter().map(|secret| secret.id).collect();
+        db::list_vault_secrets(&state.db)
+            .await
+            .map_err(|_| Redirect::to("/"))?
+            .into_iter()
+            .map(|secret| {
+                let accessible = accessible_ids.contains(&secret.id);
+                secret_display(secret, accessible)
+            })
+            .collect()
+    } else {
+        accessible.into_iter().map(|secret| secret_display(secret, true)).collect()
+    };
+
+    let template = VaultTemplate {
+        enabled: state.vault.is_some(),
+        secrets,
+        message,
+        error,
+    };
+
+    match template.render() {
+        Ok(html) => Ok(Html(html)),
+        Err(_) => Err(Redirect::to("/")),
+    }
+}
+
+/// 詳細ページを表示する（値を表示・更新できず、共有先も管理できないユーザーは一覧に戻す）
+async fn render_detail(
+    state: &AppState,
+    user: &db::User,
+    id: i32,
+    revealed: Option<RevealedDisplay>,
+    message: Option<String>,
+    error: Option<String>,
+) -> Result<Html<String>, Redirect> {
+    let secret = match db::get_vault_secret(&state.db, id).await {
+        Ok(Some(secret)) => secret,
+        _ => return Err(Redirect::to("/vault")),
+    };
+    let accessible = can_access(state, user, id).await;
+    let can_manage = can_manage(state, user, &secret).await;
+    if !accessible && !can_manage {
+        return Err(Redirect::to("/vault"));
+    }
+
+    let versions = db::list_vault_secret_versions(&state.db, id)
+        .await
+        .map_err(|_| Redirect::to("/vault"))?;
+    let grants = db::list_vault_grants(&state.db, id)
+        .await
+        .map_err(|_| Redirect::to("/vault"))?;
+
+    let mut grant_options = Vec::new();
+    if can_manage {
+        let roles = db::list_roles(&state.db).await.map_err(|_| Redirect::to("/vault"))?;
+        let users = db::list_users(&state.db).await.map_err(|_| Redirect::to("/vault"))?;
+        grant_options.extend(roles.into_iter().map(|role| GrantOption {
+            value: format!("role:{}", role.name),
+            label: format!("ロール: {}", role.name),
+        }));
+        grant_options.extend(
+            users
+                .into_iter()
+                .filter(|target| !target.is_disabled() && Some(target.id) != secret.owner_id)
+                .map(|target| GrantOption {
+                    value: format!("user:{}", target.id),
+                    label: format!("ユーザー: {}", target.username),
+                }),
+        );
+    }
+
+    let template = VaultSecretTemplate {
+        enabled: state.vault.is_some(),
+        secret: secret_display(secret, accessible),
+        can_manage,
+        versions: versions
+            .into_iter()
+            .map(|version| VersionDisplay {
+                version: version.version,
+                created_by: version.created_by_username.unwrap_or_else(|| "-".to_string()),
+                created_at: format_local(version.created_at),
+            })
+            .collect(),
+        grants: grants
+            .into_iter()
+            .map(|grant| GrantDisplay {
+                principal: principal(&grant),
+                label: match grant {
+                    db::VaultGrant::Role(role) => format!("ロール: {}", role),
+                    db::VaultGrant::User { username, .. } => format!("ユーザー: {}", username),
+                },
+            })
+            .collect(),
+        grant_options,
+        revealed,
+        message,
+        error,
+    };
+
+    match template.render() {
+        Ok(html) => Ok(Html(html)),
+        Err(_) => Err(Redirect::to("/vault")),
+    }
+}
+
+pub async fn list(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+) -> Result<impl IntoResponse, Redirect> {
+    render_list(&state, &current_user, None, None).await
+}
+
+pub async fn create(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Form(form): Form<CreateSecretForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let Some(config) = &state.vault else {
+        return render_list(&state, &current_user, None, Some(DISABLED_ERROR.into())).await;
+    };
+
+    let name = form.name.trim();
+    let description = form.description.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        let error = format!("名前は1〜{}文字で入力してください", MAX_NAME_LENGTH);
+        return render_list(&state, &current_user, None, Some(error)).await;
+    }
+    if description.chars().count() > MAX_DESCRIPTION_LENGTH {
+        let error = format!("説明は{}文字以下にしてください", MAX_DESCRIPTION_LENGTH);
+        return render_list(&state, &current_user, None, Some(error)).await;
+    }
+    if let Err(error) = validate_value(&form.value) {
+        return render_list(&state, &current_user, None, Some(error)).await;
+    }
+
+    let (nonce, ciphertext) = match seal(config, name, 1, &form.value) {
+        Ok(sealed) => sealed,
+        Err(e) => return render_list(&state, &current_user, None, Some(e.to_string())).await,
+    };
+    let id = match db::create_vault_secret(&state.db, name, description, current_user.id, &config.key_id, &nonce, &ciphertext).await {
+        Ok(id) => id,
+        Err(e) if e.as_database_error().is_some_and(|db_err| db_err.is_unique_violation()) => {
+            let error = format!("シークレット '{}' は既に存在します", name);
+            return render_list(&state, &current_user, None, Some(error)).await;
+        }
+        Err(_) => return render_list(&state, &current_user, None, Some("データベースエラーが発生しました".into())).await,
+    };
+
+    // 監査ログに記録（値は記録しない）
+    let details = format!("Created secret '{}' (version 1, length: {})", name, form.value.len());
+    audit(&state, &client, &current_user, "vault_create", id, &details).await;
+
+    render_detail(&state, &current_user, id, None, Some(format!("シークレット '{}' を作成しました", name)), None).await
+}
+
+pub async fn detail(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, Redirect> {
+    render_detail(&state, &current_user, id, None, None, None).await
+}
+
+/// 値を表示する（表示のたびに監査ログに記録する）
+pub async fn reveal(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Path(id): Path<i32>,
+    Form(form): Form<RevealForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let Ok(Some(secret)) = db::get_vault_secret(&state.db, id).await else {
+        return Err(Redirect::to("/vault"));
+    };
+    if !can_access(&state, &current_user, id).await {
+        let details = format!("Denied reveal of secret '{}' (version {}): not shared with user", secret.name, form.version);
+        audit(&state, &client, &current_user, "vault_reveal_denied", id, &details).await;
+        return render_detail(&state, &current_user, id, None, None, Some("このシークレットは共有されていません".into())).await;
+    }
+    let Some(config) = &state.vault else {
+        return render_detail(&state, &current_user, id, None, None, Some(DISABLED_ERROR.into())).await;
+    };
+
+    let row = match db::get_vault_secret_version(&state.db, id, form.version).await {
+        Ok(Some(row)) => row,
+        _ => return render_detail(&state, &current_user, id, None, None, Some("バージョンが見つかりません".into())).await,
+    };
+
+    match open(config, &secret.name, &row) {
+        Ok(value) => {
+            let details = format!("Revealed secret '{}' (version {})", secret.name, row.version);
+            audit(&state, &client, &current_user, "vault_reveal", id, &details).await;
+
+            let revealed = RevealedDisplay { version: row.version, value };
+            render_detail(&state, &current_user, id, Some(revealed), None, None).await
+        }
+        Err(e) => {
+            // エラーも記録
+            let details = format!("Reveal of secret '{}' (version {}) failed: {}", secret.name, row.version, e);
+            audit(&state, &client, &current_user, "vault_reveal_failed", id, &details).await;
+
+            render_detail(&state, &current_user, id, None, None, Some(format!("復号化エラー: {}", e))).await
+        }
+    }
+}
+
+/// 値を新しいバージョンとして保存する
+pub async fn update(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Path(id): Path<i32>,
+    Form(form): Form<UpdateSecretForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let Ok(Some(secret)) = db::get_vault_secret(&state.db, id).await else {
+        return Err(Redirect::to("/vault"));
+    };
+    if !can_access(&state, &current_user, id).await {
+        return render_detail(&state, &current_user, id, None, None, Some("このシークレットは共有されていません".into())).await;
+    }
+    let Some(config) = &state.vault else {
+        return render_detail(&state, &current_user, id, None, None, Some(DISABLED_ERROR.into())).await;
+    };
+    if let Err(error) = validate_value(&form.value) {
+        return render_detail(&state, &current_user, id, None, None, Some(error)).await;
+    }
+
+    let Some(version) = form.base_version.checked_add(1) else {
+        return render_detail(&state, &current_user, id, None, None, Some("バージョンが正しくありません".into())).await;
+    };
+    let (nonce, ciphertext) = match seal(config, &secret.name, version, &form.value) {
+        Ok(sealed) => sealed,
+        Err(e) => return render_detail(&state, &current_user, id, None, None, Some(e.to_string())).await,
+    };
+    match db::add_vault_secret_version(&state.db, id, version, current_user.id, &config.key_id, &nonce, &ciphertext).await {
+        Ok(true) => {}
+        Ok(false) => {
+            let error = "編集中に他のユーザーが更新しました。最新のバージョンを確認してからもう一度保存してください";
+            return render_detail(&state, &current_user, id, None, None, Some(error.into())).await;
+        }
+        Err(_) => return render_detail(&state, &current_user, id, None, None, Some("データベースエラーが発生しました".into())).await,
+    }
+
+    // 監査ログに記録（値は記録しない）
+    let details = format!("Updated secret '{}' to version {} (length: {})", secret.name, version, form.value.len());
+    audit(&state, &client, &current_user, "vault_update", id, &details).await;
+
+    let message = format!("バージョン {} を保存しました", version);
+    render_detail(&state, &current_user, id, None, Some(message), None).await
+}
+
+pub async fn add_grant(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Path(id): Path<i32>,
+    Form(form): Form<GrantForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let Ok(Some(secret)) = db::get_vault_secret(&state.db, id).await else {
+        return Err(Redirect::to("/vault"));
+    };
+    if !can_manage(&state, &current_user, &secret).await {
+        return Err(Redirect::to("/vault"));
+    }
+
+    let grant = match form.principal.split_once(':') {
+        Some(("user", user_id)) => match user_id.parse() {
+            Ok(user_id) => match db::get_user_by_id(&state.db, user_id).await {
+                Ok(Some(target)) => Some(db::VaultGrant::User { user_id: target.id, username: target.username }),
+                _ => None,
+            },
+            Err(_) => None,
+        },
+        Some(("role", role)) => match db::get_role(&state.db, role).await {
+            Ok(Some(role)) => Some(db::VaultGrant::Role(role.name)),
+            _ => None,
+        },
+        _ => None,
+    };
+    let Some(grant) = grant else {
+        return render_detail(&state, &current_user, id, None, None, Some("共有先が見つかりません".into())).await;
+    };
+
+    let added = match &grant {
+        db::VaultGrant::User { user_id, .. } => db::add_vault_user_grant(&state.db, id, *user_id).await,
+        db::VaultGrant::Role(role) => db::add_vault_role_grant(&state.db, id, role).await,
+    };
+    match added {
+        Ok(true) => {}
+        Ok(false) => return render_detail(&state, &current_user, id, None, None, Some("既に共有されています".into())).await,
+        Err(_) => {
+            return render_detail(&state, &current_user, id, None, None, Some("データベースエラーが発生しました".into())).await;
+        }
+    }
+
+    let details = format!("Shared secret '{}' with {}", secret.name, describe_grant(&grant));
+    audit(&state, &client, &current_user, "vault_share", id, &details).await;
+
+    render_detail(&state, &current_user, id, None, Some("共有先を追加しました".into()), None).await
+}
+
+pub async fn delete_grant(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Path(id): Path<i32>,
+    Form(form): Form<GrantForm>,
+) -> Result<impl IntoResponse, Redirect> {
+    let Ok(Some(secret)) = db::get_vault_secret(&state.db, id).await else {
+        return Err(Redirect::to("/vault"));
+    };
+    if !can_manage(&state, &current_user, &secret).await {
+        return Err(Redirect::to("/vault"));
+    }
+
+    let grants = db::list_vault_grants(&state.db, id).await.unwrap_or_default();
+    let Some(grant) = grants.into_iter().find(|grant| principal(grant) == form.principal) else {
+        return render_detail(&state, &current_user, id, None, None, Some("共有先が見つかりません".into())).await;
+    };
+    let deleted = match &grant {
+        db::VaultGrant::User { user_id, .. } => db::delete_vault_user_grant(&state.db, id, *user_id).await,
+        db::VaultGrant::Role(role) => db::delete_vault_role_grant(&state.db, id, role).await,
+    };
+    if !matches!(deleted, Ok(true)) {
+        return render_detail(&state, &current_user, id, None, None, Some("データベースエラーが発生しました".into())).await;
+    }
+
+    let details = format!("Stopped sharing secret '{}' with {}", secret.name, describe_grant(&grant));
+    audit(&state, &client, &current_user, "vault_unshare", id, &details).await;
+
+    render_detail(&state, &current_user, id, None, Some("共有先を削除しました".into()), None).await
+}
+
+pub async fn delete(
+    State(state): State<AppState>,
+    CurrentUser(current_user): CurrentUser,
+    ClientContext(client): ClientContext,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, Redirect> {
+    let Ok(Some(secret)) = db::get_vault_secret(&state.db, id).await else {
+        return Err(Redirect::to("/vault"));
+    };
+    if !can_manage(&state, &current_user, &secret).await {
+        return Err(Redirect::to("/vault"));
+    }
+    if db::delete_vault_secret(&state.db, id).await.is_err() {
+        return render_detail(&state, &current_user, id, None, None, Some("データベースエラーが発生しました".into())).await;
+    }
+
+    let details = format!("Deleted secret '{}' with {} version(s)", secret.name, secret.current_version);
+    audit(&state, &client, &current_user, "vault_delete", id, &details).await;
+
+    render_list(&state, &current_user, Some(format!("シークレット '{}' を削除しました", secret.name)), None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(master_key: [u8; 32], key_id: &str) -> VaultConfig {
+        VaultConfig { master_key, key_id: key_id.to_string() }
+    }
+
+    fn row(config: &VaultConfig, version: i32, (nonce, ciphertext): ([u8; NONCE_LEN], Vec<u8>)) -> db::VaultSecretVersion {
+        db::VaultSecretVersion {
+            version,
+            key_id: config.key_id.clone(),
+            nonce: nonce.to_vec(),
+            ciphertext,
+            created_by_username: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn sealed_value_opens() {
+        let config = config([1u8; 32], "0123456789abcdef");
+        let sealed = row(&config, 3, seal(&config, "db-password", 3, "s3cr3t").unwrap());
+        assert_eq!(open(&config, "db-password", &sealed).unwrap(), "s3cr3t");
+    }
+
+    #[test]
+    fn sealing_uses_a_fresh_nonce() {
+        let config = config([1u8; 32], "0123456789abcdef");
+        let (first_nonce, first) = seal(&config, "name", 1, "value").unwrap();
+        let (second_nonce, second) = seal(&config, "name", 1, "value").unwrap();
+        assert_ne!(first_nonce, second_nonce);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn value_copied_to_another_secret_does_not_open() {
+        let config = config([1u8; 32], "0123456789abcdef");
+        let sealed = row(&config, 1, seal(&config, "db-password", 1, "s3cr3t").unwrap());
+        assert!(open(&config, "api-key", &sealed).is_err());
+    }
+
+    #[test]
+    fn value_copied_to_another_version_does_not_open() {
+        let config = config([1u8; 32], "0123456789abcdef");
+        let mut sealed = row(&config, 1, seal(&config, "db-password", 1, "s3cr3t").unwrap());
+        sealed.version = 2;
+        assert!(open(&config, "db-password", &sealed).is_err());
+    }
+
+    #[test]
+    fn tampered_ciphertext_does_not_open() {
+        let config = config([1u8; 32], "0123456789abcdef");
+        let mut sealed = row(&config, 1, seal(&config, "name", 1, "value").unwrap());
+        sealed.ciphertext[0] ^= 0x01;
+        assert!(open(&config, "name", &sealed).is_err());
+    }
+
+    #[test]
+    fn value_sealed_with_another_master_key_is_rejected() {
+        let old = config([1u8; 32], "0123456789abcdef");
+        let sealed = row(&old, 1, seal(&old, "name", 1, "value").unwrap());
+
+        let error = open(&config([2u8; 32], "fedcba9876543210"), "name", &sealed).unwrap_err();
+        assert!(error.to_string().contains("0123456789abcdef"), "{}", error);
+        // 識別子が同じでも鍵が違えば認証に失敗する
+        assert!(open(&config([2u8; 32], "0123456789abcdef"), "name", &sealed).is_err());
+    }
+
+    #[test]
+    fn malformed_nonce_is_rejected() {
+        let config = config([1u8; 32], "0123456789abcdef");
+        let mut sealed = row(&config, 1, seal(&config, "name", 1, "value").unwrap());
+        sealed.nonce.pop();
+        assert!(open(&config, "name", &sealed).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::MySqlPool;
+use tower_sessions::cookie::time::OffsetDateTime;
+use tower_sessions::session::{Id, Record};
+use tower_sessions::session_store::{self, ExpiredDeletion, SessionStore};
+
+/// ログインしたユーザーの ID を保存するセッションのキー
+///
+/// ストアはこの値を `sessions.user_id` にも書き込み、ユーザーごとのセッションの一覧・失効に使う。
+pub const USER_ID_KEY: &str = "user_id";
+
+/// `sessions` テーブルにセッションを保存する tower-sessions 用ストア
+#[derive(Debug, Clone)]
+pub struct MySqlStore {
+    pool: MySqlPool,
+}
+
+impl MySqlStore {
+    pub fn new(pool: MySqlPool) -> Self {
+        Self { pool }
+    }
+}
+
+fn backend_error(e: sqlx::Error) -> session_store::Error {
+    session_store::Error::Backend(e.to_string())
+}
+
+fn user_id(record: &Record) -> Option<i32> {
+    record
+        .data
+        .get(USER_ID_KEY)
+        .and_then(|value| value.as_i64())
+        .and_then(|value| i32::try_from(value).ok())
+}
+
+fn to_utc(expiry_date: OffsetDateTime) -> DateTime<Utc> {
+    DateTime::from_timestamp(expiry_date.unix_timestamp(), 0).unwrap_or_default()
+}
+
+#[async_trait]
+impl SessionStore for MySqlStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        let data = serde_json::to_string(&record.data)
+            .map_err(|e| session_store::Error::Encode(e.to_string()))?;
+
+        // ID が衝突した場合は新しい ID を振り直す
+        loop {
+            let result = sqlx::query(
+                "INSERT INTO sessions (id, user_id, data, expiry_date) VALUES (?, ?, ?, ?)"
+            )
+            .bind(record.id.to_string())
+            .bind(user_id(record))
+            .bind(&data)
+            .bind(to_utc(record.expiry_date))
+            .execute(&self.pool)
+            .await;
+
+            match result {
+                Ok(_) => return Ok(()),
+                Err(e) if e.as_database_error().is_some_and(|db_err| db_err.is_unique_violation()) => {
+                    record.id = Id::default();
+                }
+                Err(e) => return Err(backend_error(e)),
+            }
+        }
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        let data = serde_json::to_string(&record.data)
+            .map_err(|e| session_store::Error::Encode(e.to_string()))?;
+
+        // 読み込んだ後に失効（削除）されたセッションを作り直さないよう、既存の行のみ更新する
+        sqlx::query(
+            "UPDATE sessions SET user_id = ?, data = ?, expiry_date = ? WHERE id = ?"
+        )
+        .bind(user_id(record))
+        .bind(&data)
+        .bind(to_utc(record.expiry_date))
+        .bind(record.id.to_string())
+        .execute(&self.pool)
+        .await
+        .map_err(backend_error)?;
+
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let row: Option<(String, DateTime<Utc>)> = sqlx::query_as(
+            "SELECT data, expiry_date FROM sessions WHERE id = ? AND expiry_date > ?"
+        )
+        .bind(session_id.to_string())
+        .bind(Utc::now())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(backend_error)?;
+
+        let Some((data, expiry_date)) = row else {
+            return Ok(None);
+        };
+
+        let data = serde_json::from_str(&data)
+            .map_err(|e| session_store::Error::Decode(e.to_string()))?;
+        let expiry_date = OffsetDateTime::from_unix_timestamp(expiry_date.timestamp())
+            .map_err(|e| session_store::Error::Decode(e.to_string()))?;
+
+        Ok(Some(Record {
+            id: *session_id,
+            data,
+            expiry_date,
+        }))
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        sqlx::query("DELETE FROM sessions WHERE id = ?")
+            .bind(session_id.to_string())
+            .execute(&self.pool)
+            .await
+            .map_err(backend_error)?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ExpiredDeletion for MySqlStore {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        sqlx::query("DELETE FROM sessions WHERE expiry_date <= ?")
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await
+            .map_err(backend_error)?;
+
+        Ok(())
+    }
+}
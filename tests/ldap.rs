@@ -0,0 +1,337 @@
+//! LDAP 認証をローカルのモックディレクトリに対して実行する
+//!
+//! モックは LDAPv3 の bind・search・unbind だけに応答する最小限のサーバーで、
+//! 受け取った bind DN と検索条件を記録してエスケープの確認に使う。
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ldap3::asn1::{PL, StructureTag, TagClass, parse_tag};
+use rust_dashboard::config::{LdapConfig, RoleMapping};
+use rust_dashboard::ldap;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const BASE_DN: &str = "ou=users,dc=example,dc=org";
+const GROUP_BASE: &str = "ou=groups,dc=example,dc=org";
+
+// LDAP の結果コード
+const RC_SUCCESS: u8 = 0;
+const RC_INVALID_CREDENTIALS: u8 = 49;
+
+/// 検索条件（等価比較のみ対応し、それ以外は `Unsupported`）
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Equality { attribute: String, value: String },
+    Unsupported,
+}
+
+#[derive(Default)]
+struct Directory {
+    /// (DN, パスワード)
+    users: Vec<(String, String)>,
+    /// (グループ名, メンバーの値（DN または uid）)
+    groups: Vec<(String, Vec<String>)>,
+    binds: Mutex<Vec<String>>,
+    searches: Mutex<Vec<Filter>>,
+}
+
+// ---- BER のエンコード（応答の組み立て用） ----
+
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len.to_be_bytes().into_iter().skip_while(|b| *b == 0).collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend(bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+fn integer(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = 0;
+    while start < 7
+        && ((bytes[start] == 0 && bytes[start + 1] & 0x80 == 0) || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0))
+    {
+        start += 1;
+    }
+    tlv(0x02, &bytes[start..])
+}
+
+fn octets(value: &str) -> Vec<u8> {
+    tlv(0x04, value.as_bytes())
+}
+
+fn message(id: i64, op: Vec<u8>) -> Vec<u8> {
+    tlv(0x30, &[integer(id), op].concat())
+}
+
+fn ldap_result(app_tag: u8, rc: u8) -> Vec<u8> {
+    tlv(app_tag, &[tlv(0x0a, &[rc]), octets(""), octets("")].concat())
+}
+
+fn search_entry(dn: &str, attribute: &str, values: &[&str]) -> Vec<u8> {
+    let values: Vec<u8> = values.iter().flat_map(|value| octets(value)).collect();
+    let attribute = tlv(0x30, &[octets(attribute), tlv(0x31, &values)].concat());
+    tlv(0x64, &[octets(dn), tlv(0x30, &attribute)].concat())
+}
+
+// ---- BER のデコード（リクエストの読み取り用） ----
+
+/// バッファの先頭にある TLV 全体の長さ（まだ揃っていなければ `None`）
+fn frame_len(buf: &[u8]) -> Option<usize> {
+    let first = *buf.get(1)?;
+    let (header, len) = if first & 0x80 == 0 {
+        (2, first as usize)
+    } else {
+        let count = (first & 0x7f) as usize;
+        let bytes = buf.get(2..2 + count)?;
+        (2 + count, bytes.iter().fold(0usize, |len, b| (len << 8) | *b as usize))
+    };
+    (buf.len() >= header + len).then_some(header + len)
+}
+
+fn children(tag: StructureTag) -> Vec<StructureTag> {
+    tag.expect_constructed().unwrap_or_default()
+}
+
+fn primitive(tag: &StructureTag) -> Vec<u8> {
+    match &tag.payload {
+        PL::P(bytes) => bytes.clone(),
+        PL::C(_) => Vec::new(),
+    }
+}
+
+fn text(tag: &StructureTag) -> String {
+    String::from_utf8(primitive(tag)).unwrap()
+}
+
+fn parse_filter(tag: StructureTag) -> Filter {
+    if tag.class != TagClass::Context || tag.id != 3 {
+        return Filter::Unsupported;
+    }
+    let parts = children(tag);
+    match parts.as_slice() {
+        [attribute, value] => Filter::Equality { attribute: text(attribute), value: text(value) },
+        _ => Filter::Unsupported,
+    }
+}
+
+async fn handle(mut socket: TcpStream, directory: Arc<Directory>) {
+    let mut buf = Vec::new();
+    let mut read_buf = [0u8; 4096];
+    loop {
+        let Some(len) = frame_len(&buf) else {
+            match socket.read(&mut read_buf).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => buf.extend_from_slice(&read_buf[..n]),
+            }
+            continue;
+        };
+        let frame: Vec<u8> = buf.drain(..len).collect();
+        let (_, tag) = parse_tag(&frame).expect("malformed LDAP message");
+
+        let mut parts = children(tag).into_iter();
+        let id = primitive(&parts.next().unwrap()).iter().fold(0i64, |id, b| (id << 8) | *b as i64);
+        let op = parts.next().unwrap();
+
+        let response = match (op.class, op.id) {
+            // BindRequest
+            (TagClass::Application, 0) => {
+                let fields = children(op);
+                let dn = text(&fields[1]);
+                let password = text(&fields[2]);
+                directory.binds.lock().unwrap().push(dn.clone());
+
+                let valid = directory.users.iter().any(|(user_dn, user_password)| *user_dn == dn && *user_password == password);
+                message(id, ldap_result(0x61, if valid { RC_SUCCESS } else { RC_INVALID_CREDENTIALS }))
+            }
+            // SearchRequest
+            (TagClass::Application, 3) => {
+                let fields = children(op);
+                let filter = parse_filter(fields[6].clone());
+                directory.searches.lock().unwrap().push(filter.clone());
+
+                let mut response = Vec::new();
+                if let Filter::Equality { value, .. } = &filter {
+                    for (name, members) in &directory.groups {
+                        if members.contains(value) {
+                            let dn = format!("cn={},{}", name, GROUP_BASE);
+                            response.extend(message(id, search_entry(&dn, "cn", &[name])));
+                        }
+                    }
+                }
+                response.extend(message(id, ldap_result(0x65, RC_SUCCESS)));
+                response
+            }
+            // UnbindRequest
+            (TagClass::Application, 2) => return,
+            _ => panic!("unexpected LDAP operation: {:?} {}", op.class, op.id),
+        };
+        if socket.write_all(&response).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn start_directory(directory: Directory) -> (Arc<Directory>, LdapConfig) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ldap://{}", listener.local_addr().unwrap());
+
+    let directory = Arc::new(directory);
+    let shared = directory.clone();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            tokio::spawn(handle(socket, shared.clone()));
+        }
+    });
+
+    let config = LdapConfig {
+        url,
+        starttls: false,
+        bind_dn_template: format!("uid={{username}},{}", BASE_DN),
+        group_base: Some(GROUP_BASE.to_string()),
+        group_filter: "(member={dn})".to_string(),
+        group_attribute: "cn".to_string(),
+        role_mapping: RoleMapping {
+            rules: vec![
+                ("dashboard-admins".to_string(), "admin".to_string()),
+                ("dashboard-users".to_string(), "user".to_string()),
+            ],
+            default_role: None,
+        },
+        sync_users: true,
+        timeout: Duration::from_secs(5),
+    };
+    (directory, config)
+}
+
+fn user_dn(uid: &str) -> String {
+    format!("uid={},{}", uid, BASE_DN)
+}
+
+fn sample_directory() -> Directory {
+    Directory {
+        users: vec![
+            (user_dn("alice"), "alicepw".to_string()),
+            (user_dn("bob"), "bobpw".to_string()),
+            (user_dn("carol"), "carolpw".to_string()),
+        ],
+        groups: vec![
+            ("dashboard-users".to_string(), vec![user_dn("alice"), user_dn("bob")]),
+            ("dashboard-admins".to_string(), vec![user_dn("bob")]),
+            ("readers".to_string(), vec![user_dn("carol")]),
+        ],
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn bind_succeeds_and_returns_groups() {
+    let (directory, config) = start_directory(sample_directory()).await;
+
+    let account = ldap::authenticate(&config, "alice", "alicepw").await.unwrap().unwrap();
+    assert_eq!(account.dn, user_dn("alice"));
+    assert_eq!(account.groups, ["dashboard-users"]);
+
+    assert_eq!(*directory.binds.lock().unwrap(), [user_dn("alice")]);
+    assert_eq!(
+        *directory.searches.lock().unwrap(),
+        [Filter::Equality { attribute: "member".to_string(), value: user_dn("alice") }]
+    );
+}
+
+#[tokio::test]
+async fn wrong_password_is_rejected() {
+    let (directory, config) = start_directory(sample_directory()).await;
+
+    assert!(ldap::authenticate(&config, "alice", "wrong").await.unwrap().is_none());
+    // 認証に失敗した場合はグループを検索しない
+    assert!(directory.searches.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn unknown_user_is_rejected() {
+    let (_, config) = start_directory(sample_directory()).await;
+
+    assert!(ldap::authenticate(&config, "mallory", "anything").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn empty_password_is_rejected_without_binding() {
+    let (directory, config) = start_directory(sample_directory()).await;
+
+    // 空のパスワードの simple bind は匿名 bind として成功してしまうため送らない
+    assert!(ldap::authenticate(&config, "alice", "").await.unwrap().is_none());
+    assert!(directory.binds.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn groups_are_mapped_to_roles() {
+    let (_, config) = start_directory(sample_directory()).await;
+
+    let alice = ldap::authenticate(&config, "alice", "alicepw").await.unwrap().unwrap();
+    assert_eq!(config.role_mapping.resolve(&alice.groups), Some("user"));
+
+    // 複数のグループに所属する場合は先に書いた対応が優先される
+    let bob = ldap::authenticate(&config, "bob", "bobpw").await.unwrap().unwrap();
+    assert_eq!(config.role_mapping.resolve(&bob.groups), Some("admin"));
+
+    // どのグループも対応しない場合は既定のロール（未設定ならログイン拒否）
+    let carol = ldap::authenticate(&config, "carol", "carolpw").await.unwrap().unwrap();
+    assert_eq!(carol.groups, ["readers"]);
+    assert_eq!(config.role_mapping.resolve(&carol.groups), None);
+    let with_default = RoleMapping { default_role: Some("viewer".to_string()), ..config.role_mapping.clone() };
+    assert_eq!(with_default.resolve(&carol.groups), Some("viewer"));
+}
+
+#[tokio::test]
+async fn without_group_base_groups_are_not_searched() {
+    let (directory, mut config) = start_directory(sample_directory()).await;
+    config.group_base = None;
+
+    let account = ldap::authenticate(&config, "alice", "alicepw").await.unwrap().unwrap();
+    assert!(account.groups.is_empty());
+    assert!(directory.searches.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn special_characters_in_username_are_escaped() {
+    let username = "doe, john";
+    let escaped_dn = format!("uid=doe\\2c john,{}", BASE_DN);
+    let (directory, config) = start_directory(Directory {
+        users: vec![(escaped_dn.clone(), "pw".to_string())],
+        ..Default::default()
+    })
+    .await;
+
+    // DN の区切り文字は DN 用にエスケープして埋め込む
+    let account = ldap::authenticate(&config, username, "pw").await.unwrap().unwrap();
+    assert_eq!(account.dn, escaped_dn);
+    assert_eq!(*directory.binds.lock().unwrap(), [escaped_dn]);
+}
+
+#[tokio::test]
+async fn filter_injection_in_username_is_escaped() {
+    let username = "x*)(uid=*";
+    let (directory, mut config) = start_directory(Directory {
+        users: vec![(user_dn("x*)(uid\\3d*"), "pw".to_string())],
+        groups: vec![("dashboard-users".to_string(), vec!["someone-else".to_string()])],
+        ..Default::default()
+    })
+    .await;
+    config.group_filter = "(memberUid={username})".to_string();
+
+    // 検索条件の特殊文字はエスケープされ、1つの等価比較のままサーバーに届く
+    let account = ldap::authenticate(&config, username, "pw").await.unwrap().unwrap();
+    assert!(account.groups.is_empty());
+    assert_eq!(
+        *directory.searches.lock().unwrap(),
+        [Filter::Equality { attribute: "memberUid".to_string(), value: username.to_string() }]
+    );
+}
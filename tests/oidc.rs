@@ -0,0 +1,297 @@
+//! 認可コードフローをローカルのモック認証サーバーに対して実行する
+//!
+//! モックはディスカバリー・認可・トークン・JWKS の各エンドポイントを持ち、
+//! PKCE の `code_challenge` を確認してから Ed25519 で署名した IDトークンを発行する。
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    Form, Json, Router,
+    extract::{Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use base64::Engine;
+use ed25519_dalek::SigningKey;
+use ed25519_dalek::pkcs8::EncodePrivateKey;
+use jsonwebtoken::{EncodingKey, Header};
+use rust_dashboard::config::{OidcConfig, RoleMapping};
+use rust_dashboard::oidc::{self, OidcFlow};
+use serde_json::{Value, json};
+
+const CLIENT_ID: &str = "rust-dashboard";
+const REDIRECT_URL: &str = "http://localhost:3000/login/oidc/callback";
+const KEY_ID: &str = "test-key";
+
+/// モックが発行する IDトークンの内容
+#[derive(Clone, Copy, PartialEq)]
+enum Behavior {
+    Valid,
+    /// ディスカバリーで別の発行者を返す
+    DiscoveryIssuerMismatch,
+    /// IDトークンの `iss` が発行者と異なる
+    TokenIssuerMismatch,
+    /// IDトークンの `nonce` が認可リクエストと異なる
+    WrongNonce,
+    /// IDトークンの有効期限が切れている
+    Expired,
+    /// IDトークンの `aud` が別のクライアント
+    WrongAudience,
+    /// JWKS にない鍵で署名する
+    UnknownSigningKey,
+}
+
+struct AuthorizationCode {
+    nonce: String,
+    code_challenge: String,
+}
+
+struct MockIdp {
+    issuer: String,
+    behavior: Behavior,
+    signing_key: SigningKey,
+    codes: Mutex<HashMap<String, AuthorizationCode>>,
+}
+
+type Shared = Arc<MockIdp>;
+
+async fn discovery(State(idp): State<Shared>) -> Json<Value> {
+    let issuer = if idp.behavior == Behavior::DiscoveryIssuerMismatch {
+        "https://attacker.example".to_string()
+    } else {
+        idp.issuer.clone()
+    };
+    Json(json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/authorize", idp.issuer),
+        "token_endpoint": format!("{}/token", idp.issuer),
+        "jwks_uri": format!("{}/jwks", idp.issuer),
+    }))
+}
+
+async fn authorize(State(idp): State<Shared>, Query(params): Query<HashMap<String, String>>) -> Response {
+    let param = |name: &str| params.get(name).cloned().unwrap_or_default();
+    if param("response_type") != "code"
+        || param("client_id") != CLIENT_ID
+        || param("redirect_uri") != REDIRECT_URL
+        || param("code_challenge_method") != "S256"
+        || !param("scope").split(' ').any(|scope| scope == "openid")
+    {
+        return (StatusCode::BAD_REQUEST, "invalid authorization request").into_response();
+    }
+
+    let code = format!("code-{}", idp.codes.lock().unwrap().len() + 1);
+    idp.codes.lock().unwrap().insert(
+        code.clone(),
+        AuthorizationCode { nonce: param("nonce"), code_challenge: param("code_challenge") },
+    );
+
+    let query = serde_urlencoded::to_string([("code", code.as_str()), ("state", param("state").as_str())]).unwrap();
+    (StatusCode::FOUND, [(header::LOCATION, format!("{}?{}", REDIRECT_URL, query))]).into_response()
+}
+
+async fn token(State(idp): State<Shared>, Form(params): Form<HashMap<String, String>>) -> Response {
+    let param = |name: &str| params.get(name).cloned().unwrap_or_default();
+    // 認可コードは1回だけ使える
+    let Some(code) = idp.codes.lock().unwrap().remove(&param("code")) else {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "invalid_grant"}))).into_response();
+    };
+    if param("grant_type") != "authorization_code"
+        || param("client_id") != CLIENT_ID
+        || param("redirect_uri") != REDIRECT_URL
+        || oidc::pkce_challenge(&param("code_verifier")) != code.code_challenge
+    {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "invalid_grant"}))).into_response();
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let mut claims = json!({
+        "iss": idp.issuer,
+        "aud": CLIENT_ID,
+        "sub": "user-1234",
+        "preferred_username": "alice",
+        "roles": ["dashboard-users"],
+        "nonce": code.nonce,
+        "iat": now,
+        "exp": now + 300,
+    });
+    match idp.behavior {
+        Behavior::TokenIssuerMismatch => claims["iss"] = json!("https://attacker.example"),
+        Behavior::WrongNonce => claims["nonce"] = json!("another-nonce"),
+        // 既定の許容誤差（60秒）を超えて期限切れにする
+        Behavior::Expired => {
+            claims["iat"] = json!(now - 3600);
+            claims["exp"] = json!(now - 600);
+        }
+        Behavior::WrongAudience => claims["aud"] = json!("another-client"),
+        _ => {}
+    }
+
+    let signing_key = if idp.behavior == Behavior::UnknownSigningKey {
+        SigningKey::from_bytes(&[9u8; 32])
+    } else {
+        idp.signing_key.clone()
+    };
+    let der = signing_key.to_pkcs8_der().unwrap();
+    let mut header = Header::new(jsonwebtoken::Algorithm::EdDSA);
+    header.kid = Some(KEY_ID.to_string());
+    let id_token = jsonwebtoken::encode(&header, &claims, &EncodingKey::from_ed_der(der.as_bytes())).unwrap();
+
+    Json(json!({"access_token": "access", "token_type": "Bearer", "id_token": id_token})).into_response()
+}
+
+async fn jwks(State(idp): State<Shared>) -> Json<Value> {
+    let x = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(idp.signing_key.verifying_key().as_bytes());
+    Json(json!({
+        "keys": [{"kty": "OKP", "crv": "Ed25519", "alg": "EdDSA", "use": "sig", "kid": KEY_ID, "x": x}]
+    }))
+}
+
+/// モックを起動し、それを発行者とする設定を返す
+async fn start_idp(behavior: Behavior) -> OidcConfig {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+    let idp = Arc::new(MockIdp {
+        issuer: issuer.clone(),
+        behavior,
+        signing_key: SigningKey::from_bytes(&[7u8; 32]),
+        codes: Mutex::new(HashMap::new()),
+    });
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/authorize", get(authorize))
+        .route("/token", post(token))
+        .route("/jwks", get(jwks))
+        .with_state(idp);
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    OidcConfig {
+        issuer_url: issuer,
+        client_id: CLIENT_ID.to_string(),
+        client_secret: None,
+        redirect_url: REDIRECT_URL.to_string(),
+        scopes: "openid profile".to_string(),
+        username_claim: "preferred_username".to_string(),
+        role_claim: "roles".to_string(),
+        role_mapping: RoleMapping {
+            rules: vec![("dashboard-users".to_string(), "user".to_string())],
+            default_role: None,
+        },
+        label: "SSO".to_string(),
+    }
+}
+
+/// ブラウザの代わりに認可エンドポイントを開き、コールバックに渡される `code` と `state` を返す
+async fn authorize_in_browser(url: &str) -> (String, String) {
+    let browser = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let response = browser.get(url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::FOUND);
+
+    let location = response.headers()[header::LOCATION].to_str().unwrap();
+    let query = location.strip_prefix(&format!("{}?", REDIRECT_URL)).unwrap();
+    let params: HashMap<String, String> = serde_urlencoded::from_str(query).unwrap();
+    (params["code"].clone(), params["state"].clone())
+}
+
+/// 認可リクエストからコールバックまでを実行する
+async fn run_flow(config: &OidcConfig) -> anyhow::Result<oidc::VerifiedIdToken> {
+    let (url, flow) = oidc::begin(config).await?;
+    let (code, state) = authorize_in_browser(&url).await;
+    assert_eq!(state, flow.state);
+    oidc::finish(config, &flow, &code).await
+}
+
+#[tokio::test]
+async fn valid_login_returns_verified_claims() {
+    let config = start_idp(Behavior::Valid).await;
+    let verified = run_flow(&config).await.unwrap();
+
+    assert_eq!(verified.issuer, config.issuer_url);
+    assert_eq!(oidc::claim_values(&verified.claims, "sub"), ["user-1234"]);
+    assert_eq!(oidc::claim_values(&verified.claims, &config.username_claim), ["alice"]);
+    let roles = oidc::claim_values(&verified.claims, &config.role_claim);
+    assert_eq!(config.role_mapping.resolve(&roles), Some("user"));
+}
+
+#[tokio::test]
+async fn authorization_url_carries_pkce_and_state() {
+    let config = start_idp(Behavior::Valid).await;
+    let (url, flow) = oidc::begin(&config).await.unwrap();
+
+    let query = url.split_once('?').unwrap().1;
+    let params: HashMap<String, String> = serde_urlencoded::from_str(query).unwrap();
+    assert_eq!(params["state"], flow.state);
+    assert_eq!(params["nonce"], flow.nonce);
+    assert_eq!(params["code_challenge"], oidc::pkce_challenge(&flow.code_verifier));
+    assert!(!params.contains_key("code_verifier"));
+}
+
+#[tokio::test]
+async fn rejects_issuer_mismatch_in_discovery() {
+    let config = start_idp(Behavior::DiscoveryIssuerMismatch).await;
+    let error = oidc::begin(&config).await.unwrap_err();
+    assert!(error.to_string().contains("issuer"), "{}", error);
+}
+
+#[tokio::test]
+async fn rejects_token_from_another_issuer() {
+    let config = start_idp(Behavior::TokenIssuerMismatch).await;
+    let error = run_flow(&config).await.unwrap_err();
+    assert!(error.to_string().contains("InvalidIssuer"), "{}", error);
+}
+
+#[tokio::test]
+async fn rejects_token_with_wrong_nonce() {
+    let config = start_idp(Behavior::WrongNonce).await;
+    let error = run_flow(&config).await.unwrap_err();
+    assert!(error.to_string().contains("nonce"), "{}", error);
+}
+
+#[tokio::test]
+async fn rejects_expired_token() {
+    let config = start_idp(Behavior::Expired).await;
+    let error = run_flow(&config).await.unwrap_err();
+    assert!(error.to_string().contains("ExpiredSignature"), "{}", error);
+}
+
+#[tokio::test]
+async fn rejects_token_for_another_client() {
+    let config = start_idp(Behavior::WrongAudience).await;
+    let error = run_flow(&config).await.unwrap_err();
+    assert!(error.to_string().contains("InvalidAudience"), "{}", error);
+}
+
+#[tokio::test]
+async fn rejects_token_signed_with_unknown_key() {
+    let config = start_idp(Behavior::UnknownSigningKey).await;
+    let error = run_flow(&config).await.unwrap_err();
+    assert!(error.to_string().contains("InvalidSignature"), "{}", error);
+}
+
+#[tokio::test]
+async fn rejects_code_verifier_from_another_flow() {
+    let config = start_idp(Behavior::Valid).await;
+    let (url, flow) = oidc::begin(&config).await.unwrap();
+    let (code, _) = authorize_in_browser(&url).await;
+
+    // 別の認可リクエストの code_verifier では交換できない（PKCE）
+    let other = OidcFlow { code_verifier: "another-verifier".to_string(), ..flow };
+    let error = oidc::finish(&config, &other, &code).await.unwrap_err();
+    assert!(error.to_string().contains("invalid_grant"), "{}", error);
+}
+
+#[tokio::test]
+async fn authorization_code_is_single_use() {
+    let config = start_idp(Behavior::Valid).await;
+    let (url, flow) = oidc::begin(&config).await.unwrap();
+    let (code, _) = authorize_in_browser(&url).await;
+
+    oidc::finish(&config, &flow, &code).await.unwrap();
+    assert!(oidc::finish(&config, &flow, &code).await.is_err());
+}
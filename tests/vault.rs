@@ -0,0 +1,130 @@
+//! シークレット保管庫のバージョンの追加と共有先を MySQL に対して確認する
+//!
+//! `TEST_DATABASE_URL` にマイグレーションを適用したデータベースを指定した場合だけ実行する
+//! （未設定の場合は何もせずに成功する）。テストごとに作成したユーザーとシークレットは最後に削除する。
+
+use rand::RngCore;
+use rust_dashboard::db::{self, VaultGrant};
+use sqlx::MySqlPool;
+
+const KEY_ID: &str = "0123456789abcdef";
+
+async fn connect() -> Option<MySqlPool> {
+    let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+        eprintln!("TEST_DATABASE_URL が未設定のためスキップします");
+        return None;
+    };
+    Some(MySqlPool::connect(&url).await.expect("TEST_DATABASE_URL に接続できません"))
+}
+
+fn unique_name(prefix: &str) -> String {
+    format!("{}-{:08x}", prefix, rand::rng().next_u32())
+}
+
+/// テスト用のユーザーと、そのユーザーが所有するシークレット（バージョン1）を作る
+async fn create_secret(pool: &MySqlPool) -> (db::User, i32) {
+    let owner = db::create_user(pool, &unique_name("vault-test"), "!", "user").await.unwrap();
+    let id = db::create_vault_secret(pool, &unique_name("secret"), "", owner.id, KEY_ID, &[0u8; 12], b"v1")
+        .await
+        .unwrap();
+    (owner, id)
+}
+
+async fn cleanup(pool: &MySqlPool, owner: &db::User, id: i32) {
+    db::delete_vault_secret(pool, id).await.unwrap();
+    db::delete_user(pool, owner.id).await.unwrap();
+}
+
+async fn add_version(pool: &MySqlPool, owner: &db::User, id: i32, version: i32) -> bool {
+    let ciphertext = format!("v{}", version);
+    db::add_vault_secret_version(pool, id, version, owner.id, KEY_ID, &[0u8; 12], ciphertext.as_bytes())
+        .await
+        .unwrap()
+}
+
+async fn versions(pool: &MySqlPool, id: i32) -> Vec<(i32, Vec<u8>)> {
+    db::list_vault_secret_versions(pool, id)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|version| (version.version, version.ciphertext))
+        .collect()
+}
+
+#[tokio::test]
+async fn version_is_added_only_on_top_of_the_current_version() {
+    let Some(pool) = connect().await else { return };
+    let (owner, id) = create_secret(&pool).await;
+
+    assert!(add_version(&pool, &owner, id, 2).await);
+    assert_eq!(db::get_vault_secret(&pool, id).await.unwrap().unwrap().current_version, 2);
+
+    // 同じバージョンを元に更新した2人目は失敗し、1人目の値は上書きされない
+    assert!(!add_version(&pool, &owner, id, 2).await);
+    // 現在のバージョンを飛ばした更新も失敗する
+    assert!(!add_version(&pool, &owner, id, 4).await);
+
+    assert!(add_version(&pool, &owner, id, 3).await);
+    assert_eq!(db::get_vault_secret(&pool, id).await.unwrap().unwrap().current_version, 3);
+    assert_eq!(
+        versions(&pool, id).await,
+        [(3, b"v3".to_vec()), (2, b"v2".to_vec()), (1, b"v1".to_vec())]
+    );
+
+    cleanup(&pool, &owner, id).await;
+}
+
+#[tokio::test]
+async fn concurrent_updates_add_exactly_one_version() {
+    let Some(pool) = connect().await else { return };
+    let (owner, id) = create_secret(&pool).await;
+
+    let updates = (0..5).map(|_| add_version(&pool, &owner, id, 2));
+    let results = futures::future::join_all(updates).await;
+    assert_eq!(results.iter().filter(|added| **added).count(), 1);
+    assert_eq!(versions(&pool, id).await.len(), 2);
+
+    cleanup(&pool, &owner, id).await;
+}
+
+#[tokio::test]
+async fn repeated_shares_do_not_duplicate_grants() {
+    let Some(pool) = connect().await else { return };
+    let (owner, id) = create_secret(&pool).await;
+    let grantee = db::create_user(&pool, &unique_name("vault-grantee"), "!", "user").await.unwrap();
+
+    assert!(db::add_vault_user_grant(&pool, id, grantee.id).await.unwrap());
+    assert!(!db::add_vault_user_grant(&pool, id, grantee.id).await.unwrap());
+    assert!(db::add_vault_role_grant(&pool, id, "user").await.unwrap());
+    assert!(!db::add_vault_role_grant(&pool, id, "user").await.unwrap());
+
+    assert_eq!(
+        db::list_vault_grants(&pool, id).await.unwrap(),
+        [
+            VaultGrant::Role("user".to_string()),
+            VaultGrant::User { user_id: grantee.id, username: grantee.username.clone() },
+        ]
+    );
+    assert!(db::can_access_vault_secret(&pool, id, grantee.id, "admin").await.unwrap());
+
+    assert!(db::delete_vault_user_grant(&pool, id, grantee.id).await.unwrap());
+    assert!(db::delete_vault_role_grant(&pool, id, "user").await.unwrap());
+    assert!(db::list_vault_grants(&pool, id).await.unwrap().is_empty());
+    assert!(!db::can_access_vault_secret(&pool, id, grantee.id, "user").await.unwrap());
+
+    db::delete_user(&pool, grantee.id).await.unwrap();
+    cleanup(&pool, &owner, id).await;
+}
+
+#[tokio::test]
+async fn grants_of_a_deleted_user_are_removed() {
+    let Some(pool) = connect().await else { return };
+    let (owner, id) = create_secret(&pool).await;
+    let grantee = db::create_user(&pool, &unique_name("vault-grantee"), "!", "user").await.unwrap();
+
+    assert!(db::add_vault_user_grant(&pool, id, grantee.id).await.unwrap());
+    db::delete_user(&pool, grantee.id).await.unwrap();
+    assert!(db::list_vault_grants(&pool, id).await.unwrap().is_empty());
+
+    cleanup(&pool, &owner, id).await;
+}